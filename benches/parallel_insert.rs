@@ -0,0 +1,63 @@
+//! 多线程向不同 root 插入文档的吞吐量
+//!
+//! `cargo bench --bench parallel_insert`，依次用 1、2、4… 个线程插入相同数量的文档，
+//! 输出每秒插入的文档数和相对单线程的加速比。
+
+use std::time::Instant;
+
+use dm_cache::{root_key, Database};
+
+/// 每轮插入的文档总数
+const DOCUMENTS: u64 = 20_000;
+
+fn document(i: u64) -> Vec<u8> {
+    format!(
+        r#"{{"id": {}, "name": "user-{}", "tags": ["a", "b", "c"], "profile": {{"age": {}, "scores": [1, 2, 3, 4, 5, 6, 7, 8], "bio": "{}"}}}}"#,
+        i,
+        i,
+        i % 100,
+        "x".repeat(64)
+    )
+    .into_bytes()
+}
+
+fn run(threads: u64) -> f64 {
+    let path = std::env::temp_dir().join(format!(
+        "dm_cache_bench_parallel_{}_{}",
+        threads,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&path);
+    let db = Database::open(path.to_str().unwrap()).unwrap();
+
+    let per_thread = DOCUMENTS / threads;
+    let start = Instant::now();
+    std::thread::scope(|s| {
+        for t in 0..threads {
+            let db = &db;
+            s.spawn(move || {
+                for i in t * per_thread..(t + 1) * per_thread {
+                    db.insert_json(&root_key(i), &mut document(i)).unwrap();
+                }
+            });
+        }
+    });
+    let elapsed = start.elapsed().as_secs_f64();
+    assert_eq!(db.root_count().unwrap(), per_thread * threads);
+    drop(db);
+    let _ = std::fs::remove_dir_all(&path);
+    (per_thread * threads) as f64 / elapsed
+}
+
+fn main() {
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get() as u64);
+    let mut threads = 1;
+    let mut baseline = None;
+    println!("{:>8} {:>14} {:>8}", "threads", "docs/s", "speedup");
+    while threads <= cores.max(1) {
+        let rate = run(threads);
+        let base = *baseline.get_or_insert(rate);
+        println!("{:>8} {:>14.0} {:>7.2}x", threads, rate, rate / base);
+        threads *= 2;
+    }
+}
@@ -0,0 +1,153 @@
+//! `dm_cache` 的派生宏
+
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields};
+
+/// 为结构体生成类型化的文档句柄
+///
+/// 对 `struct User { name: String }` 生成 `UserDocument<'a>` 和 `User::document(db, root)`，
+/// 句柄上每个字段有一对 `name()` / `set_name(value)` 方法，直接读写该字段对应的节点，
+/// 修改一个标量字段只会改写一个 key。另外 `get()` / `set(&value)` 读写整个文档。
+///
+/// 结构体及其字段类型需要实现 serde 的 `Serialize` 和 `Deserialize`，成员名即字段名。
+/// 句柄按字段名定位成员，所以结构体和字段上不能有改变成员名或跳过字段的 `#[serde(...)]` 属性，
+/// 字段名也不能是 `get` / `set`，否则和整个文档的读写方法重名，这些情况都会报编译错误。
+///
+/// # 示例
+/// ```rust,ignore
+/// #[derive(Serialize, Deserialize, CachedDocument)]
+/// struct User {
+///     name: String,
+///     age: u32,
+/// }
+///
+/// let user = User::document(&db, &root);
+/// user.set_name("x")?;
+/// assert_eq!(user.age()?, 30);
+/// ```
+#[proc_macro_derive(CachedDocument)]
+pub fn derive_cached_document(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "CachedDocument only supports structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "CachedDocument only supports structs",
+            ))
+        }
+    };
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "CachedDocument does not support generic structs",
+        ));
+    }
+
+    if let Some(attr) = input
+        .attrs
+        .iter()
+        .find(|attr| attr.path().is_ident("serde"))
+    {
+        return Err(Error::new_spanned(
+            attr,
+            "CachedDocument does not support #[serde(...)] container attributes",
+        ));
+    }
+    for field in fields {
+        if let Some(attr) = field
+            .attrs
+            .iter()
+            .find(|attr| attr.path().is_ident("serde"))
+        {
+            return Err(Error::new_spanned(
+                attr,
+                "CachedDocument does not support #[serde(...)] field attributes",
+            ));
+        }
+        let ident = field.ident.as_ref().unwrap();
+        if ident == "get" || ident == "set" {
+            return Err(Error::new_spanned(
+                ident,
+                "CachedDocument field cannot be named `get` or `set`",
+            ));
+        }
+    }
+
+    let vis = &input.vis;
+    let name = &input.ident;
+    let handle = format_ident!("{}Document", name);
+    let handle_doc = format!(
+        "`{}` 文档的类型化句柄，由 `#[derive(CachedDocument)]` 生成",
+        name
+    );
+
+    let accessors = fields.iter().map(|field| {
+        let ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        let member = ident.to_string();
+        let member = member.strip_prefix("r#").unwrap_or(&member);
+        let setter = format_ident!("set_{}", member);
+        let getter_doc = format!("读取 `{}` 字段", member);
+        let setter_doc = format!("写入 `{}` 字段，只改写该字段对应的节点", member);
+        quote! {
+            #[doc = #getter_doc]
+            pub fn #ident(&self) -> ::std::result::Result<#ty, ::dm_cache::DBError> {
+                self.db.get_value_at(&self.root, &[::dm_cache::JsonPathSegment::Key(#member.to_string())])
+            }
+
+            #[doc = #setter_doc]
+            pub fn #setter(&self, value: impl ::std::convert::Into<#ty>) -> ::std::result::Result<(), ::dm_cache::DBError> {
+                let value: #ty = value.into();
+                self.db.set_value_at(&self.root, &[::dm_cache::JsonPathSegment::Key(#member.to_string())], &value)
+            }
+        }
+    });
+
+    Ok(quote! {
+        #[doc = #handle_doc]
+        #vis struct #handle<'a> {
+            db: &'a ::dm_cache::Database,
+            root: ::std::vec::Vec<u8>,
+        }
+
+        impl #name {
+            /// 返回 `root` 文档的类型化句柄，`root` 为编码后的 root key
+            #vis fn document<'a>(db: &'a ::dm_cache::Database, root: &[u8]) -> #handle<'a> {
+                #handle {
+                    db,
+                    root: root.to_vec(),
+                }
+            }
+        }
+
+        impl #handle<'_> {
+            /// 读取整个文档
+            pub fn get(&self) -> ::std::result::Result<#name, ::dm_cache::DBError> {
+                self.db.get_value_at(&self.root, &[])
+            }
+
+            /// 替换整个文档，文档不存在时新建
+            pub fn set(&self, value: &#name) -> ::std::result::Result<(), ::dm_cache::DBError> {
+                self.db.set_value_at(&self.root, &[], value)
+            }
+
+            #(#accessors)*
+        }
+    })
+}
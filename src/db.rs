@@ -1,5 +1,31 @@
+mod allocator;
+mod array;
+mod cursor;
+mod editor;
+mod events;
+mod ingest;
+mod locks;
+mod merge;
 mod metadata;
+mod migration;
 mod operations;
+mod output;
+mod patch;
+mod relocate;
+mod transaction;
+mod typed;
+mod update;
+mod writer;
 
+pub(crate) use allocator::IdAllocator;
+pub use cursor::{NodeCursor, NodeKind};
+pub(crate) use editor::*;
+pub use events::{EventReader, JsonEvent};
+pub(crate) use locks::RootLocks;
 pub use metadata::*;
+pub use migration::MigrationProgress;
 pub use operations::*;
+pub use output::WriteOptions;
+pub use transaction::Transaction;
+pub use update::{CasConflict, Increment};
+pub use writer::DocumentWriter;
@@ -0,0 +1,110 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::kv::{AutoIncrementId, Store};
+use crate::{DBError, METADAT_KEY};
+
+use super::Metadata;
+
+/// 每次预留的 id 个数
+const ID_BLOCK_SIZE: u64 = 4096;
+
+/// 按块预留的节点 id 分配器
+///
+/// 分配只是一次内存中的原子自增，多个线程可以同时分配而不需要加锁。持久化的是预留上限：
+/// metadata 中的 `last_id` 记录已经预留到的最大 id，写入节点之前通过 `reserve` 确认
+/// 用到的 id 都已经预留，超出时用 sled 的原子更新一次再预留一整块。
+/// 重启后从预留上限之后开始分配，没有用完的 id 被跳过，所以写入过的 id 不会被重复使用。
+pub(crate) struct IdAllocator {
+    ids: AutoIncrementId,
+    /// 已经持久化的预留上限
+    reserved: AtomicU64,
+}
+
+impl IdAllocator {
+    /// 从持久化的预留上限之后开始分配
+    pub fn new(last_id: u64) -> Self {
+        Self {
+            ids: AutoIncrementId::with_value(last_id),
+            reserved: AtomicU64::new(last_id),
+        }
+    }
+
+    pub fn next(&self) -> u64 {
+        self.ids.next()
+    }
+
+    /// 最近一次分配的 id
+    pub fn last(&self) -> u64 {
+        self.ids.current()
+    }
+
+    /// 调用方直接使用了 `id` 时，保证之后分配的 id 都比它大
+    pub fn observe(&self, id: u64) {
+        self.ids.skip_to(id);
+    }
+
+    /// 确认到目前为止分配出去的 id 都已经持久化预留，需要在写入这些 id 的节点之前调用
+    pub fn reserve(&self, store: &Store) -> Result<(), DBError> {
+        let needed = self.last();
+        if self.reserved.load(Ordering::Acquire) >= needed {
+            return Ok(());
+        }
+        let target = needed + ID_BLOCK_SIZE;
+        let mut error = None;
+        let updated = store.tree.update_and_fetch(METADAT_KEY, |old| {
+            let mut metadata = match old.map(Metadata::decode).transpose() {
+                Ok(metadata) => metadata.unwrap_or_else(Metadata::new),
+                Err(e) => {
+                    error = Some(e);
+                    return old.map(<[u8]>::to_vec);
+                }
+            };
+            metadata.last_id = metadata.last_id.max(target);
+            Some(metadata.encode())
+        })?;
+        if let Some(e) = error {
+            return Err(e.into());
+        }
+        let last_id = match updated {
+            Some(raw) => Metadata::decode(&raw)?.last_id,
+            None => target,
+        };
+        self.reserved.fetch_max(last_id, Ordering::AcqRel);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::test_util::temp_database;
+
+    #[test]
+    fn test_concurrent_allocation() {
+        let db = temp_database("concurrent_allocation");
+        let start = db.ids.last();
+        let ids: Vec<Vec<u64>> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    s.spawn(|| {
+                        (0..10_000)
+                            .map(|_| {
+                                let id = db.ids.next();
+                                db.ids.reserve(&db.store).unwrap();
+                                id
+                            })
+                            .collect()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        let unique: HashSet<u64> = ids.iter().flatten().copied().collect();
+        assert_eq!(unique.len(), 80_000);
+        assert!(unique.iter().all(|id| *id > start));
+        // 预留上限已经持久化，覆盖所有分配出去的 id
+        let stored = db.stored_metadata().unwrap();
+        assert!(stored.last_id >= db.ids.last());
+    }
+}
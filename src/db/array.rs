@@ -0,0 +1,178 @@
+use simd_json::OwnedValue;
+
+use crate::kv::{Key, KeyIndex, VariableSizedId};
+use crate::{DBError, Database};
+
+use super::{format_path, parse, Editor};
+
+impl Database {
+    /// 在数组末尾追加一个元素
+    ///
+    /// # 参数
+    /// * `root` - 编码后的 root key
+    /// * `path` - 指向数组节点的 JSONPath
+    /// * `value` - 新元素的 json
+    pub fn array_push(&self, root: &[u8], path: &str, value: &mut [u8]) -> Result<(), DBError> {
+        let value = simd_json::to_tape(value).map_err(|_| DBError::DatabaseJsonError)?;
+        let _lock = self.locks.write(root);
+        let mut editor = Editor::new(self);
+        let array = resolve_array(&editor, root, path)?;
+        let len = editor.children(&array)?.len();
+        let key = editor.sub_key(&array, KeyIndex::Id(VariableSizedId::new(len as u64)));
+        editor.write_value(key, &value.0);
+        editor.commit()?;
+        Ok(())
+    }
+
+    /// 在数组的 `at` 位置插入一个元素，`at` 及之后的元素下标加一，`at` 等于长度时等同于 `array_push`
+    pub fn array_insert(
+        &self,
+        root: &[u8],
+        path: &str,
+        at: usize,
+        value: &mut [u8],
+    ) -> Result<(), DBError> {
+        let value = simd_json::to_tape(value).map_err(|_| DBError::DatabaseJsonError)?;
+        let _lock = self.locks.write(root);
+        let mut editor = Editor::new(self);
+        let array = resolve_array(&editor, root, path)?;
+        let len = editor.children(&array)?.len();
+        if at > len {
+            return Err(DBError::IndexOutOfRange(at));
+        }
+        editor.shift_elements(&array, at, 1)?;
+        let key = editor.sub_key(&array, KeyIndex::Id(VariableSizedId::new(at as u64)));
+        editor.write_value(key, &value.0);
+        editor.commit()?;
+        Ok(())
+    }
+
+    /// 删除数组 `at` 位置的元素并返回它，之后的元素下标减一
+    pub fn array_remove(&self, root: &[u8], path: &str, at: usize) -> Result<OwnedValue, DBError> {
+        let _lock = self.locks.write(root);
+        let mut editor = Editor::new(self);
+        let array = resolve_array(&editor, root, path)?;
+        let removed = remove_element(&mut editor, &array, at)?;
+        editor.commit()?;
+        Ok(removed)
+    }
+
+    /// 删除并返回数组的最后一个元素，数组为空时返回 None
+    pub fn array_pop(&self, root: &[u8], path: &str) -> Result<Option<OwnedValue>, DBError> {
+        let _lock = self.locks.write(root);
+        let mut editor = Editor::new(self);
+        let array = resolve_array(&editor, root, path)?;
+        let len = editor.children(&array)?.len();
+        if len == 0 {
+            return Ok(None);
+        }
+        let removed = remove_element(&mut editor, &array, len - 1)?;
+        editor.commit()?;
+        Ok(Some(removed))
+    }
+
+    /// 返回数组的元素个数
+    pub fn array_len(&self, root: &[u8], path: &str) -> Result<usize, DBError> {
+        let _lock = self.locks.read(root);
+        let editor = Editor::new(self);
+        let array = resolve_array(&editor, root, path)?;
+        Ok(editor.children(&array)?.len())
+    }
+}
+
+/// 查找 `path` 指向的节点，并确认它是数组
+fn resolve_array(editor: &Editor, root: &[u8], path: &str) -> Result<Key, DBError> {
+    let segments = parse(path)?;
+    let root = editor.root_key(root)?;
+    let (key, value) = editor.resolve(&root, &segments)?;
+    if !value.is_array() {
+        return Err(DBError::InvalidNodeType(format_path(&segments)));
+    }
+    Ok(key)
+}
+
+fn remove_element(editor: &mut Editor, array: &Key, at: usize) -> Result<OwnedValue, DBError> {
+    let (key, _) = editor
+        .child(array, &KeyIndex::Id(VariableSizedId::new(at as u64)))?
+        .ok_or(DBError::IndexOutOfRange(at))?;
+    let removed = editor.read_value(&key)?;
+    editor.remove_subtree(&key)?;
+    editor.shift_elements(array, at + 1, -1)?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{json, read_json, temp_document};
+    use crate::DBError;
+
+    #[test]
+    fn test_array_operations() {
+        let (db, root) = temp_document("array_operations", r#"{"list": [1, 2, 3]}"#);
+        db.array_push(&root, "$.list", &mut br#"{"a": 4}"#.to_vec())
+            .unwrap();
+        db.array_insert(&root, "$.list", 0, &mut b"0".to_vec())
+            .unwrap();
+        db.array_insert(&root, "$.list", 2, &mut b"[9]".to_vec())
+            .unwrap();
+        assert_eq!(
+            read_json(&db, &root),
+            json(r#"{"list": [0, 1, [9], 2, 3, {"a": 4}]}"#)
+        );
+        assert_eq!(db.array_len(&root, "$.list").unwrap(), 6);
+
+        assert_eq!(db.array_remove(&root, "$.list", 2).unwrap(), json("[9]"));
+        assert_eq!(
+            db.array_pop(&root, "$.list").unwrap(),
+            Some(json(r#"{"a": 4}"#))
+        );
+        assert_eq!(read_json(&db, &root), json(r#"{"list": [0, 1, 2, 3]}"#));
+        assert_eq!(db.array_len(&root, "$.list").unwrap(), 4);
+    }
+
+    #[test]
+    fn test_array_indices_stay_dense() {
+        let (db, root) = temp_document("array_dense", r#"[]"#);
+        for i in 0..20 {
+            db.array_insert(&root, "$", 0, &mut i.to_string().into_bytes())
+                .unwrap();
+        }
+        for _ in 0..5 {
+            db.array_remove(&root, "$", 3).unwrap();
+        }
+        let expected: Vec<String> = (0..20)
+            .rev()
+            .enumerate()
+            .filter(|(i, _)| !(3..8).contains(i))
+            .map(|(_, v)| v.to_string())
+            .collect();
+        assert_eq!(
+            read_json(&db, &root),
+            json(&format!("[{}]", expected.join(",")))
+        );
+        // 通过下标逐个访问，确认下标连续
+        for (i, v) in expected.iter().enumerate() {
+            let path = format!("$[{}]", i);
+            let segments = crate::parse(&path).unwrap();
+            assert!(crate::json_path_key(&db, &root, &segments).is_ok(), "{}", v);
+        }
+    }
+
+    #[test]
+    fn test_array_errors() {
+        let (db, root) = temp_document("array_errors", r#"{"list": [], "obj": {}}"#);
+        assert_eq!(db.array_pop(&root, "$.list").unwrap(), None);
+        assert!(matches!(
+            db.array_remove(&root, "$.list", 0),
+            Err(DBError::IndexOutOfRange(0))
+        ));
+        assert!(matches!(
+            db.array_insert(&root, "$.list", 1, &mut b"1".to_vec()),
+            Err(DBError::IndexOutOfRange(1))
+        ));
+        assert!(matches!(
+            db.array_len(&root, "$.obj"),
+            Err(DBError::InvalidNodeType(_))
+        ));
+    }
+}
@@ -0,0 +1,588 @@
+use std::ops::Range;
+use std::rc::Rc;
+
+use bytes::Bytes;
+use parking_lot::RwLockReadGuard;
+use simd_json::OwnedValue;
+
+use crate::kv::{
+    element_index_key, element_index_prefix, field_index_prefix, packed_children, read_field,
+    read_ordered_id, Key, KeyIndex, NodeValue, Store, VariableSizedId,
+};
+use crate::{DBError, Database};
+
+use super::Editor;
+
+/// 节点类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Null,
+    Bool,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+impl From<&NodeValue> for NodeKind {
+    fn from(value: &NodeValue) -> Self {
+        match value {
+            NodeValue::Null => NodeKind::Null,
+            NodeValue::Bool(_) => NodeKind::Bool,
+            NodeValue::Number(_) | NodeValue::NumberI(_) | NodeValue::NumberU(_) => {
+                NodeKind::Number
+            }
+            NodeValue::String(_) => NodeKind::String,
+            NodeValue::Array => NodeKind::Array,
+            NodeValue::Object => NodeKind::Object,
+            NodeValue::Packed(_) if value.is_array() => NodeKind::Array,
+            NodeValue::Packed(_) => NodeKind::Object,
+        }
+    }
+}
+
+/// 在已存储的文档上按需移动的游标
+///
+/// 每一步只读取需要的节点：`parent` 是一次 range 查找，`child`、`index` 通过索引点查，
+/// `children` 和 `slice` 只扫描直接子节点或下标索引中的一段，不会还原整个文档。
+/// packed 节点的子节点在内存中解码，不会拆开存储中的节点。
+///
+/// 游标持有文档的读锁，从它得到的游标共享同一把锁，全部释放之前同一文档的写操作会等待，
+/// 游标一路读到的是一致的文档；不要在同一线程中一边持有游标一边修改这个文档。
+#[derive(Clone)]
+pub struct NodeCursor<'a> {
+    db: &'a Database,
+    _lock: Rc<RwLockReadGuard<'a, ()>>,
+    key: Key,
+    value: NodeValue,
+    /// 位于 packed 节点内部时指向父节点，这时 `key` 不在存储中，只用来携带成员名和下标
+    packed_parent: Option<Box<NodeCursor<'a>>>,
+}
+
+impl Database {
+    /// 返回指向 `root` 文档根节点的游标，`root` 为编码后的 root key
+    pub fn cursor(&self, root: &[u8]) -> Result<NodeCursor<'_>, DBError> {
+        let lock = self.locks.read(root);
+        let editor = Editor::new(self);
+        let key = editor.root_key(root)?;
+        let value = editor.node(&key)?.ok_or(DBError::RootNotFound)?;
+        Ok(NodeCursor {
+            db: self,
+            _lock: Rc::new(lock),
+            key,
+            value,
+            packed_parent: None,
+        })
+    }
+}
+
+impl<'a> NodeCursor<'a> {
+    pub fn kind(&self) -> NodeKind {
+        NodeKind::from(&self.value)
+    }
+
+    /// 当前节点在父 object 中的成员名，数组元素和根节点返回 None
+    pub fn name(&self) -> Option<&[u8]> {
+        match &self.key.field_key {
+            KeyIndex::Field(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// 当前节点的值，容器节点会还原以它为根的子树
+    pub fn value(&self) -> Result<OwnedValue, DBError> {
+        Editor::new(self.db).value_of(&self.key, self.value.clone())
+    }
+
+    /// object 中名为 `name` 的成员，当前节点不是 object 或成员不存在时返回 None
+    pub fn child(&self, name: &str) -> Result<Option<NodeCursor<'a>>, DBError> {
+        if !self.value.is_object() {
+            return Ok(None);
+        }
+        let index = KeyIndex::Field(Bytes::copy_from_slice(name.as_bytes()));
+        if let Some(children) = self.unpacked()? {
+            return Ok(children.into_iter().find(|c| c.key.field_key == index));
+        }
+        let child = Editor::new(self.db).child(&self.key, &index)?;
+        Ok(child.map(|(key, value)| self.cursor(key, value)))
+    }
+
+    /// 数组的第 `i` 个元素，当前节点不是数组或下标越界时返回 None
+    pub fn index(&self, i: usize) -> Result<Option<NodeCursor<'a>>, DBError> {
+        if !self.value.is_array() {
+            return Ok(None);
+        }
+        Ok(self.slice(i..i + 1)?.pop())
+    }
+
+    /// 数组中下标在 `range` 内的元素，按下标排序，当前节点不是数组时返回空
+    ///
+    /// 只扫描下标索引中对应的一段，读取的 key 数和数组的长度无关。
+    pub fn slice(&self, range: Range<usize>) -> Result<Vec<NodeCursor<'a>>, DBError> {
+        if !self.value.is_array() {
+            return Ok(Vec::new());
+        }
+        if let Some(children) = self.unpacked()? {
+            let len = range.len();
+            return Ok(children.into_iter().skip(range.start).take(len).collect());
+        }
+        let range = range.start as u64..range.end as u64;
+        IndexIter::elements(&self.db.store, &self.key, range)
+            .map(|child| child.map(|(key, value)| self.cursor(key, value)))
+            .collect()
+    }
+
+    /// 父节点，根节点返回 None
+    pub fn parent(&self) -> Result<Option<NodeCursor<'a>>, DBError> {
+        if let Some(parent) = &self.packed_parent {
+            return Ok(Some(parent.as_ref().clone()));
+        }
+        if self.key.ids.len() < 2 {
+            return Ok(None);
+        }
+        // 父节点自身的 key 是 `父节点 ids + 分隔符 + field_key`，在以父节点 ids 为前缀的 key 中排在最前
+        let (key, value) = match self.db.store.get_super_node(&self.key)? {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+        if key.ids != self.key.ids[..self.key.ids.len() - 1] {
+            return Ok(None);
+        }
+        Ok(Some(self.cursor(key, value)))
+    }
+
+    /// 所有直接子节点，数组元素按下标排序，object 成员按存储顺序排列
+    pub fn children(&self) -> Result<Vec<NodeCursor<'a>>, DBError> {
+        if let Some(children) = self.unpacked()? {
+            return Ok(children);
+        }
+        if self.value.is_array() {
+            return self.slice(0..usize::MAX);
+        }
+        if !self.value.is_object() {
+            return Ok(Vec::new());
+        }
+        ChildIter::new(&self.db.store, &self.key)
+            .map(|child| child.map(|(key, value)| self.cursor(key, value)))
+            .collect()
+    }
+
+    fn cursor(&self, key: Key, value: NodeValue) -> NodeCursor<'a> {
+        NodeCursor {
+            db: self.db,
+            _lock: self._lock.clone(),
+            key,
+            value,
+            packed_parent: None,
+        }
+    }
+
+    /// 当前节点是 packed 时解码出全部子节点，否则返回 None
+    fn unpacked(&self) -> Result<Option<Vec<NodeCursor<'a>>>, DBError> {
+        let NodeValue::Packed(data) = &self.value else {
+            return Ok(None);
+        };
+        let parent = Box::new(self.clone());
+        let children = packed_children(data)?
+            .into_iter()
+            .enumerate()
+            .map(|(i, (index, value))| NodeCursor {
+                db: self.db,
+                _lock: self._lock.clone(),
+                key: self.key.sub_key(VariableSizedId::new(i as u64), index),
+                value,
+                packed_parent: Some(parent.clone()),
+            })
+            .collect();
+        Ok(Some(children))
+    }
+}
+
+/// 按 key 顺序逐个返回 `parent` 的直接子节点
+///
+/// 每找到一个子节点，下一次 range 查找就从它的子孙之后开始，所以扫描的 key 数只和子节点个数有关。
+pub(crate) struct ChildIter<'a> {
+    store: &'a Store,
+    prefix: Vec<u8>,
+    next: Option<Vec<u8>>,
+}
+
+impl<'a> ChildIter<'a> {
+    pub fn new(store: &'a Store, parent: &Key) -> Self {
+        let prefix = parent.id_prefix();
+        // 父节点自身的 key 在 ids 之后紧跟分隔符 0x00，子节点 id 的首字节不会是 0x00
+        let mut start = prefix.clone();
+        start.push(0x01);
+        Self {
+            store,
+            prefix,
+            next: Some(start),
+        }
+    }
+}
+
+impl Iterator for ChildIter<'_> {
+    type Item = Result<(Key, NodeValue), DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.next.take()?;
+        let (k, v) = match self.store.tree.range(start..).next()? {
+            Ok(kv) => kv,
+            Err(e) => return Some(Err(e.into())),
+        };
+        if !k.starts_with(&self.prefix) {
+            return None;
+        }
+        let result = Key::decode(&k)
+            .and_then(|key| Ok((key, NodeValue::decode(&Bytes::copy_from_slice(&v))?)))
+            .map_err(DBError::from);
+        if let Ok((key, _)) = &result {
+            self.next = Some(successor(&key.id_prefix()));
+        }
+        Some(result)
+    }
+}
+
+/// 按索引的顺序逐个返回 `parent` 的直接子节点：数组元素按下标，object 成员按名字的字节序
+///
+/// 扫描下标索引或字段名索引中对应的一段，每个条目再点查一次子节点，不会读取子节点的子孙。
+pub(crate) struct IndexIter<'a> {
+    store: &'a Store,
+    parent: Key,
+    prefix_len: usize,
+    entries: sled::Iter,
+    members: bool,
+}
+
+impl<'a> IndexIter<'a> {
+    /// 数组 `parent` 中下标在 `range` 内的元素
+    pub fn elements(store: &'a Store, parent: &Key, range: Range<u64>) -> Self {
+        let start = element_index_key(&parent.ids, range.start);
+        let end = element_index_key(&parent.ids, range.end.max(range.start));
+        Self {
+            store,
+            parent: parent.clone(),
+            prefix_len: element_index_prefix(&parent.ids).len(),
+            entries: store.tree.range(start..end),
+            members: false,
+        }
+    }
+
+    /// object `parent` 的所有成员
+    pub fn members(store: &'a Store, parent: &Key) -> Self {
+        let prefix = field_index_prefix(&parent.ids);
+        Self {
+            store,
+            parent: parent.clone(),
+            prefix_len: prefix.len(),
+            entries: store.tree.scan_prefix(prefix),
+            members: true,
+        }
+    }
+
+    fn child(&self, entry: &[u8], id: &[u8]) -> Result<Option<(Key, NodeValue)>, DBError> {
+        let entry = &entry[self.prefix_len..];
+        let index = if self.members {
+            KeyIndex::Field(read_field(entry)?.0)
+        } else {
+            KeyIndex::Id(VariableSizedId::new(read_ordered_id(entry)?.0))
+        };
+        let (id, _) = read_ordered_id(id)?;
+        let key = self.parent.sub_key(VariableSizedId::new(id), index);
+        let Some(value) = self.store.get_raw(&key.encode())? else {
+            return Ok(None);
+        };
+        Ok(Some((key, NodeValue::decode(&value)?)))
+    }
+}
+
+impl Iterator for IndexIter<'_> {
+    type Item = Result<(Key, NodeValue), DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (entry, id) = match self.entries.next()? {
+                Ok(kv) => kv,
+                Err(e) => return Some(Err(e.into())),
+            };
+            // 索引条目总是和节点一起写入和删除，找不到节点时跳过
+            if let Some(child) = self.child(&entry, &id).transpose() {
+                return Some(child);
+            }
+        }
+    }
+}
+
+/// 大于所有以 `prefix` 开头的 key 的最小 key
+fn successor(prefix: &[u8]) -> Vec<u8> {
+    let mut next = prefix.to_vec();
+    while let Some(last) = next.pop() {
+        if last < 0xFF {
+            next.push(last + 1);
+            return next;
+        }
+    }
+    // 全是 0xFF，不存在更大的前缀，返回一个不可能出现的最大 key
+    vec![0xFF; prefix.len() + 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::{NodeCursor, NodeKind};
+    use crate::db::writer::CHECKPOINT_PREFIX;
+    use crate::db::Editor;
+    use crate::db::ROOT_PREFIX;
+    use crate::kv::{
+        EncodeError, Key, KeyIndex, NodeValue, ELEMENT_INDEX_PREFIX, FIELD_INDEX_PREFIX,
+    };
+    use crate::test_util::{json, read_json, root_key, temp_database, temp_document};
+    use crate::{DBError, Database, METADAT_KEY};
+
+    /// 字段名索引和下标索引的条目和存储的 object 成员、数组元素一一对应
+    fn assert_indexes(db: &Database) {
+        let mut expected = Vec::new();
+        for k in db.store.tree.iter().keys() {
+            let k = k.unwrap();
+            if k == METADAT_KEY || k.first() == Some(&0) {
+                continue;
+            }
+            expected.extend(Key::decode(&k).unwrap().index_entry());
+        }
+        expected.sort();
+        let mut indexed: Vec<_> = [FIELD_INDEX_PREFIX, ELEMENT_INDEX_PREFIX]
+            .iter()
+            .flat_map(|prefix| db.store.tree.scan_prefix(prefix))
+            .map(|kv| {
+                let (k, v) = kv.unwrap();
+                (k.to_vec(), v.to_vec())
+            })
+            .collect();
+        indexed.sort();
+        assert_eq!(indexed, expected);
+    }
+
+    #[test]
+    fn test_cursor_navigation() {
+        let (db, root) = temp_document(
+            "cursor_navigation",
+            r#"{"user": {"name": "a", "tags": ["x", {"deep": [1, 2]}, "z"]}, "n": 1}"#,
+        );
+        let cursor = db.cursor(&root).unwrap();
+        assert_eq!(cursor.kind(), NodeKind::Object);
+        assert!(cursor.parent().unwrap().is_none());
+
+        let user = cursor.child("user").unwrap().unwrap();
+        assert_eq!(user.name(), Some(&b"user"[..]));
+        let tags = user.child("tags").unwrap().unwrap();
+        assert_eq!(tags.kind(), NodeKind::Array);
+        let deep = tags.index(1).unwrap().unwrap();
+        assert_eq!(deep.value().unwrap(), json(r#"{"deep": [1, 2]}"#));
+        assert!(tags.index(3).unwrap().is_none());
+        assert!(tags.child("x").unwrap().is_none());
+
+        let parent = deep.parent().unwrap().unwrap();
+        assert_eq!(
+            parent.value().unwrap(),
+            json(r#"["x", {"deep": [1, 2]}, "z"]"#)
+        );
+        assert_eq!(parent.parent().unwrap().unwrap().name(), Some(&b"user"[..]));
+
+        let values: Vec<_> = tags
+            .children()
+            .unwrap()
+            .iter()
+            .map(|c| c.value().unwrap())
+            .collect();
+        assert_eq!(
+            values,
+            vec![json(r#""x""#), json(r#"{"deep": [1, 2]}"#), json(r#""z""#)]
+        );
+        let kinds: Vec<_> = cursor
+            .children()
+            .unwrap()
+            .iter()
+            .map(|c| c.kind())
+            .collect();
+        assert_eq!(kinds, vec![NodeKind::Object, NodeKind::Number]);
+    }
+
+    #[test]
+    fn test_element_order_after_insert() {
+        let (db, root) = temp_document("element_order", r#"{"a": [0, 1, 2, 3]}"#);
+        // 插入的元素分配到更大的 id，节点 key 的顺序和下标顺序不再一致
+        db.array_insert(&root, "$.a", 1, &mut b"\"x\"".to_vec())
+            .unwrap();
+        db.array_insert(&root, "$.a", 0, &mut b"[5]".to_vec())
+            .unwrap();
+        db.array_remove(&root, "$.a", 3).unwrap();
+        let mut patch = br#"[{"op": "add", "path": "/a/2", "value": {"y": 6}}]"#.to_vec();
+        db.apply_patch(&root, &mut patch).unwrap();
+        db.move_path(&root, "$.a[5]", &root, "$.a[0]").unwrap();
+        assert_indexes(&db);
+
+        let expected = [r#"3"#, r#"[5]"#, r#"0"#, r#"{"y": 6}"#, r#""x""#, r#"2"#];
+        let expected: Vec<_> = expected.iter().map(|s| json(s)).collect();
+        let a = db.cursor(&root).unwrap().child("a").unwrap().unwrap();
+        let values = |cursors: Vec<NodeCursor>| -> Vec<_> {
+            cursors.iter().map(|c| c.value().unwrap()).collect()
+        };
+        assert_eq!(values(a.children().unwrap()), expected);
+        assert_eq!(values(a.slice(2..5).unwrap()), expected[2..5]);
+        let (from, to) = (4, 2);
+        assert!(a.slice(from..to).unwrap().is_empty());
+        assert_eq!(a.index(3).unwrap().unwrap().value().unwrap(), expected[3]);
+        assert!(a.index(6).unwrap().is_none());
+        assert_eq!(
+            read_json(&db, &root),
+            json(r#"{"a": [3, [5], 0, {"y": 6}, "x", 2]}"#)
+        );
+    }
+
+    #[test]
+    fn test_binary_member_names() {
+        let (db, root) = temp_document("binary_member_names", r#"{"a\u0000b": 1, "a": 2}"#);
+        // 非 json 格式写入的成员名可以是任意字节
+        let mut editor = Editor::new(&db);
+        let root_key = editor.root_key(&root).unwrap();
+        let name = Bytes::from_static(b"\xff\x00");
+        let key = editor.sub_key(&root_key, KeyIndex::Field(name.clone()));
+        editor.staged.insert(&key, &NodeValue::Bool(true));
+        editor.commit().unwrap();
+
+        let cursor = db.cursor(&root).unwrap();
+        assert_eq!(
+            cursor.child("a\0b").unwrap().unwrap().value().unwrap(),
+            json("1")
+        );
+        let mut names: Vec<Vec<u8>> = cursor
+            .children()
+            .unwrap()
+            .iter()
+            .map(|c| c.name().unwrap().to_vec())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec![b"a".to_vec(), b"a\0b".to_vec(), name.to_vec()]);
+        // 还原为 json 时成员名必须是 UTF-8
+        assert!(matches!(
+            cursor.value(),
+            Err(DBError::KVError(EncodeError::InvalidUtf8(_)))
+        ));
+    }
+
+    #[test]
+    fn test_field_index() {
+        let (db, root) = temp_document(
+            "field_index",
+            r#"{"a": {"b": 1, "c": [{"d": 2}]}, "e": 3, "e": 4}"#,
+        );
+        assert_indexes(&db);
+        db.rename_key(&root, "$.a.b", "x").unwrap();
+        let mut patch = br#"[{"op": "remove", "path": "/a/c"}]"#.to_vec();
+        db.apply_patch(&root, &mut patch).unwrap();
+        let mut patch = br#"{"e": {"f": 1}, "g": {"h": 2}}"#.to_vec();
+        db.merge_patch(&root, "$", &mut patch).unwrap();
+        db.move_path(&root, "$.g", &root, "$.a.x").unwrap();
+        assert_indexes(&db);
+
+        {
+            let cursor = db.cursor(&root).unwrap();
+            let a = cursor.child("a").unwrap().unwrap();
+            assert_eq!(a.value().unwrap(), json(r#"{"x": {"h": 2}}"#));
+            assert!(a.child("b").unwrap().is_none());
+            assert!(cursor.child("g").unwrap().is_none());
+            assert_eq!(
+                cursor.child("e").unwrap().unwrap().value().unwrap(),
+                json(r#"{"f": 1}"#)
+            );
+        }
+
+        let other = root_key(1);
+        let mut w = db.document_writer(&other).unwrap().batch_size(2);
+        w.value(json(r#"{"p": {"q": [{"r": null}]}}"#)).unwrap();
+        w.finish().unwrap();
+        db.transaction(|tx| tx.delete_root(&root)).unwrap();
+        assert_indexes(&db);
+        let q = db.cursor(&other).unwrap().child("p").unwrap().unwrap();
+        assert!(q.child("q").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_node_keys_outside_reserved_keys() {
+        // 节点 key 的首字节是第一个 id 的长度 1..=8，保留的 key 以 0x00 开头，metadata 以 '~' 开头；
+        // 旧的 varint 编码中 id 为 125 的 root 和 metadata 的首字节相同
+        let db = temp_database("reserved_keys");
+        let ids = [0, 125, 126, 255, u64::MAX];
+        for id in ids {
+            db.insert_json(&root_key(id), &mut br#"{"a": [1, {"b": null}]}"#.to_vec())
+                .unwrap();
+        }
+        let reserved = [
+            ROOT_PREFIX,
+            CHECKPOINT_PREFIX,
+            FIELD_INDEX_PREFIX,
+            ELEMENT_INDEX_PREFIX,
+            METADAT_KEY,
+        ];
+        let mut nodes = 0;
+        for k in db.store.tree.iter().keys() {
+            let k = k.unwrap();
+            if reserved.iter().any(|p| k.starts_with(p)) {
+                continue;
+            }
+            assert!((1..=8).contains(&k[0]), "{:?}", k);
+            Key::decode(&k).unwrap();
+            nodes += 1;
+        }
+        assert_eq!(nodes, ids.len() * 5);
+        for id in ids {
+            let a = db.cursor(&root_key(id)).unwrap().child("a").unwrap();
+            assert_eq!(a.unwrap().children().unwrap().len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_cursor_over_packed() {
+        let (db, _) = temp_document("cursor_over_packed", "[]");
+        db.set_inline_threshold(64);
+        let root = root_key(1);
+        let doc = r#"{"user": {"name": "a", "tags": ["x", {"deep": [1, 2]}]}, "n": 1}"#;
+        db.insert_json(&root, &mut doc.as_bytes().to_vec()).unwrap();
+        let keys = db.store.tree.len();
+
+        let cursor = db.cursor(&root).unwrap();
+        assert_eq!(cursor.kind(), NodeKind::Object);
+        assert_eq!(cursor.value().unwrap(), json(doc));
+        let names: Vec<_> = cursor
+            .children()
+            .unwrap()
+            .iter()
+            .map(|c| c.name().unwrap().to_vec())
+            .collect();
+        assert_eq!(names, vec![b"user".to_vec(), b"n".to_vec()]);
+
+        let user = cursor.child("user").unwrap().unwrap();
+        let tags = user.child("tags").unwrap().unwrap();
+        assert_eq!(tags.kind(), NodeKind::Array);
+        assert!(tags.child("x").unwrap().is_none());
+        assert!(tags.index(2).unwrap().is_none());
+        let deep = tags.index(1).unwrap().unwrap();
+        let deep = deep.child("deep").unwrap().unwrap();
+        assert_eq!(deep.value().unwrap(), json("[1, 2]"));
+        let two = deep.index(1).unwrap().unwrap();
+        assert_eq!(two.kind(), NodeKind::Number);
+        assert_eq!(two.value().unwrap(), json("2"));
+        assert!(two.children().unwrap().is_empty());
+
+        let mut up = two;
+        for _ in 0..4 {
+            up = up.parent().unwrap().unwrap();
+        }
+        assert_eq!(up.name(), Some(&b"user"[..]));
+        let top = up.parent().unwrap().unwrap();
+        assert!(top.parent().unwrap().is_none());
+        assert_eq!(top.value().unwrap(), json(doc));
+        assert_eq!(db.store.tree.len(), keys);
+    }
+}
@@ -0,0 +1,618 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use simd_json::{OwnedValue, StaticNode};
+use sled::transaction::{
+    ConflictableTransactionError, ConflictableTransactionResult, TransactionError,
+    TransactionalTree,
+};
+
+use crate::json::{self, ItemValue};
+use crate::kv::{
+    packed_children, EncodeError, Key, KeyIndex, NodeValue, PackedWriter, StagedStore, Store,
+    VariableSizedId,
+};
+use crate::{DBError, Database, METADAT_KEY};
+
+use super::allocator::IdAllocator;
+use super::locks::RootLocks;
+use super::{format_path, root_entry_key, JsonPathSegment, Metadata};
+
+/// 对数据库的一次原子修改
+///
+/// 所有写入先暂存在 `StagedStore` 中，之后的读操作能看到这些修改；
+/// `commit` 时通过一个 `sled::Batch` 写入，注册或注销了 root 时改为在 sled 事务中
+/// 连同 metadata 中的 root 个数一起写入。直接丢弃 `Editor` 即放弃全部修改。
+///
+/// 访问 packed 节点的子节点时先把它拆开一层暂存起来，之后的修改和普通节点一样，
+/// 只读的时候丢弃 `Editor` 即可，不会改变存储的形式。
+pub(crate) struct Editor<'a> {
+    store: &'a Store,
+    pub(crate) staged: StagedStore<'a>,
+    ids: &'a IdAllocator,
+    locks: &'a RootLocks,
+    /// 见 `Database::set_inline_threshold`
+    inline: usize,
+    /// 本次注册的 root
+    registered: Vec<Vec<u8>>,
+    /// 本次注销的 root
+    unregistered: Vec<Vec<u8>>,
+}
+
+impl<'a> Editor<'a> {
+    pub fn new(db: &'a Database) -> Self {
+        Self {
+            store: &db.store,
+            staged: StagedStore::new(&db.store),
+            ids: &db.ids,
+            locks: &db.locks,
+            inline: db.inline_threshold(),
+            registered: Vec::new(),
+            unregistered: Vec::new(),
+        }
+    }
+
+    /// 创建一个记录读集合的 `Editor`，需要通过 `commit_checked` 提交
+    pub fn tracking(db: &'a Database) -> Self {
+        Self {
+            store: &db.store,
+            staged: StagedStore::tracking(&db.store),
+            ids: &db.ids,
+            locks: &db.locks,
+            inline: db.inline_threshold(),
+            registered: Vec::new(),
+            unregistered: Vec::new(),
+        }
+    }
+
+    /// root 是否已注册，包括本次暂存的注册和注销
+    pub fn has_root(&self, root: &[u8]) -> Result<bool, DBError> {
+        Ok(self.staged.get_raw(&root_entry_key(root))?.is_some())
+    }
+
+    /// 注册 root，调用方需要先确认它还没有注册；提交时如果已被其它写入抢先注册，返回
+    /// `DBError::DuplicateRootKey`
+    pub fn register_root(&mut self, root: &[u8]) {
+        self.staged.insert_raw(root_entry_key(root), Bytes::new());
+        self.registered.push(root.to_vec());
+    }
+
+    /// 注销 root，不会删除文档的节点
+    pub fn unregister_root(&mut self, root: &[u8]) -> Result<(), DBError> {
+        if self.has_root(root)? {
+            self.staged.remove_raw(root_entry_key(root));
+            self.unregistered.push(root.to_vec());
+        }
+        Ok(())
+    }
+
+    /// 校验 root 已注册，并解码为 `Key`
+    pub fn root_key(&self, root: &[u8]) -> Result<Key, DBError> {
+        if !self.has_root(root)? {
+            return Err(DBError::RootNotFound);
+        }
+        let key = Key::decode(root)?;
+        if !key.field_key.is_root() {
+            return Err(DBError::RootNotFound);
+        }
+        Ok(key)
+    }
+
+    pub fn node(&self, key: &Key) -> Result<Option<NodeValue>, DBError> {
+        Ok(self.staged.get(key)?)
+    }
+
+    /// 返回节点自身及其所有子孙节点，按编码后的 key 排序（父节点总在子节点之前）
+    pub fn subtree(&self, key: &Key) -> Result<Vec<(Key, NodeValue)>, DBError> {
+        let mut nodes = Vec::new();
+        for (k, v) in self.staged.scan_prefix(&key.id_prefix())? {
+            nodes.push((Key::decode(&k)?, NodeValue::decode(&v)?));
+        }
+        Ok(nodes)
+    }
+
+    /// `key` 是 packed 节点时把它拆开一层：节点自身改写为普通的 object 或数组，
+    /// 直接子节点分配新的 id 写入，其中的容器仍然是 packed
+    pub fn expand(&self, key: &Key) -> Result<(), DBError> {
+        let Some(packed) = self.node(key)? else {
+            return Ok(());
+        };
+        let NodeValue::Packed(data) = &packed else {
+            return Ok(());
+        };
+        let container = if packed.is_array() {
+            NodeValue::Array
+        } else {
+            NodeValue::Object
+        };
+        self.staged.insert(key, &container);
+        for (index, value) in packed_children(data)? {
+            self.staged
+                .insert(&make_sub_key(key, self.ids, index), &value);
+        }
+        Ok(())
+    }
+
+    /// 返回直接子节点，数组元素通过下标索引按下标顺序读取，object 成员按存储顺序排列
+    pub fn children(&self, key: &Key) -> Result<Vec<(Key, NodeValue)>, DBError> {
+        self.expand(key)?;
+        if self.node(key)?.is_some_and(|v| v.is_array()) {
+            return Ok(self.staged.elements(key, 0..u64::MAX)?);
+        }
+        let depth = key.ids.len() + 1;
+        Ok(self
+            .subtree(key)?
+            .into_iter()
+            .filter(|(k, _)| k.ids.len() == depth)
+            .collect())
+    }
+
+    /// 查找直接子节点，object 成员通过字段名索引点查，数组元素通过下标索引点查
+    pub fn child(&self, key: &Key, index: &KeyIndex) -> Result<Option<(Key, NodeValue)>, DBError> {
+        self.expand(key)?;
+        match index {
+            KeyIndex::Field(name) => Ok(self.staged.member(key, name)?),
+            KeyIndex::Id(id) => Ok(self.staged.element(key, id.to_u64()?)?),
+            KeyIndex::Root => Ok(None),
+        }
+    }
+
+    /// 沿路径从 root 向下查找节点，经过的 packed 节点会被拆开
+    pub fn resolve(
+        &self,
+        root: &Key,
+        segments: &[JsonPathSegment],
+    ) -> Result<(Key, NodeValue), DBError> {
+        self.walk(root, segments, true)
+    }
+
+    /// 和 `resolve` 相同，但不拆开 packed 节点，用于不应改变存储形式的只读操作
+    ///
+    /// 路径进入 packed 节点之后在内存中查找，这时返回的 key 不在存储中，
+    /// 值是标量或 packed 值，需要通过 `value_of` 读取。
+    pub fn lookup(
+        &self,
+        root: &Key,
+        segments: &[JsonPathSegment],
+    ) -> Result<(Key, NodeValue), DBError> {
+        self.walk(root, segments, false)
+    }
+
+    fn walk(
+        &self,
+        root: &Key,
+        segments: &[JsonPathSegment],
+        expand: bool,
+    ) -> Result<(Key, NodeValue), DBError> {
+        let mut current = root.clone();
+        let mut value = self.node(root)?.ok_or(DBError::RootNotFound)?;
+        for (i, segment) in segments.iter().enumerate() {
+            let not_found = || DBError::PathNotFound(format_path(&segments[..=i]));
+            let index = match segment {
+                JsonPathSegment::Key(name) if value.is_object() => {
+                    KeyIndex::Field(Bytes::copy_from_slice(name.as_bytes()))
+                }
+                JsonPathSegment::Index(idx) if value.is_array() => {
+                    KeyIndex::Id(VariableSizedId::new(*idx as u64))
+                }
+                _ => return Err(not_found()),
+            };
+            let child = match &value {
+                NodeValue::Packed(data) if !expand => packed_children(data)?
+                    .into_iter()
+                    .enumerate()
+                    .find(|(_, (k, _))| *k == index)
+                    .map(|(i, (k, v))| (current.sub_key(VariableSizedId::new(i as u64), k), v)),
+                _ => self.child(&current, &index)?,
+            };
+            (current, value) = child.ok_or_else(not_found)?;
+        }
+        Ok((current, value))
+    }
+
+    /// 读取 `resolve` 或 `lookup` 找到的节点
+    pub fn value_of(&self, key: &Key, value: NodeValue) -> Result<OwnedValue, DBError> {
+        match value {
+            NodeValue::Object | NodeValue::Array => self.read_value(key),
+            value => leaf_value(value),
+        }
+    }
+
+    /// 将节点及其子孙还原为 `OwnedValue`
+    pub fn read_value(&self, key: &Key) -> Result<OwnedValue, DBError> {
+        let mut values = HashMap::new();
+        let mut children: HashMap<Vec<VariableSizedId>, Vec<(KeyIndex, Vec<VariableSizedId>)>> =
+            HashMap::new();
+        for (k, v) in self.subtree(key)? {
+            if k.ids.len() > key.ids.len() {
+                let parent = k.ids[..k.ids.len() - 1].to_vec();
+                children
+                    .entry(parent)
+                    .or_default()
+                    .push((k.field_key, k.ids.clone()));
+            }
+            values.insert(k.ids, v);
+        }
+        build_value(&key.ids, &mut values, &mut children)
+    }
+
+    /// 在 `parent` 下分配一个新的子节点 key
+    pub fn sub_key(&mut self, parent: &Key, index: KeyIndex) -> Key {
+        make_sub_key(parent, self.ids, index)
+    }
+
+    /// 在 `parent` 下为新子节点腾出位置并分配 key
+    ///
+    /// object 成员已存在时删除原成员的子树并沿用它的 key，替换后的成员留在原来的位置；
+    /// 数组元素则把该下标及之后的元素后移一位。
+    pub fn make_room(&mut self, parent: &Key, index: KeyIndex) -> Result<Key, DBError> {
+        match &index {
+            KeyIndex::Field(_) => {
+                if let Some((existing, _)) = self.child(parent, &index)? {
+                    self.remove_subtree(&existing)?;
+                    return Ok(existing);
+                }
+            }
+            KeyIndex::Id(id) => self.shift_elements(parent, id.to_u64()? as usize, 1)?,
+            KeyIndex::Root => {}
+        }
+        Ok(self.sub_key(parent, index))
+    }
+
+    /// 为写入 `segments` 指向的位置做准备，返回新节点的 key
+    ///
+    /// 语义同 JSON Patch 的 `add`：object 成员已存在时删除原成员，数组下标处插入，
+    /// 下标等于长度时追加。空路径表示整个文档：root 已注册时删除原文档，否则注册新的 root。
+    /// 父节点类型和最后一段不匹配时返回 `DBError::InvalidSuperNodeType`，其中带有父节点的路径。
+    pub fn prepare_slot(
+        &mut self,
+        root: &[u8],
+        segments: &[JsonPathSegment],
+    ) -> Result<Key, DBError> {
+        let (last, parent) = match segments.split_last() {
+            Some(split) => split,
+            None => {
+                if self.has_root(root)? {
+                    let key = self.root_key(root)?;
+                    self.remove_subtree(&key)?;
+                    return Ok(key);
+                }
+                let key = Key::decode(root)?;
+                if !key.field_key.is_root() {
+                    return Err(DBError::RootNotFound);
+                }
+                self.register_root(root);
+                return Ok(key);
+            }
+        };
+        let root = self.root_key(root)?;
+        let (parent_key, parent_value) = self.resolve(&root, parent)?;
+        let index = match last {
+            JsonPathSegment::Key(name) if parent_value.is_object() => {
+                KeyIndex::Field(Bytes::copy_from_slice(name.as_bytes()))
+            }
+            JsonPathSegment::Index(idx) if parent_value.is_array() => {
+                if *idx > self.children(&parent_key)?.len() {
+                    return Err(DBError::IndexOutOfRange(*idx));
+                }
+                KeyIndex::Id(VariableSizedId::new(*idx as u64))
+            }
+            _ => return Err(DBError::InvalidSuperNodeType(format_path(parent))),
+        };
+        self.make_room(&parent_key, index)
+    }
+
+    /// 把 json 值写到 `key` 上，子孙节点分配新的 id
+    ///
+    /// 同一个父节点下的子节点按 json 文本中的顺序分配 id，读出时 object 成员保持原来的顺序。
+    /// 设置了内联阈值时，编码后不超过阈值的容器整体写为一个 `NodeValue::Packed`。
+    pub fn write_value(&mut self, key: Key, value: json::TapeValue) {
+        let ids = self.ids;
+        let staged = &mut self.staged;
+        let inline = self.inline;
+        let mut json_iter = json::JsonDfsIter::new(value, key, |item, node_key| match item {
+            json::IterItem::KV(k, _) => make_sub_key(
+                node_key,
+                ids,
+                KeyIndex::Field(Bytes::copy_from_slice(k.as_bytes())),
+            ),
+            json::IterItem::IV(idx, _) => make_sub_key(
+                node_key,
+                ids,
+                KeyIndex::Id(VariableSizedId::new(*idx as u64)),
+            ),
+            json::IterItem::Array
+            | json::IterItem::Object
+            | json::IterItem::String(_)
+            | json::IterItem::Static(_)
+            | json::IterItem::Pruned(_) => {
+                if let Some(last_id) = node_key.ids.last() {
+                    if let Ok(last_id) = last_id.to_u64() {
+                        ids.observe(last_id);
+                    }
+                }
+                node_key.clone()
+            }
+        });
+        if inline > 0 {
+            json_iter = json_iter
+                .prune(move |value| pack_tape(&mut PackedWriter::default(), value, inline));
+        }
+        for (item, key) in json_iter {
+            let value = match item {
+                json::IterItem::Pruned(value) => {
+                    let mut writer = PackedWriter::default();
+                    pack_tape(&mut writer, value, usize::MAX);
+                    staged.insert(&key, &NodeValue::Packed(writer.finish()));
+                    continue;
+                }
+                json::IterItem::IV(_, v) | json::IterItem::KV(_, v) => v,
+                json::IterItem::Array => ItemValue::Array,
+                json::IterItem::Object => ItemValue::Object,
+                json::IterItem::Static(s) => ItemValue::Static(s),
+                json::IterItem::String(s) => ItemValue::String(s),
+            };
+            staged.insert(&key, &node_value(value));
+        }
+    }
+
+    /// 删除节点及其所有子孙节点
+    pub fn remove_subtree(&mut self, key: &Key) -> Result<(), DBError> {
+        for (k, _) in self.staged.scan_prefix(&key.id_prefix())? {
+            self.staged.remove_raw(k);
+        }
+        Ok(())
+    }
+
+    /// 将数组中下标 >= `from` 的元素整体平移 `delta` 位
+    ///
+    /// 数组下标只出现在元素自身的 key 和下标索引中，子孙节点的 key 只包含 id，
+    /// 所以只需要改写元素节点，下标索引随节点一起改写。
+    pub fn shift_elements(&mut self, array: &Key, from: usize, delta: i64) -> Result<(), DBError> {
+        self.expand(array)?;
+        let moved: Vec<_> = self
+            .staged
+            .elements(array, from as u64..u64::MAX)?
+            .into_iter()
+            .filter_map(|(k, v)| element_index(&k).map(|idx| (k, v, idx)))
+            .collect();
+        // 先删后写，避免新旧下标重叠时互相覆盖
+        for (k, _, _) in &moved {
+            self.staged.remove_raw(k.encode());
+        }
+        for (k, v, idx) in moved {
+            let idx = (idx as i64 + delta) as u64;
+            let k = k.with_field_key(KeyIndex::Id(VariableSizedId::new(idx)));
+            self.staged.insert(&k, &v);
+        }
+        Ok(())
+    }
+
+    /// 把以 `from` 为根的一组节点（通常来自 `subtree`）重新挂到 `to` 上
+    ///
+    /// `fresh_ids` 为 true 时所有子孙节点分配新的 id（复制），否则保留原有 id（移动）。
+    /// 不会删除原来的节点。
+    pub fn graft(&mut self, nodes: Vec<(Key, NodeValue)>, from: &Key, to: &Key, fresh_ids: bool) {
+        let mut mapped: HashMap<Vec<VariableSizedId>, Vec<VariableSizedId>> = HashMap::new();
+        mapped.insert(from.ids.clone(), to.ids.clone());
+        for (k, v) in nodes {
+            if k.ids.len() == from.ids.len() {
+                self.staged.insert(to, &v);
+                continue;
+            }
+            let key = if fresh_ids {
+                // 父节点总是先于子节点出现，所以这里一定能找到父节点的新 ids
+                let parent = &mapped[&k.ids[..k.ids.len() - 1]];
+                let mut ids = parent.clone();
+                ids.push(VariableSizedId::new(self.ids.next()));
+                mapped.insert(k.ids.clone(), ids.clone());
+                Key {
+                    ids,
+                    field_key: k.field_key,
+                }
+            } else {
+                let mut ids = to.ids.clone();
+                ids.extend_from_slice(&k.ids[from.ids.len()..]);
+                Key {
+                    ids,
+                    field_key: k.field_key,
+                }
+            };
+            self.staged.insert(&key, &v);
+        }
+    }
+
+    /// 以 sled 事务提交，读过的数据被修改时返回 `Ok(false)`，不写入任何数据
+    ///
+    /// 提交期间持有所有被修改和范围扫描过的文档的写锁，同一文档上的普通写操作在读取和写入之间
+    /// 不会插入事务的提交，重新扫描的范围在写入之前也不会变化。
+    /// 以 0 开头的是 root 登记等保留 key，不属于任何文档。
+    pub fn commit_checked(self) -> Result<bool, DBError> {
+        let written = self.staged.written_keys();
+        let scanned = self.staged.scanned_keys();
+        let _locks = self.locks.write_many(
+            written
+                .iter()
+                .chain(&scanned)
+                .map(Vec::as_slice)
+                .filter(|k| k.first() != Some(&0)),
+        );
+        self.ids.reserve(self.store)?;
+        let (registered, unregistered) = (self.registered, self.unregistered);
+        self.staged
+            .commit_transaction(|tx| update_roots(tx, &registered, &unregistered))
+            .map_err(transaction_error)
+    }
+
+    /// 原子提交所有修改
+    pub fn commit(self) -> Result<(), DBError> {
+        self.ids.reserve(self.store)?;
+        if self.registered.is_empty() && self.unregistered.is_empty() {
+            self.store.tree.apply_batch(self.staged.into_batch())?;
+            return Ok(());
+        }
+        let (registered, unregistered) = (self.registered, self.unregistered);
+        self.staged
+            .commit_transaction(|tx| update_roots(tx, &registered, &unregistered))
+            .map_err(transaction_error)?;
+        Ok(())
+    }
+}
+
+/// 在 sled 事务中确认新注册的 root 没有被抢先注册，并更新 metadata 中的 root 个数
+///
+/// 需要在写入暂存的 root 条目之前调用。
+pub(crate) fn update_roots(
+    tx: &TransactionalTree,
+    registered: &[Vec<u8>],
+    unregistered: &[Vec<u8>],
+) -> ConflictableTransactionResult<(), DBError> {
+    for root in registered {
+        if !unregistered.contains(root) && tx.get(root_entry_key(root))?.is_some() {
+            return Err(ConflictableTransactionError::Abort(
+                DBError::DuplicateRootKey,
+            ));
+        }
+    }
+    let raw = tx.get(METADAT_KEY)?.unwrap_or_default();
+    let mut metadata = Metadata::decode(&raw)
+        .map_err(|e| ConflictableTransactionError::Abort(DBError::from(e)))?;
+    metadata.root_count = metadata.root_count + registered.len() as u64 - unregistered.len() as u64;
+    tx.insert(METADAT_KEY, metadata.encode())?;
+    Ok(())
+}
+
+pub(crate) fn transaction_error(e: TransactionError<DBError>) -> DBError {
+    match e {
+        TransactionError::Abort(e) => e,
+        TransactionError::Storage(e) => e.into(),
+    }
+}
+
+pub(crate) fn make_sub_key(node_key: &Key, ids: &IdAllocator, kind: KeyIndex) -> Key {
+    node_key.sub_key(VariableSizedId::new(ids.next()), kind)
+}
+
+/// 数组元素的下标，非数组元素返回 None
+pub(crate) fn element_index(key: &Key) -> Option<u64> {
+    match &key.field_key {
+        KeyIndex::Id(id) => id.to_u64().ok(),
+        _ => None,
+    }
+}
+
+pub(crate) fn node_value(value: ItemValue) -> NodeValue {
+    match value {
+        ItemValue::Array => NodeValue::Array,
+        ItemValue::Object => NodeValue::Object,
+        ItemValue::String(s) => NodeValue::String(Bytes::copy_from_slice(s.as_bytes())),
+        ItemValue::Static(StaticNode::Bool(b)) => NodeValue::Bool(*b),
+        ItemValue::Static(StaticNode::F64(f)) => NodeValue::Number(*f),
+        ItemValue::Static(StaticNode::I64(i)) => NodeValue::NumberI(*i),
+        ItemValue::Static(StaticNode::U64(u)) => NodeValue::NumberU(*u),
+        ItemValue::Static(StaticNode::Null) => NodeValue::Null,
+    }
+}
+
+fn build_value(
+    ids: &[VariableSizedId],
+    values: &mut HashMap<Vec<VariableSizedId>, NodeValue>,
+    children: &mut HashMap<Vec<VariableSizedId>, Vec<(KeyIndex, Vec<VariableSizedId>)>>,
+) -> Result<OwnedValue, DBError> {
+    let value = values.remove(ids).ok_or(DBError::DatabaseJsonError)?;
+    let value = match value {
+        NodeValue::Array => {
+            let mut elements = children.remove(ids).unwrap_or_default();
+            elements.sort_by_key(|(index, _)| match index {
+                KeyIndex::Id(id) => id.to_u64().ok(),
+                _ => None,
+            });
+            let mut array = Vec::with_capacity(elements.len());
+            for (_, child) in elements {
+                array.push(build_value(&child, values, children)?);
+            }
+            OwnedValue::Array(Box::new(array))
+        }
+        NodeValue::Object => {
+            let members = children.remove(ids).unwrap_or_default();
+            let mut object = Vec::with_capacity(members.len());
+            for (index, child) in members {
+                if let KeyIndex::Field(name) = index {
+                    object.push((
+                        utf8(&name)?.to_string(),
+                        build_value(&child, values, children)?,
+                    ));
+                }
+            }
+            object.into_iter().collect()
+        }
+        value => leaf_value(value)?,
+    };
+    Ok(value)
+}
+
+/// 还原不需要读取子节点的值：标量和 `NodeValue::Packed`
+pub(crate) fn leaf_value(value: NodeValue) -> Result<OwnedValue, DBError> {
+    let value = match value {
+        NodeValue::Null => OwnedValue::Static(StaticNode::Null),
+        NodeValue::Bool(b) => OwnedValue::Static(StaticNode::Bool(b)),
+        NodeValue::Number(n) => OwnedValue::Static(StaticNode::F64(n)),
+        NodeValue::NumberI(i) => OwnedValue::Static(StaticNode::I64(i)),
+        NodeValue::NumberU(u) => OwnedValue::Static(StaticNode::U64(u)),
+        NodeValue::String(s) => OwnedValue::String(utf8(&s)?.to_string()),
+        NodeValue::Packed(_) => packed_value(&value)?,
+        NodeValue::Array | NodeValue::Object => return Err(DBError::DatabaseJsonError),
+    };
+    Ok(value)
+}
+
+/// 把 tape 中的容器按 packed 格式写入 `writer`，超过 `limit` 字节时提前返回 false
+fn pack_tape(writer: &mut PackedWriter, value: json::TapeValue, limit: usize) -> bool {
+    if let Some(members) = json::members(value) {
+        writer.begin_object(members.len());
+        for (name, member) in members {
+            writer.name(name.as_bytes());
+            if !pack_tape(writer, member, limit) {
+                return false;
+            }
+        }
+    } else if let Some(elements) = json::elements(value) {
+        writer.begin_array(elements.len());
+        for element in elements {
+            if !pack_tape(writer, element, limit) {
+                return false;
+            }
+        }
+    } else {
+        let scalar = match value.first() {
+            Some(simd_json::Node::String(s)) => ItemValue::String(s),
+            Some(simd_json::Node::Static(s)) => ItemValue::Static(s),
+            _ => ItemValue::Static(&StaticNode::Null),
+        };
+        writer.value(&node_value(scalar));
+    }
+    writer.len() <= limit
+}
+
+fn packed_value(packed: &NodeValue) -> Result<OwnedValue, DBError> {
+    let NodeValue::Packed(data) = packed else {
+        return Err(DBError::DatabaseJsonError);
+    };
+    let mut values = Vec::new();
+    let mut object = Vec::new();
+    for (index, value) in packed_children(data)? {
+        let value = leaf_value(value)?;
+        match index {
+            KeyIndex::Field(name) => object.push((utf8(&name)?.to_string(), value)),
+            _ => values.push(value),
+        }
+    }
+    if packed.is_array() {
+        return Ok(OwnedValue::Array(Box::new(values)));
+    }
+    Ok(object.into_iter().collect())
+}
+
+fn utf8(bytes: &[u8]) -> Result<&str, DBError> {
+    Ok(std::str::from_utf8(bytes).map_err(EncodeError::InvalidUtf8)?)
+}
@@ -0,0 +1,297 @@
+use std::vec;
+
+use parking_lot::RwLockReadGuard;
+use simd_json::StaticNode;
+
+use crate::kv::{packed_children, EncodeError, Key, KeyIndex, NodeValue, Store, VariableSizedId};
+use crate::{DBError, Database};
+
+use super::cursor::{ChildIter, IndexIter};
+use super::{parse, Editor};
+
+/// 读取已存储子树时产生的事件，和写入时 `JsonDfsIter` 产生的 `IterItem` 相对应
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonEvent {
+    StartObject,
+    StartArray,
+    /// object 成员名，后面紧跟该成员的值
+    Key(String),
+    String(String),
+    Static(StaticNode),
+    /// 结束最近一个 `StartObject` 或 `StartArray`
+    End,
+}
+
+/// 在已存储的子树上按深度优先顺序产生 `JsonEvent`
+///
+/// 直接从 sled 上按 key 范围读取节点，不会还原整个子树：object 逐个读取成员，
+/// array 沿下标索引逐个读取元素，所以内存占用只和嵌套深度有关。
+/// 事件流存在期间持有该文档的读锁，读到的是一致的子树；不要在同一线程中同时修改这个文档。
+/// packed 节点在进入时一次解码出全部子节点。
+pub struct EventReader<'a> {
+    _lock: RwLockReadGuard<'a, ()>,
+    store: &'a Store,
+    sort_keys: bool,
+    stack: Vec<Frame<'a>>,
+    /// 下一个要输出的节点，object 成员在输出 `Key` 事件之后放在这里
+    next: Option<(Key, NodeValue)>,
+}
+
+enum Frame<'a> {
+    /// 逐个从 sled 读取的 object 成员
+    Object(ChildIter<'a>),
+    /// 沿下标索引或字段名索引逐个读取的数组元素或 object 成员
+    Indexed(Box<IndexIter<'a>>),
+    /// packed 节点在内存中解码出的子节点
+    Packed(vec::IntoIter<(Key, NodeValue)>),
+}
+
+impl Database {
+    /// 返回 `path` 指向子树的事件流
+    ///
+    /// # 参数
+    /// * `root` - 编码后的 root key
+    /// * `path` - JSONPath 字符串，如 "$" 或 "$.items[0]"
+    pub fn events(&self, root: &[u8], path: &str) -> Result<EventReader<'_>, DBError> {
+        let segments = parse(path)?;
+        let lock = self.locks.read(root);
+        let editor = Editor::new(self);
+        let root = editor.root_key(root)?;
+        let (key, value) = editor.lookup(&root, &segments)?;
+        Ok(EventReader {
+            _lock: lock,
+            store: &self.store,
+            sort_keys: false,
+            stack: Vec::new(),
+            next: Some((key, value)),
+        })
+    }
+}
+
+impl EventReader<'_> {
+    /// object 成员按名字的字节序输出，沿字段名索引逐个读取，不需要一次读出全部成员
+    pub fn sort_keys(mut self, sort_keys: bool) -> Self {
+        self.sort_keys = sort_keys;
+        self
+    }
+
+    fn enter(&mut self, key: Key, value: NodeValue) -> Result<JsonEvent, DBError> {
+        let event = match value {
+            NodeValue::Null => JsonEvent::Static(StaticNode::Null),
+            NodeValue::Bool(b) => JsonEvent::Static(StaticNode::Bool(b)),
+            NodeValue::Number(n) => JsonEvent::Static(StaticNode::F64(n)),
+            NodeValue::NumberI(i) => JsonEvent::Static(StaticNode::I64(i)),
+            NodeValue::NumberU(u) => JsonEvent::Static(StaticNode::U64(u)),
+            NodeValue::String(s) => JsonEvent::String(utf8(s.to_vec())?),
+            value @ NodeValue::Packed(_) => self.enter_packed(&key, &value)?,
+            NodeValue::Object if self.sort_keys => {
+                let members = IndexIter::members(self.store, &key);
+                self.stack.push(Frame::Indexed(Box::new(members)));
+                JsonEvent::StartObject
+            }
+            NodeValue::Object => {
+                self.stack
+                    .push(Frame::Object(ChildIter::new(self.store, &key)));
+                JsonEvent::StartObject
+            }
+            NodeValue::Array => {
+                let elements = IndexIter::elements(self.store, &key, 0..u64::MAX);
+                self.stack.push(Frame::Indexed(Box::new(elements)));
+                JsonEvent::StartArray
+            }
+        };
+        Ok(event)
+    }
+
+    /// packed 节点的子节点直接在内存中输出，它们的 key 只用来携带成员名
+    fn enter_packed(&mut self, key: &Key, value: &NodeValue) -> Result<JsonEvent, DBError> {
+        let NodeValue::Packed(data) = value else {
+            return Err(DBError::DatabaseJsonError);
+        };
+        let mut children: Vec<_> = packed_children(data)?
+            .into_iter()
+            .enumerate()
+            .map(|(i, (index, v))| (key.sub_key(VariableSizedId::new(i as u64), index), v))
+            .collect();
+        if value.is_array() {
+            self.stack.push(Frame::Packed(children.into_iter()));
+            return Ok(JsonEvent::StartArray);
+        }
+        if self.sort_keys {
+            children.sort_by(|(a, _), (b, _)| field_name(a).cmp(field_name(b)));
+        }
+        self.stack.push(Frame::Packed(children.into_iter()));
+        Ok(JsonEvent::StartObject)
+    }
+
+    fn step(&mut self) -> Option<Result<JsonEvent, DBError>> {
+        if let Some((key, value)) = self.next.take() {
+            return Some(self.enter(key, value));
+        }
+        let child = match self.stack.last_mut()? {
+            Frame::Object(members) => members.next(),
+            Frame::Indexed(children) => children.next(),
+            Frame::Packed(children) => children.next().map(Ok),
+        };
+        let (key, value) = match child {
+            Some(Ok(child)) => child,
+            Some(Err(e)) => return Some(Err(e)),
+            None => {
+                self.stack.pop();
+                return Some(Ok(JsonEvent::End));
+            }
+        };
+        if let KeyIndex::Field(name) = &key.field_key {
+            let name = match utf8(name.to_vec()) {
+                Ok(name) => name,
+                Err(e) => return Some(Err(e)),
+            };
+            self.next = Some((key, value));
+            return Some(Ok(JsonEvent::Key(name)));
+        }
+        Some(self.enter(key, value))
+    }
+}
+
+impl Iterator for EventReader<'_> {
+    type Item = Result<JsonEvent, DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = self.step();
+        if let Some(Err(_)) = event {
+            // 出错后不再继续
+            self.stack.clear();
+            self.next = None;
+        }
+        event
+    }
+}
+
+fn field_name(key: &Key) -> &[u8] {
+    match &key.field_key {
+        KeyIndex::Field(name) => name,
+        _ => &[],
+    }
+}
+
+fn utf8(bytes: Vec<u8>) -> Result<String, DBError> {
+    Ok(String::from_utf8(bytes).map_err(|e| EncodeError::InvalidUtf8(e.utf8_error()))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use simd_json::StaticNode;
+
+    use super::JsonEvent::{self, *};
+    use crate::test_util::temp_document;
+
+    #[test]
+    fn test_events() {
+        let (db, root) = temp_document("events", r#"{"a": [1, {"b": null}, []], "c": "s"}"#);
+        // 插入后数组元素的 id 顺序和下标顺序不同
+        db.array_insert(&root, "$.a", 0, &mut b"true".to_vec())
+            .unwrap();
+        let events: Vec<JsonEvent> = db
+            .events(&root, "$")
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            events,
+            vec![
+                StartObject,
+                Key("a".to_string()),
+                StartArray,
+                Static(StaticNode::Bool(true)),
+                Static(StaticNode::I64(1)),
+                StartObject,
+                Key("b".to_string()),
+                Static(StaticNode::Null),
+                End,
+                StartArray,
+                End,
+                End,
+                Key("c".to_string()),
+                String("s".to_string()),
+                End,
+            ]
+        );
+
+        let events: Vec<JsonEvent> = db
+            .events(&root, "$.a[0]")
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(events, vec![Static(StaticNode::Bool(true))]);
+    }
+
+    #[test]
+    fn test_events_sort_keys() {
+        let doc = r#"{"b": 1, "ab": {"y": 2, "x": 3}, "a\u0000b": null, "a": [4]}"#;
+        let (db, root) = temp_document("events_sort_keys", doc);
+        db.array_insert(&root, "$.a", 0, &mut b"5".to_vec())
+            .unwrap();
+        let events: Vec<JsonEvent> = db
+            .events(&root, "$")
+            .unwrap()
+            .sort_keys(true)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        // 成员名按原始字节排序，"a\0b" 排在 "a" 和 "ab" 之间
+        assert_eq!(
+            events,
+            vec![
+                StartObject,
+                Key("a".to_string()),
+                StartArray,
+                Static(StaticNode::U64(5)),
+                Static(StaticNode::U64(4)),
+                End,
+                Key("a\0b".to_string()),
+                Static(StaticNode::Null),
+                Key("ab".to_string()),
+                StartObject,
+                Key("x".to_string()),
+                Static(StaticNode::U64(3)),
+                Key("y".to_string()),
+                Static(StaticNode::U64(2)),
+                End,
+                Key("b".to_string()),
+                Static(StaticNode::U64(1)),
+                End,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_events_over_packed() {
+        let doc = r#"{"z": 1, "a": {"y": [true, {"k": "v"}], "b": [-1, 2.5]}, "c": null}"#;
+        let (plain, root) = temp_document("events_plain", doc);
+        let db = crate::test_util::temp_database("events_over_packed");
+        db.set_inline_threshold(64);
+        db.insert_json(&root, &mut doc.as_bytes().to_vec()).unwrap();
+        let keys = db.store.tree.len();
+        assert!(keys < plain.store.tree.len());
+
+        let collect = |db: &crate::Database, path: &str, sort_keys: bool| -> Vec<JsonEvent> {
+            db.events(&root, path)
+                .unwrap()
+                .sort_keys(sort_keys)
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        for path in ["$", "$.a", "$.a.y", "$.a.y[1]", "$.a.b[0]"] {
+            for sort_keys in [false, true] {
+                assert_eq!(
+                    collect(&db, path, sort_keys),
+                    collect(&plain, path, sort_keys),
+                    "{} sort_keys={}",
+                    path,
+                    sort_keys
+                );
+            }
+        }
+        // 路径经过 packed 节点时只在内存中拆开
+        assert_eq!(db.store.tree.len(), keys);
+    }
+}
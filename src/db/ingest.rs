@@ -0,0 +1,445 @@
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+
+use simd_json::StaticNode;
+
+use crate::json::ItemValue;
+use crate::{DBError, Database};
+
+use super::editor::node_value;
+use super::writer::checkpoint_key;
+use super::DocumentWriter;
+
+/// 流式导入时读取输入的缓冲区大小
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+impl Database {
+    /// 从 `reader` 流式解析 json 并导入为 `root` 文档
+    ///
+    /// 和 `insert_json` 不同，输入不需要完整地放在内存中：边读边解析，节点通过
+    /// [`DocumentWriter`] 按 `batch_size` 分批提交。每次提交时在同一个 batch 中记录检查点，
+    /// 导入中途失败后可以用 [`Database::ingest_checkpoint`] 取得已提交的输入位置，
+    /// 再从该位置调用 [`Database::resume_ingest`] 继续。导入完成前文档不可见。
+    /// `root` 之前没有完成的导入连同已经提交的节点一起作废，重新开始。
+    /// 导入期间通过 `DocumentWriter` 持有 `root` 的写锁，同一文档的其它读写等到导入结束。
+    ///
+    /// # 参数
+    /// * `root` - 编码后的 root key
+    /// * `reader` - json 输入
+    /// * `batch_size` - 每个 batch 包含的节点数
+    pub fn ingest_json<R: Read>(
+        &self,
+        root: &[u8],
+        reader: R,
+        batch_size: usize,
+    ) -> Result<(), DBError> {
+        let mut writer = self.document_writer(root)?.batch_size(batch_size);
+        writer.offset = Some(0);
+        Ingest {
+            tokens: Tokenizer::new(reader, 0),
+            writer,
+        }
+        .run()
+    }
+
+    /// `root` 未完成的导入已经提交到的输入位置，没有进行中的导入时返回 None
+    pub fn ingest_checkpoint(&self, root: &[u8]) -> Result<Option<u64>, DBError> {
+        let data = self.store.tree.get(checkpoint_key(root))?;
+        Ok(data
+            .and_then(|data| data.get(..8).map(|b| b.try_into().unwrap()))
+            .map(u64::from_be_bytes))
+    }
+
+    /// 从检查点继续 `root` 的导入，`reader` 应当从 [`Database::ingest_checkpoint`] 返回的位置开始
+    pub fn resume_ingest<R: Read>(
+        &self,
+        root: &[u8],
+        reader: R,
+        batch_size: usize,
+    ) -> Result<(), DBError> {
+        let writer = self
+            .restore_writer(root)?
+            .ok_or_else(|| DBError::InvalidWriterState("no checkpoint to resume".to_string()))?
+            .batch_size(batch_size);
+        let offset = writer.offset.unwrap_or(0);
+        Ingest {
+            tokens: Tokenizer::new(reader, offset),
+            writer,
+        }
+        .run()
+    }
+}
+
+struct Ingest<'a, R: Read> {
+    tokens: Tokenizer<R>,
+    writer: DocumentWriter<'a>,
+}
+
+impl<R: Read> Ingest<'_, R> {
+    /// 下一步要读什么完全由 writer 的嵌套状态决定，所以可以从任意一个检查点继续
+    fn run(mut self) -> Result<(), DBError> {
+        loop {
+            let (object, len) = match self.writer.stack.last() {
+                Some(frame) => (frame.object, frame.len),
+                None if self.writer.started => break,
+                None => {
+                    self.value()?;
+                    continue;
+                }
+            };
+            let close = if object { b'}' } else { b']' };
+            if self.tokens.peek()? == Some(close) {
+                self.tokens.bump();
+                self.writer.end()?;
+                continue;
+            }
+            if len > 0 {
+                self.tokens.expect(b',')?;
+            }
+            if object {
+                if self.tokens.peek()? != Some(b'"') {
+                    return Err(DBError::DatabaseJsonError);
+                }
+                let name = self.tokens.string()?;
+                self.tokens.expect(b':')?;
+                self.writer.key(&name)?;
+            }
+            self.value()?;
+        }
+        // 文档之后只允许有空白
+        if self.tokens.peek()?.is_some() {
+            return Err(DBError::DatabaseJsonError);
+        }
+        self.writer.finish()
+    }
+
+    /// 读取一个值；容器只读取开头的括号，其它标量和 `insert_json` 一样用 `node_value` 编码
+    fn value(&mut self) -> Result<(), DBError> {
+        let node = match self.tokens.peek()? {
+            Some(b'{') => {
+                self.tokens.bump();
+                self.writer.offset = Some(self.tokens.offset);
+                return self.writer.begin_object();
+            }
+            Some(b'[') => {
+                self.tokens.bump();
+                self.writer.offset = Some(self.tokens.offset);
+                return self.writer.begin_array();
+            }
+            Some(b'"') => {
+                let s = self.tokens.string()?;
+                self.writer.offset = Some(self.tokens.offset);
+                return self.writer.value(s);
+            }
+            Some(b't') => self.tokens.literal(b"true", StaticNode::Bool(true))?,
+            Some(b'f') => self.tokens.literal(b"false", StaticNode::Bool(false))?,
+            Some(b'n') => self.tokens.literal(b"null", StaticNode::Null)?,
+            Some(b'-' | b'0'..=b'9') => self.tokens.number()?,
+            _ => return Err(DBError::DatabaseJsonError),
+        };
+        self.writer.offset = Some(self.tokens.offset);
+        self.writer.scalar(node_value(ItemValue::Static(&node)))
+    }
+}
+
+/// 逐字节读取 json 记号，`offset` 是已经消费的输入字节数
+struct Tokenizer<R: Read> {
+    reader: BufReader<R>,
+    offset: u64,
+}
+
+impl<R: Read> Tokenizer<R> {
+    fn new(reader: R, offset: u64) -> Self {
+        Self {
+            reader: BufReader::with_capacity(READ_BUFFER_SIZE, reader),
+            offset,
+        }
+    }
+
+    /// 下一个字节，不消费
+    fn peek_byte(&mut self) -> Result<Option<u8>, DBError> {
+        loop {
+            match self.reader.fill_buf() {
+                Ok(buf) => return Ok(buf.first().copied()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(DBError::IoError(e.to_string())),
+            }
+        }
+    }
+
+    fn bump(&mut self) {
+        self.reader.consume(1);
+        self.offset += 1;
+    }
+
+    fn next_byte(&mut self) -> Result<u8, DBError> {
+        let b = self.peek_byte()?.ok_or(DBError::DatabaseJsonError)?;
+        self.bump();
+        Ok(b)
+    }
+
+    /// 跳过空白后的下一个字节，不消费
+    fn peek(&mut self) -> Result<Option<u8>, DBError> {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek_byte()? {
+            self.bump();
+        }
+        self.peek_byte()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), DBError> {
+        if self.peek()? != Some(byte) {
+            return Err(DBError::DatabaseJsonError);
+        }
+        self.bump();
+        Ok(())
+    }
+
+    fn literal(&mut self, text: &[u8], node: StaticNode) -> Result<StaticNode, DBError> {
+        for &expected in text {
+            if self.next_byte()? != expected {
+                return Err(DBError::DatabaseJsonError);
+            }
+        }
+        Ok(node)
+    }
+
+    fn string(&mut self) -> Result<String, DBError> {
+        self.bump();
+        let mut bytes = Vec::new();
+        loop {
+            match self.next_byte()? {
+                b'"' => break,
+                b'\\' => {
+                    let c = match self.next_byte()? {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{08}',
+                        b'f' => '\u{0c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return Err(DBError::DatabaseJsonError),
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                b if b < 0x20 => return Err(DBError::DatabaseJsonError),
+                b => bytes.push(b),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| DBError::DatabaseJsonError)
+    }
+
+    /// `\u` 之后的部分，代理对需要紧跟第二个 `\uXXXX`
+    fn unicode_escape(&mut self) -> Result<char, DBError> {
+        let high = self.hex4()?;
+        let code = match high {
+            0xD800..=0xDBFF => {
+                if self.next_byte()? != b'\\' || self.next_byte()? != b'u' {
+                    return Err(DBError::DatabaseJsonError);
+                }
+                let low = self.hex4()?;
+                if !(0xDC00..=0xDFFF).contains(&low) {
+                    return Err(DBError::DatabaseJsonError);
+                }
+                0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+            }
+            _ => high,
+        };
+        char::from_u32(code).ok_or(DBError::DatabaseJsonError)
+    }
+
+    fn hex4(&mut self) -> Result<u32, DBError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = (self.next_byte()? as char)
+                .to_digit(16)
+                .ok_or(DBError::DatabaseJsonError)?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    /// 和 `insert_json` 使用的 simd_json 保持一致：负整数为 i64，非负整数为 u64，
+    /// 超出 64 位范围的整数不合法
+    fn number(&mut self) -> Result<StaticNode, DBError> {
+        let mut text = Vec::new();
+        while let Some(b @ (b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E')) = self.peek_byte()? {
+            text.push(b);
+            self.bump();
+        }
+        if !is_json_number(&text) {
+            return Err(DBError::DatabaseJsonError);
+        }
+        // 只包含 ASCII 字符
+        let text = std::str::from_utf8(&text).unwrap();
+        if !text.contains(['.', 'e', 'E']) {
+            let node = if text.starts_with('-') {
+                text.parse::<i64>().map(StaticNode::I64).ok()
+            } else {
+                text.parse::<u64>().map(StaticNode::U64).ok()
+            };
+            return node.ok_or(DBError::DatabaseJsonError);
+        }
+        text.parse::<f64>()
+            .map(StaticNode::F64)
+            .map_err(|_| DBError::DatabaseJsonError)
+    }
+}
+
+/// `-? (0 | [1-9][0-9]*) (. [0-9]+)? ([eE] [+-]? [0-9]+)?`
+fn is_json_number(text: &[u8]) -> bool {
+    fn digits(text: &[u8]) -> usize {
+        text.iter().take_while(|b| b.is_ascii_digit()).count()
+    }
+    let mut rest = text.strip_prefix(b"-").unwrap_or(text);
+    match digits(rest) {
+        0 => return false,
+        n if n > 1 && rest[0] == b'0' => return false,
+        n => rest = &rest[n..],
+    }
+    if let Some(fraction) = rest.strip_prefix(b".") {
+        match digits(fraction) {
+            0 => return false,
+            n => rest = &fraction[n..],
+        }
+    }
+    if let Some(exponent) = rest.strip_prefix(b"e").or_else(|| rest.strip_prefix(b"E")) {
+        let exponent = exponent
+            .strip_prefix(b"+")
+            .or_else(|| exponent.strip_prefix(b"-"))
+            .unwrap_or(exponent);
+        match digits(exponent) {
+            0 => return false,
+            n => rest = &exponent[n..],
+        }
+    }
+    rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use crate::kv::Key;
+    use crate::test_util::{json, read_json, root_key, stored_leaves, temp_database};
+    use crate::DBError;
+
+    const DOC: &str = r#" {"items": [1, -2.5e3, 18446744073709551615, "a\"\u00e9\ud83d\ude00",
+        {"k": null, "t": true, "f": false}, []], "empty": {}, "s": "x"} "#;
+
+    /// 读取 `limit` 字节之后返回错误
+    struct FailingReader<'a> {
+        data: &'a [u8],
+        limit: usize,
+    }
+
+    impl Read for FailingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.limit == 0 {
+                return Err(io::Error::other("disconnected"));
+            }
+            // 每次只读一个字节，让检查点落在任意位置
+            let n = buf.len().min(self.data.len()).min(1);
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            self.limit -= n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_ingest_json() {
+        let db = temp_database("ingest_json");
+        let root = root_key(0);
+        db.ingest_json(&root, DOC.as_bytes(), 2).unwrap();
+        assert_eq!(read_json(&db, &root), json(DOC));
+        assert_eq!(db.ingest_checkpoint(&root).unwrap(), None);
+
+        let root = root_key(1);
+        for bad in ["[1, 2", "[1,]", "{\"a\" 1}", "01", "[1] 2", "\"\\x\""] {
+            assert!(matches!(
+                db.ingest_json(&root, bad.as_bytes(), 1),
+                Err(DBError::DatabaseJsonError)
+            ));
+        }
+        assert!(!db.has_root(&root).unwrap());
+    }
+
+    #[test]
+    fn test_resume_ingest() {
+        let db = temp_database("resume_ingest");
+        let root = root_key(0);
+        let data = DOC.as_bytes();
+        let reader = FailingReader { data, limit: 60 };
+        assert!(matches!(
+            db.ingest_json(&root, reader, 3),
+            Err(DBError::IoError(_))
+        ));
+        assert!(!db.has_root(&root).unwrap());
+
+        // 从检查点开始再失败一次
+        let offset = db.ingest_checkpoint(&root).unwrap().unwrap() as usize;
+        assert!(offset > 0 && offset <= 60);
+        let reader = FailingReader {
+            data: &data[offset..],
+            limit: 40,
+        };
+        assert!(db.resume_ingest(&root, reader, 3).is_err());
+
+        let offset = db.ingest_checkpoint(&root).unwrap().unwrap() as usize;
+        db.resume_ingest(&root, &data[offset..], 3).unwrap();
+        assert_eq!(read_json(&db, &root), json(DOC));
+        assert_eq!(db.ingest_checkpoint(&root).unwrap(), None);
+    }
+
+    #[test]
+    fn test_restart_failed_ingest() {
+        let db = temp_database("restart_failed_ingest");
+        let expected = root_key(0);
+        db.insert_json(&expected, &mut DOC.as_bytes().to_vec())
+            .unwrap();
+
+        let root = root_key(1);
+        let reader = FailingReader {
+            data: DOC.as_bytes(),
+            limit: 100,
+        };
+        assert!(db.ingest_json(&root, reader, 2).is_err());
+        assert!(db.ingest_checkpoint(&root).unwrap().is_some());
+        // 重新开始时删除上次已经提交的节点，结果和 insert_json 相同
+        db.ingest_json(&root, DOC.as_bytes(), 2).unwrap();
+        assert_eq!(read_json(&db, &root), read_json(&db, &expected));
+        assert_eq!(stored_leaves(&db, &root), stored_leaves(&db, &expected));
+        let nodes = |root: &[u8]| {
+            let prefix = Key::decode(root).unwrap().id_prefix();
+            db.store.tree.scan_prefix(prefix).count()
+        };
+        assert_eq!(nodes(&root), nodes(&expected));
+    }
+
+    #[test]
+    fn test_ingest_number_types() {
+        let db = temp_database("ingest_number_types");
+        let doc = r#"[0, -0, 7, -7, 9223372036854775807, 9223372036854775808,
+            -9223372036854775808, 18446744073709551615, 1.5, -1e3, 2.5E-3]"#;
+        db.insert_json(&root_key(0), &mut doc.as_bytes().to_vec())
+            .unwrap();
+        db.ingest_json(&root_key(1), doc.as_bytes(), 4).unwrap();
+        assert_eq!(
+            stored_leaves(&db, &root_key(1)),
+            stored_leaves(&db, &root_key(0))
+        );
+
+        // 超出 64 位范围的整数两边都不接受
+        for bad in ["18446744073709551616", "-9223372036854775809"] {
+            assert!(db
+                .insert_json(&root_key(2), &mut bad.as_bytes().to_vec())
+                .is_err());
+            assert!(matches!(
+                db.ingest_json(&root_key(2), bad.as_bytes(), 1),
+                Err(DBError::DatabaseJsonError)
+            ));
+        }
+    }
+}
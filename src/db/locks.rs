@@ -0,0 +1,112 @@
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::kv::read_ordered_id;
+
+/// 锁表的分片数
+const LOCK_SHARDS: usize = 64;
+
+/// 按 root id 分片的读写锁表
+///
+/// 每个 key 的第一个 id 就是它所在文档的 root id，按它选择分片，所以同一个文档的读写
+/// 总是落在同一把锁上；不同文档大多落在不同分片，可以并行读写。
+/// 同时锁住多个 root 时按分片下标从小到大加锁，不会和其它多 root 操作互相等待。
+///
+/// 单次的读写操作只在操作期间持锁；`NodeCursor` 和 `EventReader` 在存在期间持有读锁，
+/// `DocumentWriter`（包括 `ingest_json` 和 `resume_ingest` 内部使用的）在存在期间持有写锁。
+///
+/// 锁不可重入：持有某个 root 的锁时（包括游标和事件流）不要在同一线程中写入同一分片。
+pub(crate) struct RootLocks {
+    shards: Vec<RwLock<()>>,
+}
+
+impl RootLocks {
+    pub fn new() -> Self {
+        Self {
+            shards: (0..LOCK_SHARDS).map(|_| RwLock::new(())).collect(),
+        }
+    }
+
+    /// `key` 所在文档的分片，`key` 可以是 root key，也可以是文档中任意节点的 key
+    fn shard(key: &[u8]) -> usize {
+        // 无法解码的 key 随后的操作会报错，放在哪个分片都可以
+        read_ordered_id(key).map_or(0, |(id, _)| (id % LOCK_SHARDS as u64) as usize)
+    }
+
+    /// 读锁，允许同一线程重复获取
+    pub fn read(&self, key: &[u8]) -> RwLockReadGuard<'_, ()> {
+        self.shards[Self::shard(key)].read_recursive()
+    }
+
+    pub fn write(&self, key: &[u8]) -> RwLockWriteGuard<'_, ()> {
+        self.shards[Self::shard(key)].write()
+    }
+
+    /// 同时锁住多个文档
+    pub fn write_many<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Vec<RwLockWriteGuard<'_, ()>> {
+        let mut shards: Vec<usize> = keys.into_iter().map(Self::shard).collect();
+        shards.sort_unstable();
+        shards.dedup();
+        shards.into_iter().map(|i| self.shards[i].write()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    use crate::test_util::{json, read_json, root_key, temp_document};
+
+    #[test]
+    fn test_other_roots_not_blocked() {
+        let (db, root) = temp_document("root_locks", r#"{"a": 1}"#);
+        let other = root_key(1);
+        db.insert_json(&other, &mut br#"{"b": 2}"#.to_vec())
+            .unwrap();
+
+        // 持有 root 0 的写锁时，其它 root 仍然可以读写
+        let guard = db.locks.write(&root);
+        let done = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                db.merge_patch(&other, "$", &mut br#"{"c": 3}"#.to_vec())
+                    .unwrap();
+                assert_eq!(db.get_value::<i64>(&other, "$.b").unwrap(), 2);
+                done.store(true, Ordering::SeqCst);
+            });
+            let blocked = s.spawn(|| db.get_value::<i64>(&root, "$.a").unwrap());
+            for _ in 0..1000 {
+                if done.load(Ordering::SeqCst) {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            assert!(done.load(Ordering::SeqCst));
+            assert!(!blocked.is_finished());
+            drop(guard);
+            assert_eq!(blocked.join().unwrap(), 1);
+        });
+        assert_eq!(read_json(&db, &other), json(r#"{"b": 2, "c": 3}"#));
+    }
+
+    #[test]
+    fn test_cursor_holds_read_lock() {
+        let (db, root) = temp_document("cursor_read_lock", r#"{"a": [1, 2]}"#);
+        let cursor = db.cursor(&root).unwrap();
+        let a = cursor.child("a").unwrap().unwrap();
+        drop(cursor);
+        std::thread::scope(|s| {
+            let writer = s.spawn(|| db.array_push(&root, "$.a", &mut b"3".to_vec()).unwrap());
+            std::thread::sleep(Duration::from_millis(100));
+            // 从游标得到的游标仍然持有读锁，写操作等它释放，之前读到的子树不会变
+            assert!(!writer.is_finished());
+            assert_eq!(a.children().unwrap().len(), 2);
+            drop(a);
+            writer.join().unwrap();
+        });
+        assert_eq!(read_json(&db, &root), json(r#"{"a": [1, 2, 3]}"#));
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use simd_json::{Node, StaticNode};
+
+use crate::json::{self, TapeValue};
+use crate::kv::{Key, KeyIndex, NodeValue};
+use crate::{DBError, Database};
+
+use super::{parse, Editor};
+
+impl Database {
+    /// 将 RFC 7396 JSON Merge Patch 合并到 `root` 文档中 `path` 指向的节点上
+    ///
+    /// object 成员递归合并到已有的 `KeyIndex::Field` 子节点上，值为 `null` 的成员会被删除，
+    /// 非 object 的值直接替换原节点。所有修改在一个 batch 中原子提交。
+    /// patch 中重复的成员名只合并一次，保留第一次出现的位置和最后一次出现的值。
+    ///
+    /// # 参数
+    /// * `root` - 编码后的 root key
+    /// * `path` - JSONPath 字符串，如 "$" 或 "$.user.profile"，目标节点必须存在
+    /// * `patch` - merge patch 文档
+    pub fn merge_patch(&self, root: &[u8], path: &str, patch: &mut [u8]) -> Result<(), DBError> {
+        let segments = parse(path)?;
+        let patch = simd_json::to_tape(patch).map_err(|_| DBError::DatabaseJsonError)?;
+        let _lock = self.locks.write(root);
+        let mut editor = Editor::new(self);
+        let root_key = editor.root_key(root)?;
+        let (key, value) = editor.resolve(&root_key, &segments)?;
+        merge(&mut editor, key, Some(value), &patch.0)?;
+        editor.commit()?;
+        Ok(())
+    }
+}
+
+fn merge(
+    editor: &mut Editor,
+    key: Key,
+    current: Option<NodeValue>,
+    patch: TapeValue,
+) -> Result<(), DBError> {
+    let members = match json::members(patch) {
+        Some(members) => members,
+        None => {
+            if current.is_some() {
+                editor.remove_subtree(&key)?;
+            }
+            editor.write_value(key, patch);
+            return Ok(());
+        }
+    };
+
+    // 原节点不是 object 时，先替换为空 object 再合并
+    let mut children = HashMap::new();
+    match current {
+        Some(value) if value.is_object() => {
+            for (k, v) in editor.children(&key)? {
+                if let KeyIndex::Field(name) = &k.field_key {
+                    children.insert(name.clone(), (k, v));
+                }
+            }
+        }
+        Some(_) => {
+            editor.remove_subtree(&key)?;
+            editor.staged.insert(&key, &NodeValue::Object);
+        }
+        None => editor.staged.insert(&key, &NodeValue::Object),
+    }
+
+    for (name, value) in members {
+        let name = Bytes::copy_from_slice(name.as_bytes());
+        let existing = children.remove(&name);
+        if let [Node::Static(StaticNode::Null)] = value {
+            if let Some((child, _)) = existing {
+                editor.remove_subtree(&child)?;
+            }
+            continue;
+        }
+        let (child, current) = match existing {
+            Some((child, current)) => (child, Some(current)),
+            None => (editor.sub_key(&key, KeyIndex::Field(name)), None),
+        };
+        merge(editor, child, current, value)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{parse, Editor};
+    use crate::kv::KeyIndex;
+    use crate::test_util::{json, read_json, temp_document};
+    use crate::{DBError, Database};
+
+    fn member_names(db: &Database, root: &[u8], path: &str) -> Vec<String> {
+        let editor = Editor::new(db);
+        let root = editor.root_key(root).unwrap();
+        let (key, _) = editor.resolve(&root, &parse(path).unwrap()).unwrap();
+        let mut names: Vec<String> = editor
+            .children(&key)
+            .unwrap()
+            .into_iter()
+            .filter_map(|(k, _)| match k.field_key {
+                KeyIndex::Field(name) => Some(String::from_utf8(name.to_vec()).unwrap()),
+                _ => None,
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let (db, root) = temp_document(
+            "merge_patch",
+            r#"{"title": "Goodbye!", "author": {"givenName": "John", "familyName": "Doe"}, "tags": ["example", "sample"], "content": "text"}"#,
+        );
+        let mut patch = br#"{"title": "Hello!", "phoneNumber": "+01-123-456-7890", "author": {"familyName": null}, "tags": ["example"]}"#.to_vec();
+        db.merge_patch(&root, "$", &mut patch).unwrap();
+        assert_eq!(
+            read_json(&db, &root),
+            json(
+                r#"{"title": "Hello!", "author": {"givenName": "John"}, "tags": ["example"], "content": "text", "phoneNumber": "+01-123-456-7890"}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_merge_patch_at_path() {
+        let (db, root) = temp_document("merge_patch_path", r#"{"a": {"b": 1, "c": [1]}, "d": 2}"#);
+        let mut patch = br#"{"c": {"x": null, "y": {"z": 1}}, "e": null}"#.to_vec();
+        db.merge_patch(&root, "$.a", &mut patch).unwrap();
+        assert_eq!(
+            read_json(&db, &root),
+            json(r#"{"a": {"b": 1, "c": {"y": {"z": 1}}}, "d": 2}"#)
+        );
+
+        let mut scalar = b"3".to_vec();
+        db.merge_patch(&root, "$.a", &mut scalar).unwrap();
+        assert_eq!(read_json(&db, &root), json(r#"{"a": 3, "d": 2}"#));
+    }
+
+    #[test]
+    fn test_merge_patch_duplicate_members() {
+        // 重复的成员名合并到同一个成员上，保留第一次出现的位置和最后一次出现的值
+        let (db, root) = temp_document("merge_patch_duplicates", r#"{"a": 1, "b": {"x": 1}}"#);
+        let mut patch =
+            br#"{"c": 1, "b": {"x": 2, "y": 3, "x": 4}, "a": null, "a": 5, "c": {"w": 3}}"#
+                .to_vec();
+        db.merge_patch(&root, "$", &mut patch).unwrap();
+        assert_eq!(member_names(&db, &root, "$"), ["a", "b", "c"]);
+        assert_eq!(member_names(&db, &root, "$.b"), ["x", "y"]);
+        let expected = r#"{"a": 5, "b": {"x": 4, "y": 3}, "c": {"w": 3}}"#;
+        assert_eq!(read_json(&db, &root), json(expected));
+    }
+
+    #[test]
+    fn test_merge_patch_missing_path() {
+        let (db, root) = temp_document("merge_patch_missing", r#"{"a": 1}"#);
+        let mut patch = br#"{"b": 1}"#.to_vec();
+        assert!(matches!(
+            db.merge_patch(&root, "$.x", &mut patch),
+            Err(DBError::PathNotFound(_))
+        ));
+    }
+}
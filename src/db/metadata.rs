@@ -1,81 +1,86 @@
-use std::collections::HashSet;
-
-use crate::kv::{read_variable_sized_id, EncodeError, VariableSizedId};
+use crate::kv::EncodeError;
 use anyhow::Result;
 
-#[derive(Debug, Clone)]
+/// 当前的存储格式版本，key、节点值或 metadata 的编码改变时加一，并在 `migration` 中登记升级步骤
+pub const FORMAT_VERSION: u64 = 6;
+
+/// root 注册表的 key 前缀，每个 root 一个条目：`ROOT_PREFIX + 编码后的 root key`，值为空
+///
+/// 节点 key 的首字节是第一个 id 编码的长度，不会是 0x00，所以这个前缀不会和节点冲突。
+pub(crate) const ROOT_PREFIX: &[u8] = b"\x00roots\x00";
+
+/// root 在注册表中的 key
+pub(crate) fn root_entry_key(root: &[u8]) -> Vec<u8> {
+    let mut key = ROOT_PREFIX.to_vec();
+    key.extend_from_slice(root);
+    key
+}
+
+/// 保存在 `METADAT_KEY` 下的计数器，大小固定，和文档数量无关
+#[derive(Debug, Clone, PartialEq)]
 pub struct Metadata {
     pub version: u64,
+    /// 已经预留到的最大节点 id，见 `IdAllocator`
     pub last_id: u64,
     pub last_timestamp: u64,
-    pub roots: HashSet<Vec<u8>>,
+    /// 已注册的 root 个数，root 本身保存在 `ROOT_PREFIX` 下
+    pub root_count: u64,
 }
 
 impl Metadata {
     pub fn new() -> Self {
         Self {
-            version: 0,
+            version: FORMAT_VERSION,
             last_id: 0,
             last_timestamp: 0,
-            roots: HashSet::new(),
+            root_count: 0,
         }
     }
 
     pub fn encode(&self) -> Vec<u8> {
-        // 1. 先计算roots的长度
-        let roots_len = self.roots.iter().fold(0, |acc, id| acc + id.len() + 1);
-        // 2. 创建一个Vec<u8>，预分配足够的空间
-        let mut buf = Vec::with_capacity(8 * 3 + roots_len);
-        // 3. 写入version
+        let mut buf = Vec::with_capacity(8 * 4);
         buf.extend_from_slice(&self.version.to_be_bytes());
-        // 4. 写入last_id
         buf.extend_from_slice(&self.last_id.to_be_bytes());
-        // 5. 写入last_timestamp
         buf.extend_from_slice(&self.last_timestamp.to_be_bytes());
-        // 6. 遍历写入roots
-        for id in &self.roots {
-            // 先写入长度，然后写入数据
-            // 这里限制每个root的key占用的字节数不能超过256
-            let len = id.len() as u8;
-            buf.extend_from_slice(&[len]);
-            buf.extend_from_slice(id);
-        }
+        buf.extend_from_slice(&self.root_count.to_be_bytes());
         buf
     }
 
+    /// 解码 metadata，版本 2 之前的 `root_count` 为 0，由升级步骤从旧的 roots 列表中计算
     pub fn decode(buf: &[u8]) -> Result<Self, EncodeError> {
-        if buf.len() < 24 {
-            return Err(EncodeError::InvalidLength);
-        }
-        // 1. 读取version
-        let version = u64::from_be_bytes([
-            buf[0], buf[1], buf[2], buf[3], buf[4], buf[5], buf[6], buf[7],
-        ]);
-        // 2. 读取last_id
-        let last_id = u64::from_be_bytes([
-            buf[8], buf[9], buf[10], buf[11], buf[12], buf[13], buf[14], buf[15],
-        ]);
-        // 3. 读取last_timestamp
-        let last_timestamp = u64::from_be_bytes([
-            buf[16], buf[17], buf[18], buf[19], buf[20], buf[21], buf[22], buf[23],
-        ]);
-        // 4. 读取roots
-        let mut roots = HashSet::new();
-        let mut offset = 24;
-        while offset < buf.len() {
-            let len = buf[offset];
-            if offset + len as usize >= buf.len() {
-                return Err(EncodeError::Overflow);
-            }
-            let id = buf[(offset + 1)..(offset + 1 + len as usize)].to_vec();
-            roots.insert(id);
-            offset += 1 + len as usize
-        }
+        let field = |i: usize| -> Result<u64, EncodeError> {
+            let bytes = buf
+                .get(i * 8..(i + 1) * 8)
+                .ok_or(EncodeError::InvalidLength)?;
+            Ok(u64::from_be_bytes(bytes.try_into().unwrap()))
+        };
+        let version = field(0)?;
+        let last_id = field(1)?;
+        let last_timestamp = field(2)?;
+        let root_count = if version < 2 { 0 } else { field(3)? };
         Ok(Self {
             version,
             last_id,
             last_timestamp,
-            roots,
+            root_count,
         })
     }
 }
+
+/// 版本 2 之前 metadata 中直接保存的 roots 列表：24 字节的计数器之后，每个 root 一字节长度加内容
+pub(crate) fn decode_legacy_roots(buf: &[u8]) -> Result<Vec<Vec<u8>>, EncodeError> {
+    if buf.len() < 24 {
+        return Err(EncodeError::InvalidLength);
+    }
+    let mut roots = Vec::new();
+    let mut offset = 24;
+    while offset < buf.len() {
+        let len = buf[offset] as usize;
+        if offset + len >= buf.len() {
+            return Err(EncodeError::Overflow);
+        }
+        roots.push(buf[(offset + 1)..(offset + 1 + len)].to_vec());
+        offset += 1 + len
+    }
+    Ok(roots)
+}
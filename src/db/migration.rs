@@ -0,0 +1,740 @@
+use bytes::Bytes;
+
+use crate::kv::{
+    read_ordered_id, read_variable_sized_id, EncodeError, Key, KeyIndex, NodeValue,
+    VariableSizedId, FIELD_INDEX_PREFIX,
+};
+use crate::{DBError, Database, METADAT_KEY};
+
+use super::writer::{rewrite_checkpoint, CHECKPOINT_PREFIX};
+use super::{decode_legacy_roots, root_entry_key, FORMAT_VERSION, ROOT_PREFIX};
+
+/// 打开旧版本数据库时的升级进度
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrationProgress {
+    /// 开始把数据库从 `from` 版本升级到 `from + 1`
+    Started {
+        from: u64,
+        description: &'static str,
+    },
+    /// 当前步骤已经处理的 key 数
+    Processed { from: u64, keys: u64 },
+    /// 升级到 `from + 1` 完成，新版本号已经写入
+    Finished { from: u64 },
+}
+
+/// 把数据库从 `from` 版本升级到 `from + 1` 的一个步骤
+///
+/// `run` 在原地改写数据，可以通过回调报告已处理的 key 数。步骤完成后才写入新的版本号，
+/// 所以中途失败时下次打开会重新执行该步骤，步骤本身需要能够重复执行。
+struct Migration {
+    from: u64,
+    description: &'static str,
+    run: MigrationFn,
+}
+
+type MigrationFn = fn(&mut Database, &mut dyn FnMut(u64)) -> Result<(), DBError>;
+
+/// 所有升级步骤，按 `from` 递增，覆盖 0 到 `FORMAT_VERSION - 1`
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 0,
+        description: "renumber integer value tags",
+        run: migrate_value_tags,
+    },
+    Migration {
+        from: 1,
+        description: "move roots into the root registry",
+        run: migrate_root_registry,
+    },
+    Migration {
+        from: 2,
+        description: "re-encode key ids in sort order and index array elements",
+        run: migrate_ordered_ids,
+    },
+    Migration {
+        from: 3,
+        description: "escape binary field names in keys",
+        run: migrate_escaped_fields,
+    },
+    Migration {
+        from: 4,
+        description: "build the field-name index",
+        run: migrate_field_index,
+    },
+    Migration {
+        from: 5,
+        description: "allow packed subtree values",
+        // 版本 5 的数据中没有 packed 值，升级版本号只是为了让旧程序拒绝打开新写入的数据
+        run: |_, _| Ok(()),
+    },
+];
+
+/// 每处理多少个 key 报告一次进度
+const PROGRESS_INTERVAL: u64 = 1024;
+
+/// 把版本 0 写入的整数值改为现在的类型编号
+///
+/// 版本 0 把 i64 写为 3、u64 写为 4，和字符串、数组的编号重复，读出来的整数会变成字符串或数组。
+/// 4 之后跟着 8 个字节的只能是 u64；3 之后跟着 8 个字节、且不是合法 UTF-8 的是 i64。
+/// 版本 0 只为负数写入 i64，大端序的首字节不小于 0x80，除了极少数恰好是合法 UTF-8 的值以外
+/// 都能和字符串区分开。改写后的值不再匹配这两条规则，中途失败时可以重复执行。
+fn migrate_value_tags(db: &mut Database, progress: &mut dyn FnMut(u64)) -> Result<(), DBError> {
+    let mut batch = sled::Batch::default();
+    let mut done = 0u64;
+    for kv in db.store.tree.iter() {
+        let (key, value) = kv?;
+        // metadata 和 0x00 开头的 root 条目、检查点都不是节点
+        if key == METADAT_KEY || key.first() == Some(&0) {
+            continue;
+        }
+        if let Some(value) = retag_v0_value(&value) {
+            batch.insert(key, value.as_ref());
+        }
+        done += 1;
+        if done.is_multiple_of(PROGRESS_INTERVAL) {
+            db.store.tree.apply_batch(std::mem::take(&mut batch))?;
+            progress(done);
+        }
+    }
+    db.store.tree.apply_batch(batch)?;
+    progress(done);
+    Ok(())
+}
+
+/// 版本 0 的整数值按现在的编号重新编码，其他值返回 None
+fn retag_v0_value(value: &[u8]) -> Option<Bytes> {
+    match value {
+        [4, number @ ..] => {
+            Some(NodeValue::NumberU(u64::from_be_bytes(number.try_into().ok()?)).encode())
+        }
+        [3, number @ ..] if std::str::from_utf8(number).is_err() => {
+            Some(NodeValue::NumberI(i64::from_be_bytes(number.try_into().ok()?)).encode())
+        }
+        _ => None,
+    }
+}
+
+/// 把 metadata 中的 roots 列表拆成 `ROOT_PREFIX` 下的独立条目
+///
+/// 旧的 metadata 在最后一个 batch 中才替换为新格式，中途失败时重复执行会得到相同的结果。
+fn migrate_root_registry(db: &mut Database, progress: &mut dyn FnMut(u64)) -> Result<(), DBError> {
+    let raw = db
+        .store
+        .get_raw(METADAT_KEY)?
+        .ok_or(EncodeError::InvalidLength)?;
+    let roots = decode_legacy_roots(&raw)?;
+    let mut batch = sled::Batch::default();
+    for (i, root) in roots.iter().enumerate() {
+        batch.insert(root_entry_key(root), &[]);
+        let done = i as u64 + 1;
+        if done.is_multiple_of(PROGRESS_INTERVAL) {
+            db.store.tree.apply_batch(std::mem::take(&mut batch))?;
+            progress(done);
+        }
+    }
+    db.metadata.root_count = roots.len() as u64;
+    let mut metadata = db.metadata.clone();
+    metadata.version = 2;
+    batch.insert(METADAT_KEY, metadata.encode());
+    db.store.tree.apply_batch(batch)?;
+    progress(roots.len() as u64);
+    Ok(())
+}
+
+/// 改写 key 的编码时暂存改写后键值的 tree
+const REENCODE_TREE: &[u8] = b"~~MIGRATION_REENCODE~~";
+/// 改写后的键值全部写入暂存 tree 之后写入的标记，节点 key 和保留 key 都不会以 0xFF 开头
+const REENCODE_DONE: &[u8] = b"\xffdone";
+
+/// 旧版本 key 的解码方式和下一个版本的编码方式
+struct KeyCodec {
+    decode: fn(&[u8]) -> Result<Key, EncodeError>,
+    encode: fn(&Key) -> Vec<u8>,
+}
+
+impl KeyCodec {
+    fn rewrite(&self, key: &[u8]) -> Result<Vec<u8>, EncodeError> {
+        Ok((self.encode)(&(self.decode)(key)?))
+    }
+}
+
+/// 把所有 key 中的 id 从 `id + 1` 的小端 varint 改为 `write_ordered_id` 的保序编码
+fn migrate_ordered_ids(db: &mut Database, progress: &mut dyn FnMut(u64)) -> Result<(), DBError> {
+    let codec = KeyCodec {
+        decode: |key| decode_legacy_key(key, read_v2_id, read_variable_sized_index),
+        encode: encode_v3_key,
+    };
+    rewrite_keys(db, 3, progress, codec)
+}
+
+/// 把 field 名从 key 末尾的原始字节改为 `write_field` 的转义格式
+fn migrate_escaped_fields(db: &mut Database, progress: &mut dyn FnMut(u64)) -> Result<(), DBError> {
+    let codec = KeyCodec {
+        decode: |key| decode_legacy_key(key, read_ordered_id, read_ordered_id),
+        encode: Key::encode,
+    };
+    rewrite_keys(db, 4, progress, codec)
+}
+
+/// 为所有 object 成员写入字段名索引
+///
+/// 先删除上次中途失败时留下的索引条目再重新写入。旧版本可能存了重名的成员，之前按名字查找
+/// 得到的是 id 最小的那个，这里倒序扫描，让它的索引条目最后写入。
+fn migrate_field_index(db: &mut Database, progress: &mut dyn FnMut(u64)) -> Result<(), DBError> {
+    clear_prefix(db, FIELD_INDEX_PREFIX)?;
+    let mut batch = sled::Batch::default();
+    let mut done = 0u64;
+    for kv in db.store.tree.iter().rev() {
+        let (key, _) = kv?;
+        if key == METADAT_KEY || key.first() == Some(&0) {
+            continue;
+        }
+        if let Some((index, id)) = Key::decode(&key)?.field_index_entry() {
+            batch.insert(index, id);
+        }
+        done += 1;
+        if done.is_multiple_of(PROGRESS_INTERVAL) {
+            db.store.tree.apply_batch(std::mem::take(&mut batch))?;
+            progress(done);
+        }
+    }
+    let mut metadata = db.stored_metadata()?;
+    metadata.version = 5;
+    batch.insert(METADAT_KEY, metadata.encode());
+    db.store.tree.apply_batch(batch)?;
+    progress(done);
+    Ok(())
+}
+
+/// 删除所有以 `prefix` 开头的 key
+fn clear_prefix(db: &Database, prefix: &[u8]) -> Result<(), DBError> {
+    let mut batch = sled::Batch::default();
+    for (i, key) in db.store.tree.scan_prefix(prefix).keys().enumerate() {
+        batch.remove(key?);
+        if (i as u64 + 1).is_multiple_of(PROGRESS_INTERVAL) {
+            db.store.tree.apply_batch(std::mem::take(&mut batch))?;
+        }
+    }
+    db.store.tree.apply_batch(batch)?;
+    Ok(())
+}
+
+/// 用 `codec` 改写所有 key，同时为所有数组元素写入下标索引（见 `element_index_key`），
+/// 完成后把版本号设为 `version`
+///
+/// 新旧两种编码无法区分，不能原地逐个改写。先把改写后的全部键值写入暂存 tree 并打上完成标记，
+/// 再删除主 tree 中除 metadata 以外的所有 key，从暂存 tree 复制回来，最后一个 batch 同时写入
+/// 新的版本号。复制阶段中途失败时，下次根据完成标记直接重新删除和复制。
+/// 每个 key 在写入暂存 tree 和复制回来时各报告一次进度。
+fn rewrite_keys(
+    db: &mut Database,
+    version: u64,
+    progress: &mut dyn FnMut(u64),
+    codec: KeyCodec,
+) -> Result<(), DBError> {
+    let scratch = db.store.tree.open_tree(REENCODE_TREE)?;
+    let mut done = 0u64;
+    if scratch.get(REENCODE_DONE)?.is_none() {
+        scratch.clear()?;
+        let mut batch = sled::Batch::default();
+        for kv in db.store.tree.iter() {
+            let (key, value) = kv?;
+            if key == METADAT_KEY {
+                continue;
+            }
+            if key.first() != Some(&0) {
+                // 下标索引只包含 id 和下标，从旧 key 解码得到的条目和新版本写入的相同
+                if let Some((index, id)) = (codec.decode)(&key)?.element_index_entry() {
+                    batch.insert(index, id);
+                }
+            }
+            let (key, value) = reencode(&key, &value, &codec)?;
+            batch.insert(key, value);
+            done += 1;
+            if done.is_multiple_of(PROGRESS_INTERVAL) {
+                scratch.apply_batch(std::mem::take(&mut batch))?;
+                progress(done);
+            }
+        }
+        batch.insert(REENCODE_DONE, &[]);
+        scratch.apply_batch(batch)?;
+        scratch.flush()?;
+    }
+
+    let mut batch = sled::Batch::default();
+    for (i, key) in db.store.tree.iter().keys().enumerate() {
+        let key = key?;
+        if key != METADAT_KEY {
+            batch.remove(key);
+        }
+        if (i as u64 + 1).is_multiple_of(PROGRESS_INTERVAL) {
+            db.store.tree.apply_batch(std::mem::take(&mut batch))?;
+        }
+    }
+    db.store.tree.apply_batch(std::mem::take(&mut batch))?;
+    for kv in scratch.iter() {
+        let (key, value) = kv?;
+        if key == REENCODE_DONE {
+            continue;
+        }
+        batch.insert(key, value);
+        done += 1;
+        if done.is_multiple_of(PROGRESS_INTERVAL) {
+            db.store.tree.apply_batch(std::mem::take(&mut batch))?;
+            progress(done);
+        }
+    }
+    let mut metadata = db.stored_metadata()?;
+    metadata.version = version;
+    batch.insert(METADAT_KEY, metadata.encode());
+    db.store.tree.apply_batch(batch)?;
+    db.store.tree.flush()?;
+    db.store.tree.drop_tree(REENCODE_TREE)?;
+    progress(done);
+    Ok(())
+}
+
+/// 用 `codec` 改写一个键值，root 注册表和导入检查点中嵌入的 key 一并改写
+fn reencode(key: &[u8], value: &[u8], codec: &KeyCodec) -> Result<(Vec<u8>, Vec<u8>), DBError> {
+    let prefixed = |prefix: &[u8], rest: &[u8]| -> Result<Vec<u8>, EncodeError> {
+        let mut key = prefix.to_vec();
+        key.extend_from_slice(&codec.rewrite(rest)?);
+        Ok(key)
+    };
+    if let Some(root) = key.strip_prefix(ROOT_PREFIX) {
+        Ok((prefixed(ROOT_PREFIX, root)?, value.to_vec()))
+    } else if let Some(root) = key.strip_prefix(CHECKPOINT_PREFIX) {
+        let value = rewrite_checkpoint(value, |k| codec.rewrite(k).ok())
+            .ok_or_else(|| DBError::InvalidWriterState("corrupt checkpoint".to_string()))?;
+        Ok((prefixed(CHECKPOINT_PREFIX, root)?, value))
+    } else if key.first() == Some(&0) {
+        Ok((key.to_vec(), value.to_vec()))
+    } else {
+        Ok((codec.rewrite(key)?, value.to_vec()))
+    }
+}
+
+/// 读取一个 id 或数组下标，返回 (值, 消费了多少字节)
+type ReadId = fn(&[u8]) -> Result<(u64, usize), EncodeError>;
+
+/// 解码版本 4 之前的 key：field 名是 0x01 之后直到 key 末尾的原始字节
+///
+/// 版本 3 之前 id 和数组下标是小端 varint，id 额外加一；版本 3 起两者都是保序编码。
+fn decode_legacy_key(
+    bytes: &[u8],
+    read_id: ReadId,
+    read_index: ReadId,
+) -> Result<Key, EncodeError> {
+    let mut ids = Vec::new();
+    let mut rest = bytes;
+    loop {
+        match rest.first() {
+            None => return Err(EncodeError::InvalidLength),
+            Some(0) => {
+                rest = &rest[1..];
+                break;
+            }
+            Some(_) => {
+                let (id, consumed) = read_id(rest)?;
+                ids.push(VariableSizedId::new(id));
+                rest = &rest[consumed..];
+            }
+        }
+    }
+    let field_key = match rest.split_first() {
+        Some((0x01, name)) => KeyIndex::Field(Bytes::copy_from_slice(name)),
+        Some((0x02, index)) => KeyIndex::Id(VariableSizedId::new(read_index(index)?.0)),
+        _ => KeyIndex::decode(rest)?,
+    };
+    Ok(Key { ids, field_key })
+}
+
+fn read_v2_id(data: &[u8]) -> Result<(u64, usize), EncodeError> {
+    let (id, consumed) = read_variable_sized_index(data)?;
+    let id = id.checked_sub(1).ok_or(EncodeError::InvalidLength)?;
+    Ok((id, consumed))
+}
+
+fn read_variable_sized_index(data: &[u8]) -> Result<(u64, usize), EncodeError> {
+    let (id, consumed) = read_variable_sized_id(data)?;
+    Ok((id.to_u64()?, consumed))
+}
+
+/// 版本 3 的 key：id 已经是保序编码，field 名还是原始字节
+fn encode_v3_key(key: &Key) -> Vec<u8> {
+    let mut buf = key.id_prefix();
+    buf.push(0x00);
+    match &key.field_key {
+        KeyIndex::Field(name) => {
+            buf.push(0x01);
+            buf.extend_from_slice(name);
+        }
+        index => buf.extend(index.encode()),
+    }
+    buf
+}
+
+impl Database {
+    /// 检查 metadata 中的版本号，依次执行升级步骤直到 `FORMAT_VERSION`
+    ///
+    /// 版本号比当前程序支持的更新时返回 `DBError::UnsupportedVersion`，不会改动任何数据。
+    pub(crate) fn migrate(
+        &mut self,
+        progress: &mut dyn FnMut(&MigrationProgress),
+    ) -> Result<(), DBError> {
+        if self.metadata.version > FORMAT_VERSION {
+            return Err(DBError::UnsupportedVersion {
+                found: self.metadata.version,
+                supported: FORMAT_VERSION,
+            });
+        }
+        while self.metadata.version < FORMAT_VERSION {
+            let from = self.metadata.version;
+            let Some(step) = MIGRATIONS.iter().find(|m| m.from == from) else {
+                return Err(DBError::UnsupportedVersion {
+                    found: from,
+                    supported: FORMAT_VERSION,
+                });
+            };
+            progress(&MigrationProgress::Started {
+                from,
+                description: step.description,
+            });
+            (step.run)(self, &mut |keys| {
+                progress(&MigrationProgress::Processed { from, keys })
+            })?;
+            // 版本号总是 metadata 的前 8 个字节，其余部分的格式变化由升级步骤自己改写
+            self.metadata.version = from + 1;
+            let mut raw = self
+                .store
+                .get_raw(METADAT_KEY)?
+                .ok_or(EncodeError::InvalidLength)?
+                .to_vec();
+            raw.get_mut(..8)
+                .ok_or(EncodeError::InvalidLength)?
+                .copy_from_slice(&self.metadata.version.to_be_bytes());
+            self.store.set_raw(METADAT_KEY, &raw)?;
+            self.store.tree.flush()?;
+            progress(&MigrationProgress::Finished { from });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_v3_key, MigrationProgress, MIGRATIONS, REENCODE_TREE};
+    use crate::db::{
+        element_index, parse, root_entry_key, Editor, Metadata, FORMAT_VERSION, ROOT_PREFIX,
+    };
+    use crate::kv::{
+        Key, KeyIndex, NodeValue, VariableSizedId, ELEMENT_INDEX_PREFIX, FIELD_INDEX_PREFIX,
+    };
+    use crate::test_util::{json, read_json, root_key, temp_database, temp_document};
+    use crate::{DBError, Database, METADAT_KEY};
+
+    #[test]
+    fn test_migrations_cover_all_versions() {
+        let froms: Vec<u64> = MIGRATIONS.iter().map(|m| m.from).collect();
+        assert_eq!(froms, (0..FORMAT_VERSION).collect::<Vec<_>>());
+    }
+
+    /// 最初版本的 `insert_json` 写入的全部键值：root 0 是 `BASELINE_DOC`，root 7 是
+    /// `BASELINE_LIST`。整数的类型编号和字符串、数组重复。
+    const BASELINE_DB: &[(&[u8], &[u8])] = &[
+        (b"\x01\x00\x03", b"\x05"),
+        (b"\x01\x02\x00\x01s", b"\x03hello"),
+        (
+            b"\x01\x03\x00\x01n",
+            b"\x04\x00\x00\x00\x00\x00\x00\x00\x2a",
+        ),
+        (
+            b"\x01\x04\x00\x01neg",
+            b"\x03\xff\xff\xff\xff\xff\xff\xff\xf9",
+        ),
+        (
+            b"\x01\x05\x00\x01f",
+            b"\x02\x3f\xf8\x00\x00\x00\x00\x00\x00",
+        ),
+        (b"\x01\x06\x00\x01t", b"\x01\x01"),
+        (b"\x01\x07\x00\x01z", b"\x00"),
+        (b"\x01\x08\x00\x01list", b"\x04"),
+        (
+            b"\x01\x08\x0b\x00\x02\x00",
+            b"\x04\x00\x00\x00\x00\x00\x00\x00\x01",
+        ),
+        (b"\x01\x08\x0c\x00\x02\x01", b"\x03two"),
+        (b"\x01\x08\x0d\x00\x02\x02", b"\x04"),
+        (
+            b"\x01\x08\x0d\x0e\x00\x02\x00",
+            b"\x04\x00\x00\x00\x00\x00\x00\x00\x03",
+        ),
+        (b"\x01\x09\x00\x01o", b"\x05"),
+        (b"\x01\x09\x0a\x00\x01k", b"\x03v"),
+        (b"\x08\x00\x03", b"\x04"),
+        (
+            b"\x08\x0f\x00\x02\x00",
+            b"\x03\xff\xff\xff\xff\xff\xff\xff\xff",
+        ),
+        (
+            b"\x08\x10\x00\x02\x01",
+            b"\x04\xff\xff\xff\xff\xff\xff\xff\xff",
+        ),
+        (b"\x08\x11\x00\x02\x02", b"\x0312345678"),
+        (
+            b"~~METADATA~~",
+            b"\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x10\
+              \x00\x00\x00\x00\x00\x00\x00\x00\x03\x01\x00\x03\x03\x08\x00\x03",
+        ),
+    ];
+    const BASELINE_DOC: &str = r#"{"s": "hello", "n": 42, "neg": -7, "f": 1.5, "t": true,
+        "z": null, "list": [1, "two", [3]], "o": {"k": "v"}}"#;
+    const BASELINE_LIST: &str = r#"[-1, 18446744073709551615, "12345678"]"#;
+
+    /// 用 `BASELINE_DB` 替换数据库中的全部数据
+    fn load_baseline(db: &mut Database) {
+        db.store.tree.clear().unwrap();
+        for (key, value) in BASELINE_DB {
+            db.store.set_raw(key, value).unwrap();
+        }
+        db.metadata = Metadata::decode(&db.store.get_raw(METADAT_KEY).unwrap().unwrap()).unwrap();
+    }
+
+    /// 版本 3 之前的 key：id 是 `id + 1` 的小端 varint，数组下标是 varint，field 名是原始字节
+    fn v2_key(key: &[u8]) -> Vec<u8> {
+        let key = Key::decode(key).unwrap();
+        let varint = |mut v: u64| {
+            let mut buf = Vec::new();
+            while v >= 0x80 {
+                buf.push(v as u8 | 0x80);
+                v >>= 7;
+            }
+            buf.push(v as u8);
+            buf
+        };
+        let mut buf = Vec::new();
+        for id in &key.ids {
+            buf.extend(varint(id.to_u64().unwrap() + 1));
+        }
+        buf.push(0x00);
+        match &key.field_key {
+            KeyIndex::Id(index) => {
+                buf.push(0x02);
+                buf.extend(varint(index.to_u64().unwrap()));
+            }
+            KeyIndex::Field(name) => {
+                buf.push(0x01);
+                buf.extend_from_slice(name);
+            }
+            KeyIndex::Root => buf.push(0x03),
+        }
+        buf
+    }
+
+    fn v3_key(key: &[u8]) -> Vec<u8> {
+        encode_v3_key(&Key::decode(key).unwrap())
+    }
+
+    /// 删除版本 `version` 中还没有的索引（字段名索引从版本 5 开始，下标索引从版本 3 开始），
+    /// 并把版本号设为 `version`
+    fn drop_indexes(db: &mut Database, version: u64) {
+        let indexes = [(5, FIELD_INDEX_PREFIX), (3, ELEMENT_INDEX_PREFIX)];
+        for (since, prefix) in indexes {
+            if version >= since {
+                continue;
+            }
+            for key in db.store.tree.scan_prefix(prefix).keys() {
+                db.store.tree.remove(key.unwrap()).unwrap();
+            }
+        }
+        let mut raw = db.store.get_raw(METADAT_KEY).unwrap().unwrap().to_vec();
+        raw[..8].copy_from_slice(&version.to_be_bytes());
+        db.store.set_raw(METADAT_KEY, &raw).unwrap();
+        db.metadata.version = version;
+    }
+
+    /// 用 `legacy_key` 把节点 key 和 root 注册表改写为旧版本的编码，并把版本号设为 `version`
+    fn downgrade_keys(db: &mut Database, version: u64, legacy_key: fn(&[u8]) -> Vec<u8>) {
+        drop_indexes(db, version);
+        let entries: Vec<_> = db.store.tree.iter().collect::<Result<_, _>>().unwrap();
+        for (key, value) in entries {
+            let legacy = if let Some(root) = key.strip_prefix(ROOT_PREFIX) {
+                root_entry_key(&legacy_key(root))
+            } else if key.first() == Some(&0x00) || key == METADAT_KEY {
+                continue;
+            } else {
+                legacy_key(&key)
+            };
+            db.store.tree.remove(&key).unwrap();
+            db.store.tree.insert(legacy, value).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut db = temp_database("migrate");
+        load_baseline(&mut db);
+        assert_eq!(db.metadata.version, 0);
+
+        let mut events = Vec::new();
+        db.migrate(&mut |p| events.push(p.clone())).unwrap();
+        assert_eq!(db.metadata.version, FORMAT_VERSION);
+        assert_eq!(db.metadata.root_count, 2);
+        assert_eq!(
+            events.first(),
+            Some(&MigrationProgress::Started {
+                from: 0,
+                description: MIGRATIONS[0].description,
+            })
+        );
+        assert!(events.contains(&MigrationProgress::Processed { from: 1, keys: 2 }));
+        assert_eq!(
+            events.last(),
+            Some(&MigrationProgress::Finished {
+                from: FORMAT_VERSION - 1
+            })
+        );
+        let stored = Metadata::decode(&db.store.get_raw(METADAT_KEY).unwrap().unwrap()).unwrap();
+        assert_eq!(stored, db.metadata);
+        let (root, other) = (root_key(0), root_key(7));
+        assert!(db.has_root(&root).unwrap());
+        assert!(db.has_root(&other).unwrap());
+        assert_eq!(read_json(&db, &root), json(BASELINE_DOC));
+        // 8 个字节的字符串仍然是字符串
+        assert_eq!(read_json(&db, &other), json(BASELINE_LIST));
+
+        // 已经是最新版本时不再执行任何步骤
+        let mut events = Vec::new();
+        db.migrate(&mut |p| events.push(p.clone())).unwrap();
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_migrate_value_tags_twice() {
+        let mut db = temp_database("migrate_value_tags_twice");
+        load_baseline(&mut db);
+        // 第一步中途失败后重新执行，已经改写的值保持不变
+        (MIGRATIONS[0].run)(&mut db, &mut |_| {}).unwrap();
+        (MIGRATIONS[0].run)(&mut db, &mut |_| {}).unwrap();
+        let list = db.store.get_raw(b"\x08\x0f\x00\x02\x00").unwrap().unwrap();
+        assert_eq!(NodeValue::decode(&list).unwrap(), NodeValue::NumberI(-1));
+        db.migrate(&mut |_| {}).unwrap();
+        assert_eq!(read_json(&db, &root_key(0)), json(BASELINE_DOC));
+        assert_eq!(read_json(&db, &root_key(7)), json(BASELINE_LIST));
+    }
+
+    #[test]
+    fn test_migrate_ordered_ids() {
+        let list: Vec<u64> = (0..300).collect();
+        let doc = format!(
+            r#"{{"list": {:?}, "nested": {{"a": [{{"b": null}}]}}}}"#,
+            list
+        );
+        let (mut db, root) = temp_document("migrate_ordered_ids", &doc);
+        let other = root_key(200);
+        db.insert_json(&other, &mut br#"{"x": "y"}"#.to_vec())
+            .unwrap();
+        downgrade_keys(&mut db, 2, v2_key);
+
+        let mut events = Vec::new();
+        db.migrate(&mut |p| events.push(p.clone())).unwrap();
+        assert_eq!(
+            events.first(),
+            Some(&MigrationProgress::Started {
+                from: 2,
+                description: MIGRATIONS[2].description,
+            })
+        );
+        assert_eq!(db.stored_metadata().unwrap().version, FORMAT_VERSION);
+        assert_eq!(db.root_count().unwrap(), 2);
+        assert!(db.has_root(&other).unwrap());
+        assert_eq!(read_json(&db, &root), json(&doc));
+        assert_eq!(read_json(&db, &other), json(r#"{"x": "y"}"#));
+        assert!(!db
+            .store
+            .tree
+            .tree_names()
+            .iter()
+            .any(|n| n == REENCODE_TREE));
+        let indexed = db.store.tree.scan_prefix(ELEMENT_INDEX_PREFIX).count();
+        assert_eq!(indexed, 300 + 1);
+
+        // 新写入的数组，按 key 顺序扫描得到的元素就是下标顺序
+        let editor = Editor::new(&db);
+        let (list, _) = editor
+            .resolve(&editor.root_key(&root).unwrap(), &parse("$.list").unwrap())
+            .unwrap();
+        let indices: Vec<u64> = db
+            .store
+            .tree
+            .scan_prefix(list.id_prefix())
+            .keys()
+            .map(|k| Key::decode(&k.unwrap()).unwrap())
+            .filter(|k| k.ids.len() == list.ids.len() + 1)
+            .map(|k| element_index(&k).unwrap())
+            .collect();
+        assert_eq!(indices, (0..300).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_migrate_escaped_fields() {
+        let doc = r#"{"a\u0000b": {"": [1, {"c": "d"}]}, "é": null}"#;
+        let (mut db, root) = temp_document("migrate_escaped_fields", doc);
+        downgrade_keys(&mut db, 3, v3_key);
+        let mut events = Vec::new();
+        db.migrate(&mut |p| events.push(p.clone())).unwrap();
+        assert_eq!(
+            events.first(),
+            Some(&MigrationProgress::Started {
+                from: 3,
+                description: MIGRATIONS[3].description,
+            })
+        );
+        assert_eq!(db.stored_metadata().unwrap().version, FORMAT_VERSION);
+        assert_eq!(read_json(&db, &root), json(doc));
+    }
+
+    #[test]
+    fn test_migrate_field_index() {
+        let doc = r#"{"a": {"b": [{"c": 1}]}, "d": "e"}"#;
+        let (mut db, root) = temp_document("migrate_field_index", doc);
+        let indexed = |db: &Database| db.store.tree.scan_prefix(FIELD_INDEX_PREFIX).count();
+        assert_eq!(indexed(&db), 4);
+        let editor = Editor::new(&db);
+        let root_key = editor.root_key(&root).unwrap();
+        let (a, _) = editor.resolve(&root_key, &parse("$.a").unwrap()).unwrap();
+        // 旧版本可能留下的重名成员，id 更大，之前按名字查找不到它
+        let duplicate = a.sub_key(
+            VariableSizedId::new(db.ids.next()),
+            KeyIndex::Field("b".into()),
+        );
+        db.store
+            .set_raw(&duplicate.encode(), &NodeValue::Null.encode())
+            .unwrap();
+        drop_indexes(&mut db, 4);
+
+        db.migrate(&mut |_| {}).unwrap();
+        assert_eq!(db.stored_metadata().unwrap().version, FORMAT_VERSION);
+        assert_eq!(indexed(&db), 4);
+        let editor = Editor::new(&db);
+        let read = |path: &str| {
+            let (key, _) = editor.resolve(&root_key, &parse(path).unwrap()).unwrap();
+            editor.read_value(&key).unwrap()
+        };
+        assert_eq!(read("$.a.b[0].c"), json("1"));
+        assert_eq!(read("$.d"), json(r#""e""#));
+    }
+
+    #[test]
+    fn test_refuse_future_version() {
+        let (mut db, root) = temp_document("refuse_future_version", "[]");
+        db.metadata.version = FORMAT_VERSION + 1;
+        assert!(matches!(
+            db.migrate(&mut |_| panic!("no migration expected")),
+            Err(DBError::UnsupportedVersion { found, supported })
+                if found == FORMAT_VERSION + 1 && supported == FORMAT_VERSION
+        ));
+        assert_eq!(read_json(&db, &root), json("[]"));
+    }
+}
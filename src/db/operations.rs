@@ -1,16 +1,16 @@
-// 这些导入暂时未使用，为未来扩展保留
-// use crate::kv::{read_variable_sized_id, EncodeError, VariableSizedId};
+use std::fmt::Write;
+
 use jsonpath_rust::parser::parse_json_path;
 use thiserror::Error;
 
-use crate::{kv::{Key, VariableSizedId}, DBError, Database};
+use crate::{DBError, Database};
 
 /// JSONPath 路径段，表示路径中的一个访问操作
 /// 
 /// 目前只支持两种基本的访问模式：
 /// - `Key(String)`: 对象属性访问，如 `.name` 或 `["name"]`
 /// - `Index(usize)`: 数组索引访问，如 `[0]` 或 `[1]`
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum JsonPathSegment {
     /// 对象键访问，例如 $.user.name 中的 "user" 和 "name"
     Key(String),
@@ -19,7 +19,7 @@ pub enum JsonPathSegment {
 }
 
 /// JSONPath 解析错误类型
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 pub enum JsonPathParseError {
     #[error("JSONPath解析失败: {0}")]
     ParseFailed(String),
@@ -27,6 +27,8 @@ pub enum JsonPathParseError {
     UnsupportedSegmentType,
     #[error("不支持的选择器类型")]
     UnsupportedSelectorType,
+    #[error("无效的 JSON Pointer: {0}")]
+    InvalidPointer(String),
 }
 
 /// 解析 JSONPath 字符串为 JsonPathSegment 向量
@@ -60,6 +62,7 @@ pub enum JsonPathParseError {
 /// - 负数索引 (`$.users[-1]`)
 /// - 过滤器表达式 (`$.users[?(@.active)]`)
 /// - 切片操作 (`$.users[0:2]`)
+#[allow(clippy::if_same_then_else)]
 pub fn parse(path: &str) -> Result<Vec<JsonPathSegment>, JsonPathParseError> {
     let jp = parse_json_path(path)
         .map_err(|e| JsonPathParseError::ParseFailed(format!("{:?}", e)))?;
@@ -107,12 +110,57 @@ pub fn parse(path: &str) -> Result<Vec<JsonPathSegment>, JsonPathParseError> {
     Ok(segments)
 }
 
-/*
-TODO: 1. 创建根据db + root_key + Vec<JsonPathSegment> 创建 kv::node::Key 的方法 
- */
- pub fn json_path_key(db: &Database, root_key: &str, segments: &Vec<JsonPathSegment>) -> Result<Key, DBError> {
-    unimplemented!()
- }
+/// 解析 RFC 6901 JSON Pointer 为引用 token 序列
+///
+/// `""` 表示整个文档，其余必须以 `/` 开头；`~1` 还原为 `/`，`~0` 还原为 `~`。
+/// token 是对象键还是数组下标要到查找节点时才能确定，所以这里只返回字符串。
+pub fn parse_pointer(pointer: &str) -> Result<Vec<String>, JsonPathParseError> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    let rest = pointer
+        .strip_prefix('/')
+        .ok_or_else(|| JsonPathParseError::InvalidPointer(pointer.to_string()))?;
+    rest.split('/')
+        .map(|token| {
+            if token.replace("~0", "").replace("~1", "").contains('~') {
+                return Err(JsonPathParseError::InvalidPointer(pointer.to_string()));
+            }
+            Ok(token.replace("~1", "/").replace("~0", "~"))
+        })
+        .collect()
+}
+
+/// 将路径段格式化为 JSONPath 字符串，用于错误信息
+pub fn format_path(segments: &[JsonPathSegment]) -> String {
+    let mut path = String::from("$");
+    for segment in segments {
+        match segment {
+            JsonPathSegment::Key(key) => {
+                path.push('.');
+                path.push_str(key);
+            }
+            JsonPathSegment::Index(index) => {
+                let _ = write!(path, "[{}]", index);
+            }
+        }
+    }
+    path
+}
+
+/// 根据 root key 和路径段查找节点，返回节点编码后的 key
+///
+/// 对象键只能作用在 object 节点上，数组下标只能作用在 array 节点上，
+/// 否则返回 `DBError::PathNotFound`，其中带有出错位置的路径。
+/// 路径经过 packed 节点时会先把它们拆开写回，返回的 key 总是实际存在。
+pub fn json_path_key(
+    db: &Database,
+    root_key: &[u8],
+    segments: &[JsonPathSegment],
+) -> Result<Vec<u8>, DBError> {
+    let (_lock, key) = db.resolve_stored(root_key, segments)?;
+    Ok(key.encode())
+}
 
 #[cfg(test)]
 mod tests {
@@ -210,6 +258,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn test_complex_path() {
         // 测试复杂路径
         let result = parse("$.library.books[0].chapters[5].title").unwrap();
@@ -388,4 +437,38 @@ mod tests {
             _ => panic!("Expected ParseFailed error"),
         }
     }
+
+    #[test]
+    fn test_parse_pointer() {
+        assert!(parse_pointer("").unwrap().is_empty());
+        assert_eq!(parse_pointer("/a/0").unwrap(), vec!["a", "0"]);
+        assert_eq!(parse_pointer("/a~1b/m~0n").unwrap(), vec!["a/b", "m~n"]);
+        assert_eq!(parse_pointer("/").unwrap(), vec![""]);
+        assert!(parse_pointer("a/b").is_err());
+        assert!(parse_pointer("/a~2").is_err());
+    }
+
+    #[test]
+    fn test_json_path_key() {
+        use crate::kv::{Key, KeyIndex};
+
+        let (db, root) = crate::test_util::temp_document("json_path_key", r#"{"users": [{"name": "a"}]}"#);
+
+        let key = json_path_key(&db, &root, &parse("$.users[0].name").unwrap()).unwrap();
+        let key = Key::decode(&key).unwrap();
+        assert_eq!(key.ids.len(), 4);
+        assert_eq!(key.field_key, KeyIndex::Field(bytes::Bytes::from_static(b"name")));
+
+        match json_path_key(&db, &root, &parse("$.users[1].name").unwrap()) {
+            Err(DBError::PathNotFound(path)) => assert_eq!(path, "$.users[1]"),
+            other => panic!("expected PathNotFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_format_path() {
+        let segments = parse("$.users[0].name").unwrap();
+        assert_eq!(format_path(&segments), "$.users[0].name");
+        assert_eq!(format_path(&[]), "$");
+    }
 }
@@ -0,0 +1,274 @@
+use std::io::Write;
+
+use simd_json::StaticNode;
+
+use crate::{DBError, Database};
+
+use super::JsonEvent;
+
+/// `write_json` 的输出格式
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// 缩进的空格数，None 表示紧凑输出
+    pub indent: Option<usize>,
+    /// object 成员按名字排序
+    pub sort_keys: bool,
+    /// 非 ASCII 字符输出为 `\uXXXX`
+    pub escape_non_ascii: bool,
+}
+
+impl WriteOptions {
+    /// 紧凑输出，不带任何空白
+    pub fn compact() -> Self {
+        Self::default()
+    }
+
+    /// 缩进两个空格的格式化输出
+    pub fn pretty() -> Self {
+        Self {
+            indent: Some(2),
+            ..Self::default()
+        }
+    }
+}
+
+impl Database {
+    /// 把 `path` 指向的子树以 json 文本写入 `out`
+    ///
+    /// 边扫描节点边输出，不会在内存中还原整个子树，见 [`Database::events`]。
+    /// 输出由许多小块写入组成，`out` 没有缓冲时建议包一层 `BufWriter`；
+    /// 写入失败时 `out` 中可能已经有部分输出。
+    ///
+    /// # 参数
+    /// * `root` - 编码后的 root key
+    /// * `path` - JSONPath 字符串，如 "$" 或 "$.items[0]"
+    /// * `out` - 输出目标
+    /// * `opts` - 输出格式
+    pub fn write_json<W: Write>(
+        &self,
+        root: &[u8],
+        path: &str,
+        out: &mut W,
+        opts: &WriteOptions,
+    ) -> Result<(), DBError> {
+        let events = self.events(root, path)?.sort_keys(opts.sort_keys);
+        let mut writer = JsonWriter {
+            out,
+            opts,
+            stack: Vec::new(),
+            after_key: false,
+        };
+        for event in events {
+            writer.write(event?)?;
+        }
+        Ok(())
+    }
+}
+
+struct JsonWriter<'a, W: Write> {
+    out: &'a mut W,
+    opts: &'a WriteOptions,
+    /// 每层容器的右括号，以及是否还没有输出过成员
+    stack: Vec<(u8, bool)>,
+    /// 刚输出了 object 成员的键
+    after_key: bool,
+}
+
+impl<W: Write> JsonWriter<'_, W> {
+    fn write(&mut self, event: JsonEvent) -> Result<(), DBError> {
+        match event {
+            JsonEvent::StartObject => self.open(b'{', b'}'),
+            JsonEvent::StartArray => self.open(b'[', b']'),
+            JsonEvent::End => {
+                let (close, empty) = self.stack.pop().ok_or(DBError::DatabaseJsonError)?;
+                if !empty {
+                    self.newline()?;
+                }
+                self.raw(&[close])
+            }
+            JsonEvent::Key(name) => {
+                self.separator()?;
+                self.string(&name)?;
+                let colon: &[u8] = if self.opts.indent.is_some() {
+                    b": "
+                } else {
+                    b":"
+                };
+                self.raw(colon)?;
+                self.after_key = true;
+                Ok(())
+            }
+            JsonEvent::String(s) => {
+                self.value_prefix()?;
+                self.string(&s)
+            }
+            JsonEvent::Static(node) => {
+                self.value_prefix()?;
+                let text = match node {
+                    StaticNode::Null => "null".to_string(),
+                    StaticNode::Bool(b) => b.to_string(),
+                    StaticNode::I64(i) => i.to_string(),
+                    StaticNode::U64(u) => u.to_string(),
+                    // Debug 格式总是带小数点或指数，读回时仍是浮点数
+                    StaticNode::F64(f) => format!("{:?}", f),
+                };
+                self.raw(text.as_bytes())
+            }
+        }
+    }
+
+    fn open(&mut self, open: u8, close: u8) -> Result<(), DBError> {
+        self.value_prefix()?;
+        self.raw(&[open])?;
+        self.stack.push((close, true));
+        Ok(())
+    }
+
+    /// 值之前的分隔符，object 成员的值紧跟在键之后
+    fn value_prefix(&mut self) -> Result<(), DBError> {
+        if self.after_key {
+            self.after_key = false;
+            return Ok(());
+        }
+        self.separator()
+    }
+
+    /// 容器中第二个及之后的成员前输出逗号，格式化输出时换行缩进
+    fn separator(&mut self) -> Result<(), DBError> {
+        let first = match self.stack.last_mut() {
+            Some((_, first)) => std::mem::replace(first, false),
+            None => return Ok(()),
+        };
+        if !first {
+            self.raw(b",")?;
+        }
+        self.newline()
+    }
+
+    fn newline(&mut self) -> Result<(), DBError> {
+        if let Some(indent) = self.opts.indent {
+            self.raw(b"\n")?;
+            for _ in 0..indent * self.stack.len() {
+                self.raw(b" ")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn string(&mut self, s: &str) -> Result<(), DBError> {
+        self.raw(b"\"")?;
+        let bytes = s.as_bytes();
+        let mut start = 0;
+        for (i, c) in s.char_indices() {
+            let escaped = match c {
+                '"' => "\\\"".to_string(),
+                '\\' => "\\\\".to_string(),
+                '\n' => "\\n".to_string(),
+                '\r' => "\\r".to_string(),
+                '\t' => "\\t".to_string(),
+                '\u{08}' => "\\b".to_string(),
+                '\u{0c}' => "\\f".to_string(),
+                c if (c as u32) < 0x20 => format!("\\u{:04x}", c as u32),
+                c if !c.is_ascii() && self.opts.escape_non_ascii => {
+                    let mut units = [0u16; 2];
+                    c.encode_utf16(&mut units)
+                        .iter()
+                        .map(|u| format!("\\u{:04x}", u))
+                        .collect()
+                }
+                _ => continue,
+            };
+            self.raw(&bytes[start..i])?;
+            self.raw(escaped.as_bytes())?;
+            start = i + c.len_utf8();
+        }
+        self.raw(&bytes[start..])?;
+        self.raw(b"\"")
+    }
+
+    fn raw(&mut self, bytes: &[u8]) -> Result<(), DBError> {
+        self.out
+            .write_all(bytes)
+            .map_err(|e| DBError::IoError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WriteOptions;
+    use crate::test_util::{json, temp_document};
+
+    fn write(db: &crate::Database, root: &[u8], path: &str, opts: &WriteOptions) -> String {
+        let mut out = Vec::new();
+        db.write_json(root, path, &mut out, opts).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write_json() {
+        let (db, root) = temp_document(
+            "write_json",
+            r#"{"b": [1, 2.5, {}, []], "a": {"s": "q\"\n\u0001é😀", "n": null, "t": true}}"#,
+        );
+        let compact = write(&db, &root, "$", &WriteOptions::compact());
+        assert_eq!(
+            compact,
+            r#"{"b":[1,2.5,{},[]],"a":{"s":"q\"\n\u0001é😀","n":null,"t":true}}"#
+        );
+        assert_eq!(json(&compact), crate::test_util::read_json(&db, &root));
+
+        let opts = WriteOptions {
+            indent: Some(2),
+            sort_keys: true,
+            escape_non_ascii: true,
+        };
+        assert_eq!(
+            write(&db, &root, "$", &opts),
+            r#"{
+  "a": {
+    "n": null,
+    "s": "q\"\n\u0001\u00e9\ud83d\ude00",
+    "t": true
+  },
+  "b": [
+    1,
+    2.5,
+    {},
+    []
+  ]
+}"#
+        );
+        assert_eq!(write(&db, &root, "$.b[1]", &WriteOptions::pretty()), "2.5");
+    }
+
+    #[test]
+    fn test_member_order() {
+        // 成员多于 32 个且不按名字排序，写入的文本原样读出
+        let members: Vec<String> = (0..40)
+            .rev()
+            .map(|i| format!(r#""m{}":{}"#, i, i))
+            .collect();
+        let text = format!(
+            r#"{{{},"nested":{{"z":1,"a":[{{"y":2,"b":3}}]}}}}"#,
+            members.join(",")
+        );
+        let (db, root) = temp_document("member_order", &text);
+        assert_eq!(write(&db, &root, "$", &WriteOptions::compact()), text);
+
+        // 新增的成员排在后面，替换和改名不改变位置
+        let mut patch = br#"{"nested": {"c": 4, "z": 5}, "new": true}"#.to_vec();
+        db.merge_patch(&root, "$", &mut patch).unwrap();
+        db.insert_at(&root, "$.nested.b", &mut b"6".to_vec())
+            .unwrap();
+        db.insert_at(&root, "$.nested.a", &mut b"7".to_vec())
+            .unwrap();
+        db.rename_key(&root, "$.nested.z", "x").unwrap();
+        assert_eq!(
+            write(&db, &root, "$.nested", &WriteOptions::compact()),
+            r#"{"x":5,"a":7,"c":4,"b":6}"#
+        );
+        let written = write(&db, &root, "$", &WriteOptions::compact());
+        assert!(written.starts_with(&format!("{{{},", members.join(","))));
+        assert!(written.ends_with(r#","new":true}"#));
+    }
+}
@@ -0,0 +1,299 @@
+use bytes::Bytes;
+use simd_json::Node;
+
+use crate::json::{self, TapeValue};
+use crate::kv::{Key, KeyIndex, NodeValue, VariableSizedId};
+use crate::{DBError, Database};
+
+use super::{element_index, parse_pointer, Editor};
+
+/// `locate` 的结果：(父节点, 节点 key, 节点值)
+type Located = (Option<(Key, NodeValue)>, Key, NodeValue);
+
+/// add/move/copy 的目标位置
+enum Target {
+    /// 整个文档
+    Root,
+    /// object 的成员，已存在时会被替换
+    Member(Key, Bytes),
+    /// array 的元素，原位置及之后的元素后移
+    Element(Key, usize),
+}
+
+impl Database {
+    /// 将 RFC 6902 JSON Patch 应用到 `root` 对应的文档上
+    ///
+    /// 操作直接作用在拆分后的节点上，只改写受影响的 key；
+    /// 所有操作通过一个 `sled::Batch` 原子提交，任何一个操作失败（包括 `test` 不相等）
+    /// 都不会对文档产生任何修改。
+    ///
+    /// # 参数
+    /// * `root` - 编码后的 root key
+    /// * `patch` - JSON Patch 文档，即操作对象组成的数组
+    pub fn apply_patch(&self, root: &[u8], patch: &mut [u8]) -> Result<(), DBError> {
+        let patch = simd_json::to_tape(patch).map_err(|_| DBError::DatabaseJsonError)?;
+        let operations = json::elements(&patch.0)
+            .ok_or_else(|| DBError::InvalidPatch("patch must be an array".to_string()))?;
+        let _lock = self.locks.write(root);
+        let mut editor = Editor::new(self);
+        let root_key = editor.root_key(root)?;
+        for operation in operations {
+            apply_operation(&mut editor, &root_key, operation)?;
+        }
+        editor.commit()?;
+        Ok(())
+    }
+}
+
+fn apply_operation(editor: &mut Editor, root: &Key, operation: TapeValue) -> Result<(), DBError> {
+    let op = string_member(operation, "op")?;
+    let path = string_member(operation, "path")?;
+    let tokens = parse_pointer(path)?;
+    match op {
+        "add" => {
+            let value = member(operation, "value")?;
+            let key = prepare_target(editor, root, path, &tokens)?;
+            editor.write_value(key, value);
+        }
+        "remove" => {
+            remove(editor, root, path, &tokens)?;
+        }
+        "replace" => {
+            let value = member(operation, "value")?;
+            let (_, key, _) = locate(editor, root, path, &tokens)?;
+            editor.remove_subtree(&key)?;
+            editor.write_value(key, value);
+        }
+        "move" => {
+            let from = string_member(operation, "from")?;
+            let from_tokens = parse_pointer(from)?;
+            if from_tokens == tokens {
+                // 移动到自身，只需确认路径存在
+                locate(editor, root, from, &from_tokens)?;
+                return Ok(());
+            }
+            if tokens.starts_with(&from_tokens) {
+                return Err(DBError::InvalidPatch(format!(
+                    "cannot move {} into its own child {}",
+                    from, path
+                )));
+            }
+            let (_, from_key, _) = locate(editor, root, from, &from_tokens)?;
+            let nodes = editor.subtree(&from_key)?;
+            remove(editor, root, from, &from_tokens)?;
+            let key = prepare_target(editor, root, path, &tokens)?;
+            editor.graft(nodes, &from_key, &key, false);
+        }
+        "copy" => {
+            let from = string_member(operation, "from")?;
+            let from_tokens = parse_pointer(from)?;
+            let (_, from_key, _) = locate(editor, root, from, &from_tokens)?;
+            let nodes = editor.subtree(&from_key)?;
+            let key = prepare_target(editor, root, path, &tokens)?;
+            editor.graft(nodes, &from_key, &key, true);
+        }
+        "test" => {
+            let expected = json::to_owned_value(member(operation, "value")?);
+            let (_, key, _) = locate(editor, root, path, &tokens)?;
+            if editor.read_value(&key)? != expected {
+                return Err(DBError::PatchTestFailed(path.to_string()));
+            }
+        }
+        _ => return Err(DBError::InvalidPatch(format!("unknown op {}", op))),
+    }
+    Ok(())
+}
+
+fn member<'a>(operation: TapeValue<'a>, name: &str) -> Result<TapeValue<'a>, DBError> {
+    json::members(operation)
+        .ok_or_else(|| DBError::InvalidPatch("operation must be an object".to_string()))?
+        .into_iter()
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+        .ok_or_else(|| DBError::InvalidPatch(format!("missing member {}", name)))
+}
+
+fn string_member<'a>(operation: TapeValue<'a>, name: &str) -> Result<&'a str, DBError> {
+    match member(operation, name)? {
+        [Node::String(s)] => Ok(s),
+        _ => Err(DBError::InvalidPatch(format!(
+            "member {} must be a string",
+            name
+        ))),
+    }
+}
+
+/// 将 pointer token 解释为 `parent` 下子节点的 `KeyIndex`
+fn child_index(parent: &NodeValue, token: &str, path: &str) -> Result<KeyIndex, DBError> {
+    match parent {
+        parent if parent.is_object() => {
+            Ok(KeyIndex::Field(Bytes::copy_from_slice(token.as_bytes())))
+        }
+        parent if parent.is_array() => {
+            let index =
+                array_index(token).ok_or_else(|| DBError::PathNotFound(path.to_string()))?;
+            Ok(KeyIndex::Id(VariableSizedId::new(index as u64)))
+        }
+        _ => Err(DBError::PathNotFound(path.to_string())),
+    }
+}
+
+/// RFC 6901 的数组下标：十进制数字，除 "0" 外不能有前导 0
+fn array_index(token: &str) -> Option<usize> {
+    if token.is_empty()
+        || !token.bytes().all(|b| b.is_ascii_digit())
+        || (token.len() > 1 && token.starts_with('0'))
+    {
+        return None;
+    }
+    token.parse().ok()
+}
+
+/// 查找 pointer 指向的节点，返回 (父节点, 节点 key, 节点值)，根节点没有父节点
+fn locate(editor: &Editor, root: &Key, path: &str, tokens: &[String]) -> Result<Located, DBError> {
+    let mut parent = None;
+    let mut key = root.clone();
+    let mut value = editor.node(root)?.ok_or(DBError::RootNotFound)?;
+    for token in tokens {
+        let index = child_index(&value, token, path)?;
+        let (child_key, child_value) = editor
+            .child(&key, &index)?
+            .ok_or_else(|| DBError::PathNotFound(path.to_string()))?;
+        parent = Some((key, value));
+        key = child_key;
+        value = child_value;
+    }
+    Ok((parent, key, value))
+}
+
+fn remove(editor: &mut Editor, root: &Key, path: &str, tokens: &[String]) -> Result<(), DBError> {
+    let (parent, key, _) = locate(editor, root, path, tokens)?;
+    let (parent_key, parent_value) = parent
+        .ok_or_else(|| DBError::InvalidPatch("cannot remove the document root".to_string()))?;
+    editor.remove_subtree(&key)?;
+    if parent_value.is_array() {
+        if let Some(index) = element_index(&key) {
+            editor.shift_elements(&parent_key, index as usize + 1, -1)?;
+        }
+    }
+    Ok(())
+}
+
+fn target(editor: &Editor, root: &Key, path: &str, tokens: &[String]) -> Result<Target, DBError> {
+    let (last, parent_tokens) = match tokens.split_last() {
+        Some(split) => split,
+        None => return Ok(Target::Root),
+    };
+    let (_, parent_key, parent_value) = locate(editor, root, path, parent_tokens)?;
+    match parent_value {
+        value if value.is_object() => Ok(Target::Member(
+            parent_key,
+            Bytes::copy_from_slice(last.as_bytes()),
+        )),
+        value if value.is_array() => {
+            let len = editor.children(&parent_key)?.len();
+            let index = if last == "-" {
+                len
+            } else {
+                array_index(last)
+                    .filter(|index| *index <= len)
+                    .ok_or_else(|| DBError::PathNotFound(path.to_string()))?
+            };
+            Ok(Target::Element(parent_key, index))
+        }
+        _ => Err(DBError::PathNotFound(path.to_string())),
+    }
+}
+
+/// 为目标位置腾出空间，返回新节点应写入的 key
+fn prepare_target(
+    editor: &mut Editor,
+    root: &Key,
+    path: &str,
+    tokens: &[String],
+) -> Result<Key, DBError> {
+    match target(editor, root, path, tokens)? {
+        Target::Root => {
+            editor.remove_subtree(root)?;
+            Ok(root.clone())
+        }
+        Target::Member(parent, name) => editor.make_room(&parent, KeyIndex::Field(name)),
+        Target::Element(parent, index) => {
+            editor.make_room(&parent, KeyIndex::Id(VariableSizedId::new(index as u64)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{json, read_json, temp_document};
+    use crate::DBError;
+
+    #[test]
+    fn test_apply_patch_operations() {
+        let (db, root) = temp_document(
+            "patch_operations",
+            r#"{"a": 1, "b": {"c": [1, 2, 3]}, "d": "x"}"#,
+        );
+        let mut patch = br#"[
+            {"op": "add", "path": "/b/c/1", "value": {"n": 9}},
+            {"op": "remove", "path": "/b/c/0"},
+            {"op": "replace", "path": "/a", "value": [true]},
+            {"op": "move", "from": "/d", "path": "/b/d"},
+            {"op": "copy", "from": "/b/c", "path": "/e"},
+            {"op": "add", "path": "/e/-", "value": null},
+            {"op": "test", "path": "/b/c/0/n", "value": 9}
+        ]"#
+        .to_vec();
+        db.apply_patch(&root, &mut patch).unwrap();
+        assert_eq!(
+            read_json(&db, &root),
+            json(
+                r#"{"a": [true], "b": {"c": [{"n": 9}, 2, 3], "d": "x"}, "e": [{"n": 9}, 2, 3, null]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_apply_patch_failed_test_is_atomic() {
+        let (db, root) = temp_document("patch_atomic", r#"{"a": 1, "b": [1, 2]}"#);
+        let mut patch = br#"[
+            {"op": "remove", "path": "/b/0"},
+            {"op": "test", "path": "/a", "value": 2}
+        ]"#
+        .to_vec();
+        match db.apply_patch(&root, &mut patch) {
+            Err(DBError::PatchTestFailed(path)) => assert_eq!(path, "/a"),
+            other => panic!("expected PatchTestFailed, got {:?}", other),
+        }
+        assert_eq!(read_json(&db, &root), json(r#"{"a": 1, "b": [1, 2]}"#));
+    }
+
+    #[test]
+    fn test_apply_patch_errors() {
+        let (db, root) = temp_document("patch_errors", r#"{"a": {"b": 1}, "c": [1]}"#);
+        let mut missing = br#"[{"op": "remove", "path": "/x"}]"#.to_vec();
+        assert!(matches!(
+            db.apply_patch(&root, &mut missing),
+            Err(DBError::PathNotFound(_))
+        ));
+        let mut into_child = br#"[{"op": "move", "from": "/a", "path": "/a/b"}]"#.to_vec();
+        assert!(matches!(
+            db.apply_patch(&root, &mut into_child),
+            Err(DBError::InvalidPatch(_))
+        ));
+        let mut out_of_range = br#"[{"op": "add", "path": "/c/2", "value": 1}]"#.to_vec();
+        assert!(matches!(
+            db.apply_patch(&root, &mut out_of_range),
+            Err(DBError::PathNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_apply_patch_replace_root() {
+        let (db, root) = temp_document("patch_root", r#"{"a": 1}"#);
+        let mut patch = br#"[{"op": "replace", "path": "", "value": [1, 2]}]"#.to_vec();
+        db.apply_patch(&root, &mut patch).unwrap();
+        assert_eq!(read_json(&db, &root), json("[1, 2]"));
+    }
+}
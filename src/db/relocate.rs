@@ -0,0 +1,271 @@
+use bytes::Bytes;
+
+use crate::kv::{Key, KeyIndex, NodeValue};
+use crate::{DBError, Database};
+
+use super::{element_index, format_path, parse, Editor, JsonPathSegment};
+
+impl Database {
+    /// 把 object 成员 `path` 改名为 `new_name`
+    ///
+    /// 子孙节点的 key 只包含 id，所以只需要改写成员节点自身的 `KeyIndex::Field`。
+    /// `new_name` 已经存在时返回 `DBError::DuplicateField`。
+    ///
+    /// # 参数
+    /// * `root` - 编码后的 root key
+    /// * `path` - 指向 object 成员的 JSONPath，如 "$.user.name"
+    /// * `new_name` - 新的成员名
+    pub fn rename_key(&self, root: &[u8], path: &str, new_name: &str) -> Result<(), DBError> {
+        let segments = parse(path)?;
+        let _lock = self.locks.write(root);
+        let editor = Editor::new(self);
+        let root = editor.root_key(root)?;
+        let (key, value) = editor.resolve(&root, &segments)?;
+        if !key.field_key.is_field() {
+            return Err(DBError::InvalidPath(format_path(&segments)));
+        }
+        let index = KeyIndex::Field(Bytes::copy_from_slice(new_name.as_bytes()));
+        if key.field_key == index {
+            return Ok(());
+        }
+        let (_, parent) = segments.split_last().unwrap();
+        let (parent_key, _) = editor.resolve(&root, parent)?;
+        if editor.child(&parent_key, &index)?.is_some() {
+            return Err(DBError::DuplicateField(new_name.to_string()));
+        }
+        editor.staged.remove_raw(key.encode());
+        editor.staged.insert(&key.with_field_key(index), &value);
+        editor.commit()?;
+        Ok(())
+    }
+
+    /// 把 `src_root` 中 `src_path` 指向的子树移动到 `dst_root` 的 `dst_path`
+    ///
+    /// 子树内节点保留原有 id，只改写 key 的前缀。目标位置的语义同 JSON Patch 的 `add`：
+    /// object 成员已存在时被替换，数组下标处插入，下标等于长度时追加；
+    /// `dst_path` 为 "$" 时替换整个目标文档，目标 root 不存在时新建。
+    /// 源是数组元素时后续元素前移，源为 "$" 时源文档被删除。所有修改在一个 batch 中原子提交。
+    pub fn move_path(
+        &self,
+        src_root: &[u8],
+        src_path: &str,
+        dst_root: &[u8],
+        dst_path: &str,
+    ) -> Result<(), DBError> {
+        let src_segments = parse(src_path)?;
+        let dst_segments = parse(dst_path)?;
+        let _locks = self.locks.write_many([src_root, dst_root]);
+        let mut editor = Editor::new(self);
+        let src_root_key = editor.root_key(src_root)?;
+        let (src_key, _) = editor.resolve(&src_root_key, &src_segments)?;
+        if src_root == dst_root {
+            if src_segments == dst_segments {
+                return Ok(());
+            }
+            if dst_segments.starts_with(&src_segments) {
+                return Err(DBError::InvalidPath(format!(
+                    "cannot move {} into itself",
+                    format_path(&src_segments)
+                )));
+            }
+        }
+
+        let nodes = editor.subtree(&src_key)?;
+        detach(&mut editor, &src_root_key, &src_segments, &src_key)?;
+        if src_segments.is_empty() {
+            editor.unregister_root(src_root)?;
+        }
+        let dst_key = editor.prepare_slot(dst_root, &dst_segments)?;
+        editor.graft(nodes, &src_key, &dst_key, false);
+        editor.commit()?;
+        Ok(())
+    }
+
+    /// 把 `src_root` 中 `src_path` 指向的子树复制到 `dst_root` 的 `dst_path`
+    ///
+    /// 副本中的每个子孙节点都分配新的 id，和原子树互不影响。
+    /// 目标位置的语义同 `move_path`，复制到源子树内部也是允许的。所有修改在一个 batch 中原子提交。
+    pub fn copy_path(
+        &self,
+        src_root: &[u8],
+        src_path: &str,
+        dst_root: &[u8],
+        dst_path: &str,
+    ) -> Result<(), DBError> {
+        let src_segments = parse(src_path)?;
+        let dst_segments = parse(dst_path)?;
+        let _locks = self.locks.write_many([src_root, dst_root]);
+        self.copy_locked(src_root, &src_segments, dst_root, &dst_segments)
+    }
+
+    /// 把整个文档复制为一个新文档，`dst` 为新文档编码后的 root key，已存在时返回
+    /// `DBError::DuplicateRootKey`
+    pub fn clone_root(&self, src: &[u8], dst: &[u8]) -> Result<(), DBError> {
+        let _locks = self.locks.write_many([src, dst]);
+        if self.has_root(dst)? {
+            return Err(DBError::DuplicateRootKey);
+        }
+        self.copy_locked(src, &[], dst, &[])
+    }
+
+    /// `copy_path` 的实现，调用方需要持有两个 root 的写锁
+    fn copy_locked(
+        &self,
+        src_root: &[u8],
+        src_segments: &[JsonPathSegment],
+        dst_root: &[u8],
+        dst_segments: &[JsonPathSegment],
+    ) -> Result<(), DBError> {
+        let mut editor = Editor::new(self);
+        let src_root_key = editor.root_key(src_root)?;
+        // 只读取源子树，不拆开源路径上的 packed 节点
+        let (src_key, src_value) = editor.lookup(&src_root_key, src_segments)?;
+        if src_root == dst_root && src_segments == dst_segments {
+            return Ok(());
+        }
+        // 先取出源子树，之后的替换或数组平移不会影响副本的内容；
+        // 位于 packed 节点内部的源节点不在存储中，它自身就是完整的值
+        let nodes = match src_value {
+            NodeValue::Object | NodeValue::Array => editor.subtree(&src_key)?,
+            value => vec![(src_key.clone(), value)],
+        };
+        let dst_key = editor.prepare_slot(dst_root, dst_segments)?;
+        editor.graft(nodes, &src_key, &dst_key, true);
+        editor.commit()?;
+        Ok(())
+    }
+}
+
+/// 删除 `key` 指向的子树，数组元素删除后后续元素前移
+fn detach(
+    editor: &mut Editor,
+    root: &Key,
+    segments: &[JsonPathSegment],
+    key: &Key,
+) -> Result<(), DBError> {
+    editor.remove_subtree(key)?;
+    if let (Some(index), Some((_, parent))) = (element_index(key), segments.split_last()) {
+        let (parent_key, _) = editor.resolve(root, parent)?;
+        editor.shift_elements(&parent_key, index as usize + 1, -1)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{json, read_json, root_key, temp_document};
+    use crate::DBError;
+
+    #[test]
+    fn test_rename_key() {
+        let (db, root) = temp_document(
+            "rename_key",
+            r#"{"user": {"name": "a", "tags": [1, {"x": 2}]}, "id": 1}"#,
+        );
+        db.rename_key(&root, "$.user", "owner").unwrap();
+        db.rename_key(&root, "$.owner.tags", "labels").unwrap();
+        assert_eq!(
+            read_json(&db, &root),
+            json(r#"{"owner": {"name": "a", "labels": [1, {"x": 2}]}, "id": 1}"#)
+        );
+        assert!(matches!(
+            db.rename_key(&root, "$.id", "owner"),
+            Err(DBError::DuplicateField(_))
+        ));
+        assert!(matches!(
+            db.rename_key(&root, "$.owner.labels[0]", "x"),
+            Err(DBError::InvalidPath(_))
+        ));
+    }
+
+    #[test]
+    fn test_move_path_across_roots() {
+        let (db, inbox) = temp_document(
+            "move_path_roots",
+            r#"{"items": [{"id": 1, "body": {"t": "a"}}, {"id": 2}]}"#,
+        );
+        let archive = root_key(1);
+        let mut value = br#"{"items": [{"id": 0}]}"#.to_vec();
+        db.insert_json(&archive, &mut value).unwrap();
+
+        db.move_path(&inbox, "$.items[0]", &archive, "$.items[1]")
+            .unwrap();
+        assert_eq!(read_json(&db, &inbox), json(r#"{"items": [{"id": 2}]}"#));
+        assert_eq!(
+            read_json(&db, &archive),
+            json(r#"{"items": [{"id": 0}, {"id": 1, "body": {"t": "a"}}]}"#)
+        );
+
+        // 移动整个文档到新的 root
+        let moved = root_key(2);
+        db.move_path(&archive, "$", &moved, "$").unwrap();
+        assert!(!db.has_root(&archive).unwrap());
+        assert_eq!(
+            read_json(&db, &moved),
+            json(r#"{"items": [{"id": 0}, {"id": 1, "body": {"t": "a"}}]}"#)
+        );
+    }
+
+    #[test]
+    fn test_copy_path() {
+        let (db, root) = temp_document("copy_path", r#"{"a": {"b": [1, {"c": 2}]}, "list": [0]}"#);
+        db.copy_path(&root, "$.a", &root, "$.list[0]").unwrap();
+        // 复制到源子树内部
+        db.copy_path(&root, "$.a.b", &root, "$.a.b[2]").unwrap();
+        assert_eq!(
+            read_json(&db, &root),
+            json(
+                r#"{"a": {"b": [1, {"c": 2}, [1, {"c": 2}]]}, "list": [{"b": [1, {"c": 2}]}, 0]}"#
+            )
+        );
+        // 副本和原子树互不影响
+        db.merge_patch(&root, "$.list[0].b[1]", &mut br#"{"c": 3}"#.to_vec())
+            .unwrap();
+        assert_eq!(
+            read_json(&db, &root),
+            json(
+                r#"{"a": {"b": [1, {"c": 2}, [1, {"c": 2}]]}, "list": [{"b": [1, {"c": 3}]}, 0]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_clone_root() {
+        let (db, draft) = temp_document("clone_root", r#"{"title": "t", "body": [{"p": 1}]}"#);
+        let fork = root_key(1);
+        let last_id = db.ids.last();
+        db.clone_root(&draft, &fork).unwrap();
+        // root 以外的 4 个节点分配了新 id
+        assert_eq!(db.ids.last(), last_id + 4);
+        db.rename_key(&fork, "$.title", "name").unwrap();
+        assert_eq!(
+            read_json(&db, &draft),
+            json(r#"{"title": "t", "body": [{"p": 1}]}"#)
+        );
+        assert_eq!(
+            read_json(&db, &fork),
+            json(r#"{"name": "t", "body": [{"p": 1}]}"#)
+        );
+        assert!(matches!(
+            db.clone_root(&draft, &fork),
+            Err(DBError::DuplicateRootKey)
+        ));
+    }
+
+    #[test]
+    fn test_move_path_within_root() {
+        let (db, root) = temp_document("move_path_within", r#"{"a": [1, 2, 3], "b": {"c": 1}}"#);
+        db.move_path(&root, "$.a[0]", &root, "$.a[2]").unwrap();
+        db.move_path(&root, "$.b", &root, "$.a[0]").unwrap();
+        assert_eq!(read_json(&db, &root), json(r#"{"a": [{"c": 1}, 2, 3, 1]}"#));
+        assert!(matches!(
+            db.move_path(&root, "$.a", &root, "$.a[0].d"),
+            Err(DBError::InvalidPath(_))
+        ));
+        assert!(matches!(
+            db.move_path(&root, "$.a[1]", &root, "$.missing.x"),
+            Err(DBError::PathNotFound(_))
+        ));
+        assert_eq!(read_json(&db, &root), json(r#"{"a": [{"c": 1}, 2, 3, 1]}"#));
+    }
+}
@@ -0,0 +1,232 @@
+use simd_json::OwnedValue;
+
+use crate::kv::{Key, KeyIndex, VariableSizedId};
+use crate::{DBError, Database};
+
+use super::{element_index, format_path, parse, Editor, JsonPathSegment};
+
+/// 一个跨文档的事务
+///
+/// 事务内的读操作能看到本事务已经做出的修改；所有修改在闭包返回 `Ok` 后一次性原子提交。
+/// 写操作接收 `&[u8]` 而不是 `&mut [u8]`，因为冲突重试时闭包会被再次执行，
+/// 而 simd_json 解析时会改写输入缓冲区。
+pub struct Transaction<'a> {
+    editor: Editor<'a>,
+}
+
+impl Database {
+    /// 在一个事务中执行 `f`，并原子提交其中所有文档的修改
+    ///
+    /// 事务采用乐观并发控制：提交时如果发现事务读到过的数据已经被修改，会丢弃全部修改
+    /// 并重新执行 `f`，所以 `f` 可能被调用多次，不应有外部副作用。
+    /// `f` 返回 `Err` 时放弃事务并返回该错误。
+    ///
+    /// # 示例
+    /// ```rust,ignore
+    /// db.transaction(|tx| {
+    ///     tx.set_path(&inbox, "$.unread", b"0")?;
+    ///     tx.delete_root(&draft)?;
+    ///     Ok(())
+    /// })?;
+    /// ```
+    pub fn transaction<F, T>(&self, f: F) -> Result<T, DBError>
+    where
+        F: Fn(&mut Transaction) -> Result<T, DBError>,
+    {
+        loop {
+            let mut tx = Transaction {
+                editor: Editor::tracking(self),
+            };
+            let result = f(&mut tx)?;
+            if tx.editor.commit_checked()? {
+                return Ok(result);
+            }
+            // 读过的数据已被修改，重新执行
+        }
+    }
+}
+
+impl Transaction<'_> {
+    /// 读取 `path` 指向的子树
+    pub fn get_path(&self, root: &[u8], path: &str) -> Result<OwnedValue, DBError> {
+        let segments = parse(path)?;
+        let root = self.editor.root_key(root)?;
+        let (key, value) = self.editor.lookup(&root, &segments)?;
+        self.editor.value_of(&key, value)
+    }
+
+    /// 插入一个新文档，`root` 为编码后的 root key
+    pub fn insert_root(&mut self, root: &[u8], value: &[u8]) -> Result<(), DBError> {
+        let key = Key::decode(root)?;
+        if !key.field_key.is_root() {
+            return Err(DBError::RootNotFound);
+        }
+        if self.editor.has_root(root)? {
+            return Err(DBError::DuplicateRootKey);
+        }
+        let mut value = value.to_vec();
+        let value = simd_json::to_tape(&mut value).map_err(|_| DBError::DatabaseJsonError)?;
+        self.editor.register_root(root);
+        self.editor.write_value(key, &value.0);
+        Ok(())
+    }
+
+    /// 设置 `path` 指向的节点
+    ///
+    /// 节点存在时替换整个子树；不存在时，如果父节点是 object 则新增成员，
+    /// 如果父节点是 array 且下标等于数组长度则追加元素，否则返回 `DBError::PathNotFound`。
+    pub fn set_path(&mut self, root: &[u8], path: &str, value: &[u8]) -> Result<(), DBError> {
+        let segments = parse(path)?;
+        let mut value = value.to_vec();
+        let value = simd_json::to_tape(&mut value).map_err(|_| DBError::DatabaseJsonError)?;
+        let root = self.editor.root_key(root)?;
+        let key = match self.editor.resolve(&root, &segments) {
+            Ok((key, _)) => {
+                self.editor.remove_subtree(&key)?;
+                key
+            }
+            Err(DBError::PathNotFound(_)) if !segments.is_empty() => {
+                let (last, parent) = segments.split_last().unwrap();
+                let (parent_key, parent_value) = self.editor.resolve(&root, parent)?;
+                let index = match last {
+                    JsonPathSegment::Key(name) if parent_value.is_object() => {
+                        KeyIndex::Field(name.as_bytes().to_vec().into())
+                    }
+                    JsonPathSegment::Index(idx)
+                        if parent_value.is_array()
+                            && *idx == self.editor.children(&parent_key)?.len() =>
+                    {
+                        KeyIndex::Id(VariableSizedId::new(*idx as u64))
+                    }
+                    _ => return Err(DBError::PathNotFound(format_path(&segments))),
+                };
+                self.editor.sub_key(&parent_key, index)
+            }
+            Err(e) => return Err(e),
+        };
+        self.editor.write_value(key, &value.0);
+        Ok(())
+    }
+
+    /// 删除 `path` 指向的节点，数组元素删除后后续元素前移；`path` 为 "$" 时等同于 `delete_root`
+    pub fn delete_path(&mut self, root: &[u8], path: &str) -> Result<(), DBError> {
+        let segments = parse(path)?;
+        let (_, parent) = match segments.split_last() {
+            Some(split) => split,
+            None => return self.delete_root(root),
+        };
+        let root = self.editor.root_key(root)?;
+        let (parent_key, parent_value) = self.editor.resolve(&root, parent)?;
+        let (key, _) = self.editor.resolve(&root, &segments)?;
+        self.editor.remove_subtree(&key)?;
+        if parent_value.is_array() {
+            if let Some(index) = element_index(&key) {
+                self.editor
+                    .shift_elements(&parent_key, index as usize + 1, -1)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 删除整个文档
+    pub fn delete_root(&mut self, root: &[u8]) -> Result<(), DBError> {
+        let key = self.editor.root_key(root)?;
+        self.editor.remove_subtree(&key)?;
+        self.editor.unregister_root(root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use simd_json::OwnedValue;
+
+    use crate::kv::NodeValue;
+    use crate::test_util::{json, read_json, root_key, temp_document};
+    use crate::{json_path_key, parse, DBError};
+
+    #[test]
+    fn test_transaction_across_roots() {
+        let (db, inbox) = temp_document("transaction_roots", r#"{"unread": 2, "items": [1, 2]}"#);
+        let archive = root_key(1);
+        let moved = db
+            .transaction(|tx| {
+                let item = tx.get_path(&inbox, "$.items[0]")?;
+                tx.delete_path(&inbox, "$.items[0]")?;
+                tx.set_path(&inbox, "$.unread", b"1")?;
+                tx.insert_root(&archive, b"{\"items\": []}")?;
+                tx.set_path(&archive, "$.items[0]", item.to_string().as_bytes())?;
+                // 读到本事务中的修改
+                assert_eq!(tx.get_path(&inbox, "$.items")?, json("[2]"));
+                Ok(item)
+            })
+            .unwrap();
+        assert_eq!(moved, json("1"));
+        assert_eq!(
+            read_json(&db, &inbox),
+            json(r#"{"unread": 1, "items": [2]}"#)
+        );
+        assert_eq!(read_json(&db, &archive), json(r#"{"items": [1]}"#));
+
+        db.transaction(|tx| tx.delete_root(&archive)).unwrap();
+        assert!(!db.has_root(&archive).unwrap());
+    }
+
+    #[test]
+    fn test_transaction_abort() {
+        let (db, root) = temp_document("transaction_abort", r#"{"a": 1}"#);
+        let result: Result<(), DBError> = db.transaction(|tx| {
+            tx.set_path(&root, "$.a", b"2")?;
+            tx.set_path(&root, "$.missing.b", b"3")
+        });
+        assert!(matches!(result, Err(DBError::PathNotFound(_))));
+        assert_eq!(read_json(&db, &root), json(r#"{"a": 1}"#));
+    }
+
+    #[test]
+    fn test_transaction_retry_on_conflict() {
+        let (db, root) = temp_document("transaction_retry", r#"{"n": 1, "copy": 0}"#);
+        let n_key = json_path_key(&db, &root, &parse("$.n").unwrap()).unwrap();
+        let tree = db.store.tree.clone();
+        let attempts = Cell::new(0);
+        db.transaction(|tx| {
+            attempts.set(attempts.get() + 1);
+            let n = tx.get_path(&root, "$.n")?;
+            if attempts.get() == 1 {
+                // 模拟事务执行期间的并发修改
+                tree.insert(&n_key, NodeValue::NumberI(5).encode().as_ref())
+                    .unwrap();
+            }
+            tx.set_path(&root, "$.copy", n.to_string().as_bytes())
+        })
+        .unwrap();
+        assert_eq!(attempts.get(), 2);
+        assert_eq!(read_json(&db, &root), json(r#"{"n": 5, "copy": 5}"#));
+    }
+
+    #[test]
+    fn test_transaction_retry_on_insert_into_scanned_range() {
+        let (db, root) = temp_document("transaction_scan", r#"{"list": [1, 2], "len": 0}"#);
+        let attempts = Cell::new(0);
+        db.transaction(|tx| {
+            attempts.set(attempts.get() + 1);
+            let list = tx.get_path(&root, "$.list")?;
+            if attempts.get() == 1 {
+                // 事务读过的节点都没有改变，只是在扫描过的范围内新增了 key
+                db.array_push(&root, "$.list", &mut b"3".to_vec())?;
+            }
+            let len = match list {
+                OwnedValue::Array(list) => list.len(),
+                _ => 0,
+            };
+            tx.set_path(&root, "$.len", len.to_string().as_bytes())
+        })
+        .unwrap();
+        assert_eq!(attempts.get(), 2);
+        assert_eq!(
+            read_json(&db, &root),
+            json(r#"{"list": [1, 2, 3], "len": 3}"#)
+        );
+    }
+}
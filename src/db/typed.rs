@@ -0,0 +1,301 @@
+mod de;
+mod ser;
+
+use std::fmt::Display;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::kv::Key;
+use crate::{DBError, Database};
+
+use super::{parse, Editor, JsonPathSegment};
+
+impl serde::ser::Error for DBError {
+    fn custom<T: Display>(msg: T) -> Self {
+        DBError::SerdeError(msg.to_string())
+    }
+}
+
+impl serde::de::Error for DBError {
+    fn custom<T: Display>(msg: T) -> Self {
+        DBError::SerdeError(msg.to_string())
+    }
+}
+
+impl Database {
+    /// 把任意实现了 `Serialize` 的值插入为一个新文档
+    ///
+    /// 值直接被拆分为节点写入，不经过 json 文本；数据模型和 serde_json 一致。
+    /// 序列化失败时不会写入任何数据。
+    ///
+    /// # 参数
+    /// * `root` - 新文档编码后的 root key，已存在时返回 `DBError::DuplicateRootKey`
+    /// * `value` - 要存储的值
+    pub fn insert_value<T: Serialize + ?Sized>(
+        &self,
+        root: &[u8],
+        value: &T,
+    ) -> Result<(), DBError> {
+        let key = Key::decode(root)?;
+        if !key.field_key.is_root() {
+            return Err(DBError::RootNotFound);
+        }
+        let _lock = self.locks.write(root);
+        let mut editor = Editor::new(self);
+        if editor.has_root(root)? {
+            return Err(DBError::DuplicateRootKey);
+        }
+        editor.register_root(root);
+        value.serialize(ser::NodeSerializer {
+            editor: &mut editor,
+            key,
+        })?;
+        editor.commit()?;
+        Ok(())
+    }
+
+    /// 把 `path` 指向的子树直接读取为 `T`，只读取 `T` 需要的节点
+    pub fn get_value<T: DeserializeOwned>(&self, root: &[u8], path: &str) -> Result<T, DBError> {
+        self.get_value_at(root, &parse(path)?)
+    }
+
+    /// 同 `get_value`，路径为已经解析好的路径段
+    pub fn get_value_at<T: DeserializeOwned>(
+        &self,
+        root: &[u8],
+        segments: &[JsonPathSegment],
+    ) -> Result<T, DBError> {
+        let _lock = self.locks.read(root);
+        let editor = Editor::new(self);
+        let root = editor.root_key(root)?;
+        let (key, value) = editor.resolve(&root, segments)?;
+        T::deserialize(de::NodeDeserializer {
+            editor: &editor,
+            key,
+            value,
+        })
+    }
+
+    /// 把 `value` 写到 `segments` 指向的位置
+    ///
+    /// 节点已存在时在原 key 上替换整个子树，所以替换一个标量只会改写一个 key；
+    /// 不存在时按 `insert_at` 的规则新建。
+    pub fn set_value_at<T: Serialize + ?Sized>(
+        &self,
+        root: &[u8],
+        segments: &[JsonPathSegment],
+        value: &T,
+    ) -> Result<(), DBError> {
+        let _lock = self.locks.write(root);
+        let mut editor = Editor::new(self);
+        let existing = if editor.has_root(root)? {
+            let root = editor.root_key(root)?;
+            match editor.resolve(&root, segments) {
+                Ok((key, _)) => Some(key),
+                Err(DBError::PathNotFound(_)) => None,
+                Err(e) => return Err(e),
+            }
+        } else {
+            None
+        };
+        let key = match existing {
+            Some(key) => {
+                editor.remove_subtree(&key)?;
+                key
+            }
+            None => editor.prepare_slot(root, segments)?,
+        };
+        value.serialize(ser::NodeSerializer {
+            editor: &mut editor,
+            key,
+        })?;
+        editor.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::kv::NodeValue;
+    use crate::test_util::{json, read_json, root_key, stored_leaves, temp_database};
+    use crate::{json_path_key, parse, CachedDocument, DBError};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Status {
+        Active,
+        Banned { reason: String },
+        Pending(u32),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        age: u8,
+        score: f64,
+        email: Option<String>,
+        tags: Vec<String>,
+        status: Status,
+        history: Vec<Status>,
+        limits: BTreeMap<u32, i64>,
+        point: (i32, bool),
+    }
+
+    fn user() -> User {
+        User {
+            name: "alice".to_string(),
+            age: 30,
+            score: 1.5,
+            email: None,
+            tags: vec!["a".to_string(), "b".to_string()],
+            status: Status::Banned {
+                reason: "spam".to_string(),
+            },
+            history: vec![Status::Active, Status::Pending(3)],
+            limits: BTreeMap::from([(1, -1), (20, 2)]),
+            point: (-4, true),
+        }
+    }
+
+    #[test]
+    fn test_insert_and_get_value() {
+        let db = temp_database("typed_value");
+        let root = root_key(0);
+        db.insert_value(&root, &user()).unwrap();
+        assert_eq!(
+            read_json(&db, &root),
+            json(
+                r#"{"name": "alice", "age": 30, "score": 1.5, "email": null, "tags": ["a", "b"],
+                "status": {"Banned": {"reason": "spam"}}, "history": ["Active", {"Pending": 3}],
+                "limits": {"1": -1, "20": 2}, "point": [-4, true]}"#
+            )
+        );
+        assert_eq!(db.get_value::<User>(&root, "$").unwrap(), user());
+        assert_eq!(
+            db.get_value::<Vec<String>>(&root, "$.tags").unwrap(),
+            vec!["a", "b"]
+        );
+        assert_eq!(
+            db.get_value::<Status>(&root, "$.history[1]").unwrap(),
+            Status::Pending(3)
+        );
+        assert!(matches!(
+            db.insert_value(&root, &user()),
+            Err(DBError::DuplicateRootKey)
+        ));
+    }
+
+    #[test]
+    fn test_get_value_from_json() {
+        let db = temp_database("typed_from_json");
+        let root = root_key(0);
+        db.insert_json(
+            &root,
+            &mut br#"{"a": [1, 2.5, 18446744073709551615]}"#.to_vec(),
+        )
+        .unwrap();
+        assert_eq!(
+            db.get_value::<(u8, f32, u64)>(&root, "$.a").unwrap(),
+            (1, 2.5, u64::MAX)
+        );
+        assert!(matches!(
+            db.get_value::<String>(&root, "$.a[0]"),
+            Err(DBError::SerdeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_insert_value_number_types() {
+        let db = temp_database("typed_number_types");
+        let doc = r#"[0, 7, -7, 9223372036854775807, 18446744073709551615, 1.5]"#;
+        db.insert_json(&root_key(0), &mut doc.as_bytes().to_vec())
+            .unwrap();
+        let value = (0i64, 7u8, -7i32, i64::MAX, u64::MAX, 1.5);
+        db.insert_value(&root_key(1), &value).unwrap();
+        // 和 insert_json 解析同样的 json 文本存储的数值类型一致
+        assert_eq!(
+            stored_leaves(&db, &root_key(1)),
+            stored_leaves(&db, &root_key(0))
+        );
+    }
+
+    #[test]
+    fn test_get_value_skips_unknown_members() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Small {
+            name: String,
+            age: u8,
+        }
+
+        let db = temp_database("typed_skip_members");
+        let root = root_key(0);
+        let extra: Vec<String> = (0..1000).map(|i| format!(r#"{{"s": "{}"}}"#, i)).collect();
+        let doc = format!(
+            r#"{{"name": "a", "extra": [{}], "age": 3}}"#,
+            extra.join(",")
+        );
+        db.insert_json(&root, &mut doc.into_bytes()).unwrap();
+        // 跳过的成员不会被逐个读取，其中不是合法 utf8 的字符串不影响结果
+        let s = json_path_key(&db, &root, &parse("$.extra[500].s").unwrap()).unwrap();
+        let invalid = NodeValue::String(vec![0xff].into()).encode();
+        db.store.tree.insert(s, invalid.as_ref()).unwrap();
+        assert_eq!(
+            db.get_value::<Small>(&root, "$").unwrap(),
+            Small {
+                name: "a".to_string(),
+                age: 3
+            }
+        );
+        assert!(db
+            .get_value::<Vec<BTreeMap<String, String>>>(&root, "$.extra")
+            .is_err());
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize, CachedDocument)]
+    struct Profile {
+        name: String,
+        age: u32,
+        tags: Vec<String>,
+    }
+
+    #[test]
+    fn test_derive_cached_document() {
+        let db = temp_database("typed_derive");
+        let root = root_key(0);
+        let profile = Profile::document(&db, &root);
+        profile
+            .set(&Profile {
+                name: "a".to_string(),
+                age: 1,
+                tags: vec![],
+            })
+            .unwrap();
+        profile.set_name("x").unwrap();
+        profile.set_tags(vec!["t".to_string()]).unwrap();
+        assert_eq!(profile.name().unwrap(), "x");
+        assert_eq!(profile.age().unwrap(), 1);
+        assert_eq!(
+            profile.get().unwrap(),
+            Profile {
+                name: "x".to_string(),
+                age: 1,
+                tags: vec!["t".to_string()],
+            }
+        );
+
+        // 修改标量字段只改写一个 key，不分配新 id，也不改写 metadata
+        let before: Vec<_> = db.store.tree.iter().map(Result::unwrap).collect();
+        Profile::document(&db, &root).set_age(2u32).unwrap();
+        let after: Vec<_> = db.store.tree.iter().map(Result::unwrap).collect();
+        let changed = before.iter().zip(&after).filter(|(a, b)| a != b).count();
+        assert_eq!((before.len(), changed), (after.len(), 1));
+        assert_eq!(
+            read_json(&db, &root),
+            json(r#"{"name": "x", "age": 2, "tags": ["t"]}"#)
+        );
+    }
+}
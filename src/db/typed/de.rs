@@ -0,0 +1,285 @@
+use std::vec;
+
+use serde::de::{
+    self, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, VariantAccess,
+    Visitor,
+};
+use serde::forward_to_deserialize_any;
+
+use crate::db::Editor;
+use crate::kv::{EncodeError, Key, KeyIndex, NodeValue};
+use crate::DBError;
+
+/// 从存储的节点直接构造 Rust 值
+///
+/// 只在 visitor 需要某个容器的内容时才扫描它的直接子节点，不会先还原成完整的 json。
+pub(crate) struct NodeDeserializer<'e, 'a> {
+    pub editor: &'e Editor<'a>,
+    pub key: Key,
+    pub value: NodeValue,
+}
+
+fn utf8(bytes: &[u8]) -> Result<&str, DBError> {
+    Ok(std::str::from_utf8(bytes).map_err(EncodeError::InvalidUtf8)?)
+}
+
+impl<'e, 'a> NodeDeserializer<'e, 'a> {
+    fn children(&self) -> Result<Children<'e, 'a>, DBError> {
+        Ok(Children {
+            editor: self.editor,
+            iter: self.editor.children(&self.key)?.into_iter(),
+            value: None,
+        })
+    }
+}
+
+impl<'de> de::Deserializer<'de> for NodeDeserializer<'_, '_> {
+    type Error = DBError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DBError> {
+        match &self.value {
+            NodeValue::Null => visitor.visit_unit(),
+            NodeValue::Bool(b) => visitor.visit_bool(*b),
+            NodeValue::Number(n) => visitor.visit_f64(*n),
+            NodeValue::NumberI(i) => visitor.visit_i64(*i),
+            NodeValue::NumberU(u) => visitor.visit_u64(*u),
+            NodeValue::String(s) => visitor.visit_str(utf8(s)?),
+            value if value.is_array() => visitor.visit_seq(self.children()?),
+            _ => visitor.visit_map(self.children()?),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DBError> {
+        match self.value {
+            NodeValue::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DBError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DBError> {
+        match &self.value {
+            NodeValue::String(s) => visitor.visit_enum(utf8(s)?.into_deserializer()),
+            value if value.is_object() => {
+                let mut members = self.editor.children(&self.key)?;
+                if members.len() != 1 {
+                    return Err(DBError::SerdeError(
+                        "enum object must have exactly one member".to_string(),
+                    ));
+                }
+                let (key, value) = members.pop().unwrap();
+                let variant = match &key.field_key {
+                    KeyIndex::Field(name) => utf8(name)?.to_string(),
+                    _ => return Err(DBError::SerdeError("invalid enum member".to_string())),
+                };
+                visitor.visit_enum(Enum {
+                    variant,
+                    value: NodeDeserializer {
+                        editor: self.editor,
+                        key,
+                        value,
+                    },
+                })
+            }
+            _ => Err(DBError::SerdeError(
+                "enum must be a string or an object".to_string(),
+            )),
+        }
+    }
+
+    // 跳过的值不需要读取，也不扫描它的子节点
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DBError> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier
+    }
+}
+
+/// 容器的直接子节点，同时用作 `SeqAccess` 和 `MapAccess`
+struct Children<'e, 'a> {
+    editor: &'e Editor<'a>,
+    iter: vec::IntoIter<(Key, NodeValue)>,
+    /// `MapAccess` 中已经读出键、尚未读取值的成员
+    value: Option<(Key, NodeValue)>,
+}
+
+impl<'e, 'a> Children<'e, 'a> {
+    fn deserializer(&self, (key, value): (Key, NodeValue)) -> NodeDeserializer<'e, 'a> {
+        NodeDeserializer {
+            editor: self.editor,
+            key,
+            value,
+        }
+    }
+}
+
+impl<'de> SeqAccess<'de> for Children<'_, '_> {
+    type Error = DBError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, DBError> {
+        match self.iter.next() {
+            Some(child) => seed.deserialize(self.deserializer(child)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+impl<'de> MapAccess<'de> for Children<'_, '_> {
+    type Error = DBError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, DBError> {
+        let (key, value) = match self.iter.next() {
+            Some(child) => child,
+            None => return Ok(None),
+        };
+        let name = match &key.field_key {
+            KeyIndex::Field(name) => utf8(name)?.to_string(),
+            _ => {
+                return Err(DBError::SerdeError(
+                    "object member without field name".to_string(),
+                ))
+            }
+        };
+        self.value = Some((key, value));
+        seed.deserialize(MapKey(name)).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, DBError> {
+        let child = self
+            .value
+            .take()
+            .ok_or_else(|| DBError::SerdeError("map value without key".to_string()))?;
+        seed.deserialize(self.deserializer(child))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+/// object 成员名，数字类型的键按十进制解析，和 serde_json 一致
+struct MapKey(String);
+
+macro_rules! deserialize_integer_key {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DBError> {
+                match self.0.parse() {
+                    Ok(n) => visitor.$visit(n),
+                    Err(_) => visitor.visit_string(self.0),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for MapKey {
+    type Error = DBError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DBError> {
+        visitor.visit_string(self.0)
+    }
+
+    deserialize_integer_key! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DBError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DBError> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+
+    forward_to_deserialize_any! {
+        bool i128 u128 f32 f64 char str string bytes byte_buf option unit
+        unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// 外部标签枚举 `{variant: value}`
+struct Enum<'e, 'a> {
+    variant: String,
+    value: NodeDeserializer<'e, 'a>,
+}
+
+impl<'de, 'e, 'a> EnumAccess<'de> for Enum<'e, 'a> {
+    type Error = DBError;
+    type Variant = NodeDeserializer<'e, 'a>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), DBError> {
+        let variant =
+            seed.deserialize(IntoDeserializer::<DBError>::into_deserializer(self.variant))?;
+        Ok((variant, self.value))
+    }
+}
+
+impl<'de> VariantAccess<'de> for NodeDeserializer<'_, '_> {
+    type Error = DBError;
+
+    fn unit_variant(self) -> Result<(), DBError> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, DBError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, DBError> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DBError> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
@@ -0,0 +1,511 @@
+use bytes::Bytes;
+use serde::ser::{self, Impossible, Serialize};
+
+use crate::db::Editor;
+use crate::kv::{Key, KeyIndex, NodeValue, VariableSizedId};
+use crate::DBError;
+
+/// 把 Rust 值直接拆分为节点写到 `key` 上，不经过 json 文本
+///
+/// 数据模型和 serde_json 一致：struct 和 map 写为 object，序列写为 array，
+/// 枚举采用外部标签（`"Variant"` 或 `{"Variant": ...}`），`None` 和 `()` 写为 null。
+pub(crate) struct NodeSerializer<'e, 'a> {
+    pub editor: &'e mut Editor<'a>,
+    pub key: Key,
+}
+
+impl<'e, 'a> NodeSerializer<'e, 'a> {
+    fn write(self, value: NodeValue) -> Result<(), DBError> {
+        self.editor.staged.insert(&self.key, &value);
+        Ok(())
+    }
+
+    fn begin(self, value: NodeValue) -> Compound<'e, 'a> {
+        self.editor.staged.insert(&self.key, &value);
+        Compound {
+            editor: self.editor,
+            key: self.key,
+            len: 0,
+            field: None,
+        }
+    }
+
+    /// 外部标签枚举：写入 `{variant: ...}`，返回 variant 成员的序列化器
+    fn variant(self, variant: &'static str) -> NodeSerializer<'e, 'a> {
+        self.editor.staged.insert(&self.key, &NodeValue::Object);
+        let key = self.editor.sub_key(&self.key, field(variant));
+        NodeSerializer {
+            editor: self.editor,
+            key,
+        }
+    }
+}
+
+fn field(name: &str) -> KeyIndex {
+    KeyIndex::Field(Bytes::copy_from_slice(name.as_bytes()))
+}
+
+/// array 或 object 的序列化状态，`len` 为已写入的元素个数，`field` 为 map 中待写入值的键
+pub(crate) struct Compound<'e, 'a> {
+    editor: &'e mut Editor<'a>,
+    key: Key,
+    len: u64,
+    field: Option<String>,
+}
+
+impl<'a> Compound<'_, 'a> {
+    fn element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), DBError> {
+        let index = KeyIndex::Id(VariableSizedId::new(self.len));
+        self.len += 1;
+        self.child(index, value)
+    }
+
+    fn child<T: ?Sized + Serialize>(&mut self, index: KeyIndex, value: &T) -> Result<(), DBError> {
+        let key = self.editor.sub_key(&self.key, index);
+        value.serialize(NodeSerializer {
+            editor: self.editor,
+            key,
+        })
+    }
+}
+
+impl<'e, 'a> ser::Serializer for NodeSerializer<'e, 'a> {
+    type Ok = ();
+    type Error = DBError;
+    type SerializeSeq = Compound<'e, 'a>;
+    type SerializeTuple = Compound<'e, 'a>;
+    type SerializeTupleStruct = Compound<'e, 'a>;
+    type SerializeTupleVariant = Compound<'e, 'a>;
+    type SerializeMap = Compound<'e, 'a>;
+    type SerializeStruct = Compound<'e, 'a>;
+    type SerializeStructVariant = Compound<'e, 'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), DBError> {
+        self.write(NodeValue::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), DBError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), DBError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), DBError> {
+        self.serialize_i64(v as i64)
+    }
+
+    // 和 insert_json 解析 json 文本的结果保持一致：非负整数存为 NumberU，负数存为 NumberI
+    fn serialize_i64(self, v: i64) -> Result<(), DBError> {
+        match u64::try_from(v) {
+            Ok(u) => self.write(NodeValue::NumberU(u)),
+            Err(_) => self.write(NodeValue::NumberI(v)),
+        }
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), DBError> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), DBError> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), DBError> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), DBError> {
+        self.write(NodeValue::NumberU(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), DBError> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), DBError> {
+        self.write(NodeValue::Number(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), DBError> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), DBError> {
+        self.write(NodeValue::String(Bytes::copy_from_slice(v.as_bytes())))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), DBError> {
+        let mut seq = self.begin(NodeValue::Array);
+        for b in v {
+            seq.element(b)?;
+        }
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), DBError> {
+        self.write(NodeValue::Null)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<(), DBError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), DBError> {
+        self.write(NodeValue::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), DBError> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), DBError> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), DBError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), DBError> {
+        value.serialize(self.variant(variant))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Compound<'e, 'a>, DBError> {
+        Ok(self.begin(NodeValue::Array))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Compound<'e, 'a>, DBError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Compound<'e, 'a>, DBError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'e, 'a>, DBError> {
+        Ok(self.variant(variant).begin(NodeValue::Array))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Compound<'e, 'a>, DBError> {
+        Ok(self.begin(NodeValue::Object))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'e, 'a>, DBError> {
+        Ok(self.begin(NodeValue::Object))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'e, 'a>, DBError> {
+        Ok(self.variant(variant).begin(NodeValue::Object))
+    }
+}
+
+impl ser::SerializeSeq for Compound<'_, '_> {
+    type Ok = ();
+    type Error = DBError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), DBError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), DBError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for Compound<'_, '_> {
+    type Ok = ();
+    type Error = DBError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), DBError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), DBError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for Compound<'_, '_> {
+    type Ok = ();
+    type Error = DBError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), DBError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), DBError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleVariant for Compound<'_, '_> {
+    type Ok = ();
+    type Error = DBError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), DBError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), DBError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for Compound<'_, '_> {
+    type Ok = ();
+    type Error = DBError;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), DBError> {
+        self.field = Some(key.serialize(MapKeySerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), DBError> {
+        let name = self
+            .field
+            .take()
+            .ok_or_else(|| DBError::SerdeError("map value without key".to_string()))?;
+        self.child(field(&name), value)
+    }
+
+    fn end(self) -> Result<(), DBError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for Compound<'_, '_> {
+    type Ok = ();
+    type Error = DBError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), DBError> {
+        self.child(field(key), value)
+    }
+
+    fn end(self) -> Result<(), DBError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStructVariant for Compound<'_, '_> {
+    type Ok = ();
+    type Error = DBError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), DBError> {
+        self.child(field(key), value)
+    }
+
+    fn end(self) -> Result<(), DBError> {
+        Ok(())
+    }
+}
+
+/// 把 map 的键转换为字符串，整数和 char 转为十进制或单字符字符串，和 serde_json 一致
+struct MapKeySerializer;
+
+fn key_must_be_string() -> DBError {
+    DBError::SerdeError("map key must be a string".to_string())
+}
+
+impl ser::Serializer for MapKeySerializer {
+    type Ok = String;
+    type Error = DBError;
+    type SerializeSeq = Impossible<String, DBError>;
+    type SerializeTuple = Impossible<String, DBError>;
+    type SerializeTupleStruct = Impossible<String, DBError>;
+    type SerializeTupleVariant = Impossible<String, DBError>;
+    type SerializeMap = Impossible<String, DBError>;
+    type SerializeStruct = Impossible<String, DBError>;
+    type SerializeStructVariant = Impossible<String, DBError>;
+
+    fn serialize_bool(self, v: bool) -> Result<String, DBError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<String, DBError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<String, DBError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<String, DBError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<String, DBError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<String, DBError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<String, DBError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<String, DBError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<String, DBError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<String, DBError> {
+        Err(key_must_be_string())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<String, DBError> {
+        Err(key_must_be_string())
+    }
+
+    fn serialize_char(self, v: char) -> Result<String, DBError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<String, DBError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<String, DBError> {
+        Err(key_must_be_string())
+    }
+
+    fn serialize_none(self) -> Result<String, DBError> {
+        Err(key_must_be_string())
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, _value: &T) -> Result<String, DBError> {
+        Err(key_must_be_string())
+    }
+
+    fn serialize_unit(self) -> Result<String, DBError> {
+        Err(key_must_be_string())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<String, DBError> {
+        Err(key_must_be_string())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<String, DBError> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<String, DBError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<String, DBError> {
+        Err(key_must_be_string())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, DBError> {
+        Err(key_must_be_string())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, DBError> {
+        Err(key_must_be_string())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, DBError> {
+        Err(key_must_be_string())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, DBError> {
+        Err(key_must_be_string())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, DBError> {
+        Err(key_must_be_string())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, DBError> {
+        Err(key_must_be_string())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, DBError> {
+        Err(key_must_be_string())
+    }
+}
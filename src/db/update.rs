@@ -0,0 +1,323 @@
+use bytes::{Bytes, BytesMut};
+use parking_lot::RwLockReadGuard;
+use simd_json::{OwnedValue, StaticNode};
+
+use crate::kv::{Key, NodeValue};
+use crate::{DBError, Database};
+
+use super::{format_path, parse, Editor, JsonPathSegment};
+
+/// `incr_path` 的增量
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Increment {
+    Int(i64),
+    Float(f64),
+}
+
+impl From<i64> for Increment {
+    fn from(value: i64) -> Self {
+        Increment::Int(value)
+    }
+}
+
+impl From<f64> for Increment {
+    fn from(value: f64) -> Self {
+        Increment::Float(value)
+    }
+}
+
+/// `cas_path` 冲突时返回的当前值
+#[derive(Debug, Clone, PartialEq)]
+pub struct CasConflict {
+    pub current: OwnedValue,
+}
+
+impl Database {
+    /// 只有当 `path` 指向的子树等于 `expected` 时，才用 `new` 替换它
+    ///
+    /// 比较和写入都在该 root 的写锁下完成，其它写操作不可能在两者之间插入；
+    /// 替换通过一个 batch 原子提交。和 `sled::Tree::compare_and_swap` 一样，
+    /// 外层错误表示操作本身失败，内层 `Err(CasConflict)` 表示比较不相等，其中带有实际的当前值。
+    ///
+    /// # 参数
+    /// * `root` - 编码后的 root key
+    /// * `path` - 目标节点的 JSONPath，节点必须存在
+    /// * `expected` - 期望的当前值
+    /// * `new` - 要写入的新值
+    pub fn cas_path(
+        &self,
+        root: &[u8],
+        path: &str,
+        expected: &mut [u8],
+        new: &mut [u8],
+    ) -> Result<Result<(), CasConflict>, DBError> {
+        let segments = parse(path)?;
+        let expected =
+            simd_json::to_owned_value(expected).map_err(|_| DBError::DatabaseJsonError)?;
+        let new = simd_json::to_tape(new).map_err(|_| DBError::DatabaseJsonError)?;
+        let _lock = self.locks.write(root);
+        let mut editor = Editor::new(self);
+        let root = editor.root_key(root)?;
+        let (key, _) = editor.resolve(&root, &segments)?;
+        let current = editor.read_value(&key)?;
+        if current != expected {
+            return Ok(Err(CasConflict { current }));
+        }
+        editor.remove_subtree(&key)?;
+        editor.write_value(key, &new.0);
+        editor.commit()?;
+        Ok(Ok(()))
+    }
+
+    /// 对数字节点做原子加法，返回相加后的值
+    ///
+    /// 整数相加时结果非负存为 `NumberU`，负数存为 `NumberI`，和 `insert_json` 解析 json 文本的
+    /// 结果一致，超出两者范围时返回 `DBError::NumericOverflow`；任意一方是浮点数时结果提升为 `Number`。
+    /// 只读写目标节点这一个 key，通过 compare-and-swap 保证原子性，不需要全局写锁。
+    ///
+    /// # 参数
+    /// * `root` - 编码后的 root key
+    /// * `path` - 指向数字节点的 JSONPath
+    /// * `delta` - 增量，可以是 `i64` 或 `f64`
+    pub fn incr_path(
+        &self,
+        root: &[u8],
+        path: &str,
+        delta: impl Into<Increment>,
+    ) -> Result<OwnedValue, DBError> {
+        let delta = delta.into();
+        let value = self.update_node(root, path, |current| add(current, delta, path))?;
+        Ok(match value {
+            NodeValue::Number(n) => OwnedValue::Static(StaticNode::F64(n)),
+            NodeValue::NumberI(i) => OwnedValue::Static(StaticNode::I64(i)),
+            NodeValue::NumberU(u) => OwnedValue::Static(StaticNode::U64(u)),
+            _ => unreachable!("add only returns numbers"),
+        })
+    }
+
+    /// 在字符串节点末尾原子地追加 `s`，返回追加后的字节长度
+    pub fn append_str(&self, root: &[u8], path: &str, s: &str) -> Result<usize, DBError> {
+        let value = self.update_node(root, path, |current| match current {
+            NodeValue::String(old) => {
+                let mut new = BytesMut::with_capacity(old.len() + s.len());
+                new.extend_from_slice(old);
+                new.extend_from_slice(s.as_bytes());
+                Ok(NodeValue::String(new.freeze()))
+            }
+            _ => Err(DBError::InvalidNodeType(path.to_string())),
+        })?;
+        match value {
+            NodeValue::String(s) => Ok(s.len()),
+            _ => unreachable!("append_str only returns strings"),
+        }
+    }
+
+    /// 对 `path` 指向的单个节点做 read-modify-write，冲突时重试
+    fn update_node<F>(&self, root: &[u8], path: &str, f: F) -> Result<NodeValue, DBError>
+    where
+        F: Fn(&NodeValue) -> Result<NodeValue, DBError>,
+    {
+        let segments = parse(path)?;
+        // 只改写单个 key，靠 compare-and-swap 保证原子性，读锁只是防止解析路径时节点被移动
+        let (_lock, key) = self.resolve_stored(root, &segments)?;
+        let key = key.encode();
+
+        let mut current = self.store.get_raw(&key)?;
+        loop {
+            let old = current.ok_or_else(|| DBError::PathNotFound(format_path(&segments)))?;
+            let new = f(&NodeValue::decode(&old)?)?;
+            let swapped = self.store.tree.compare_and_swap(
+                &key,
+                Some(old.as_ref()),
+                Some(new.encode().as_ref()),
+            )?;
+            match swapped {
+                Ok(()) => return Ok(new),
+                Err(e) => current = e.current.map(|v| Bytes::copy_from_slice(&v)),
+            }
+        }
+    }
+
+    /// 解析 `segments` 指向的节点，返回时持有该文档的读锁
+    ///
+    /// 路径经过 packed 节点时，先在写锁下把沿途的 packed 节点拆开提交，再重新解析，
+    /// 所以返回的 key 一定在存储中，可以直接读写。
+    pub(crate) fn resolve_stored(
+        &self,
+        root: &[u8],
+        segments: &[JsonPathSegment],
+    ) -> Result<(RwLockReadGuard<'_, ()>, Key), DBError> {
+        loop {
+            {
+                let lock = self.locks.read(root);
+                let editor = Editor::new(self);
+                let root_key = editor.root_key(root)?;
+                let (key, _) = editor.resolve(&root_key, segments)?;
+                if !editor.staged.is_dirty() {
+                    return Ok((lock, key));
+                }
+            }
+            let _lock = self.locks.write(root);
+            let editor = Editor::new(self);
+            let root_key = editor.root_key(root)?;
+            editor.resolve(&root_key, segments)?;
+            editor.commit()?;
+        }
+    }
+}
+
+fn add(current: &NodeValue, delta: Increment, path: &str) -> Result<NodeValue, DBError> {
+    let overflow = || DBError::NumericOverflow(path.to_string());
+    let int = match current {
+        NodeValue::NumberI(i) => *i as i128,
+        NodeValue::NumberU(u) => *u as i128,
+        NodeValue::Number(n) => {
+            let delta = match delta {
+                Increment::Int(d) => d as f64,
+                Increment::Float(d) => d,
+            };
+            return float(n + delta).ok_or_else(overflow);
+        }
+        _ => return Err(DBError::InvalidNodeType(path.to_string())),
+    };
+    match delta {
+        Increment::Int(d) => {
+            let sum = int + d as i128;
+            if let Ok(u) = u64::try_from(sum) {
+                Ok(NodeValue::NumberU(u))
+            } else if let Ok(i) = i64::try_from(sum) {
+                Ok(NodeValue::NumberI(i))
+            } else {
+                Err(overflow())
+            }
+        }
+        Increment::Float(d) => float(int as f64 + d).ok_or_else(overflow),
+    }
+}
+
+fn float(n: f64) -> Option<NodeValue> {
+    n.is_finite().then_some(NodeValue::Number(n))
+}
+
+#[cfg(test)]
+mod tests {
+    use simd_json::{OwnedValue, StaticNode};
+
+    use crate::db::{parse, Editor};
+    use crate::kv::NodeValue;
+    use crate::test_util::{json, read_json, temp_document};
+    use crate::{DBError, Database};
+
+    fn stored(db: &Database, root: &[u8], path: &str) -> NodeValue {
+        let editor = Editor::new(db);
+        let root = editor.root_key(root).unwrap();
+        editor.resolve(&root, &parse(path).unwrap()).unwrap().1
+    }
+
+    #[test]
+    fn test_incr_path() {
+        let (db, root) = temp_document(
+            "incr_path",
+            r#"{"likes": 1, "big": 9223372036854775807, "ratio": 0.5}"#,
+        );
+        assert_eq!(
+            db.incr_path(&root, "$.likes", 2).unwrap(),
+            OwnedValue::Static(StaticNode::U64(3))
+        );
+        // 非负的和存为 NumberU，和 insert_json 写入的类型一致
+        assert_eq!(stored(&db, &root, "$.likes"), NodeValue::NumberU(3));
+        assert_eq!(
+            db.incr_path(&root, "$.likes", -5).unwrap(),
+            OwnedValue::Static(StaticNode::I64(-2))
+        );
+        assert_eq!(stored(&db, &root, "$.likes"), NodeValue::NumberI(-2));
+        // 超出 i64 时仍然能存为 u64
+        assert_eq!(
+            db.incr_path(&root, "$.big", 1).unwrap(),
+            OwnedValue::Static(StaticNode::U64(9223372036854775808))
+        );
+        assert_eq!(
+            stored(&db, &root, "$.big"),
+            NodeValue::NumberU(9223372036854775808)
+        );
+        assert_eq!(
+            db.incr_path(&root, "$.ratio", 1).unwrap(),
+            OwnedValue::Static(StaticNode::F64(1.5))
+        );
+        assert_eq!(
+            db.incr_path(&root, "$.likes", 0.5).unwrap(),
+            OwnedValue::Static(StaticNode::F64(-1.5))
+        );
+        assert_eq!(
+            read_json(&db, &root),
+            json(r#"{"likes": -1.5, "big": 9223372036854775808, "ratio": 1.5}"#)
+        );
+    }
+
+    #[test]
+    fn test_incr_path_errors() {
+        let (db, root) = temp_document(
+            "incr_path_errors",
+            r#"{"max": 18446744073709551615, "s": "x"}"#,
+        );
+        assert!(matches!(
+            db.incr_path(&root, "$.max", 1),
+            Err(DBError::NumericOverflow(_))
+        ));
+        assert!(matches!(
+            db.incr_path(&root, "$.s", 1),
+            Err(DBError::InvalidNodeType(_))
+        ));
+        assert!(matches!(
+            db.incr_path(&root, "$.missing", 1),
+            Err(DBError::PathNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_append_str() {
+        let (db, root) = temp_document("append_str", r#"{"s": "ab", "n": 1}"#);
+        assert_eq!(db.append_str(&root, "$.s", "cd").unwrap(), 4);
+        assert_eq!(read_json(&db, &root), json(r#"{"s": "abcd", "n": 1}"#));
+        assert!(matches!(
+            db.append_str(&root, "$.n", "x"),
+            Err(DBError::InvalidNodeType(_))
+        ));
+    }
+
+    #[test]
+    fn test_incr_path_concurrent() {
+        let (db, root) = temp_document("incr_path_concurrent", r#"{"n": 0}"#);
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..50 {
+                        db.incr_path(&root, "$.n", 1).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(read_json(&db, &root), json(r#"{"n": 200}"#));
+    }
+
+    #[test]
+    fn test_cas_path() {
+        let (db, root) = temp_document("cas_path", r#"{"a": {"v": 1, "tags": ["x"]}}"#);
+        let swapped = db
+            .cas_path(
+                &root,
+                "$.a",
+                &mut br#"{"tags": ["x"], "v": 1}"#.to_vec(),
+                &mut br#"{"v": 2}"#.to_vec(),
+            )
+            .unwrap();
+        assert_eq!(swapped, Ok(()));
+        assert_eq!(read_json(&db, &root), json(r#"{"a": {"v": 2}}"#));
+
+        let conflict = db
+            .cas_path(&root, "$.a.v", &mut b"1".to_vec(), &mut b"3".to_vec())
+            .unwrap();
+        assert_eq!(conflict, Err(super::CasConflict { current: json("2") }));
+        assert_eq!(read_json(&db, &root), json(r#"{"a": {"v": 2}}"#));
+    }
+}
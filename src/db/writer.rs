@@ -0,0 +1,532 @@
+use bytes::Bytes;
+use parking_lot::RwLockWriteGuard;
+use simd_json::{OwnedValue, StaticNode};
+
+use crate::kv::{
+    Key, KeyIndex, NodeValue, VariableSizedId, ELEMENT_INDEX_PREFIX, FIELD_INDEX_PREFIX,
+};
+use crate::{DBError, Database};
+
+use super::{make_sub_key, root_entry_key, transaction_error, update_roots};
+
+/// 默认每写入多少个节点提交一次
+const DEFAULT_BATCH_SIZE: usize = 4096;
+
+/// 增量构建一个新文档，节点边生成边分批写入 sled
+///
+/// 按 json 的结构依次调用 `begin_object` / `key` / `begin_array` / `value` / `end`，
+/// 最后调用 `finish` 注册 root。内存中只保存当前的嵌套路径和一个未提交的 batch，
+/// 文档本身不需要完整地放在内存中。
+///
+/// 调用 `finish` 之前文档不可见。构建期间一直持有 `root` 的写锁，同一个 root 的其它写入和读取
+/// 要等到 `finish` 或 drop 之后；没有 `finish` 就 drop 时删除已经提交的节点。
+///
+/// # 示例
+/// ```rust,ignore
+/// let mut w = db.document_writer(&root)?;
+/// w.begin_object()?;
+/// w.key("items")?;
+/// w.begin_array()?;
+/// for i in 0..1_000_000 {
+///     w.value(i)?;
+/// }
+/// w.end()?;
+/// w.end()?;
+/// w.finish()?;
+/// ```
+pub struct DocumentWriter<'a> {
+    db: &'a Database,
+    _lock: RwLockWriteGuard<'a, ()>,
+    root: Vec<u8>,
+    root_key: Key,
+    pub(super) stack: Vec<Frame>,
+    /// 根节点是否已经写入
+    pub(super) started: bool,
+    batch: sled::Batch,
+    pending: usize,
+    batch_size: usize,
+    /// 流式导入时输入已经消费到的位置，每次提交 batch 时连同写入状态一起保存为检查点
+    pub(super) offset: Option<u64>,
+    /// `finish` 已经成功，drop 时不再删除节点
+    finished: bool,
+}
+
+pub(super) struct Frame {
+    pub key: Key,
+    pub object: bool,
+    /// 已写入的成员个数，也是数组下一个元素的下标
+    pub len: u64,
+    /// object 中已经给出、还没有写入值的成员名
+    pub field: Option<Bytes>,
+}
+
+impl Database {
+    /// 开始增量构建 `root` 文档，`root` 为编码后的 root key，已存在时返回 `DBError::DuplicateRootKey`
+    ///
+    /// 只需要共享引用，多个线程可以同时构建不同的文档。返回的 writer 持有 `root` 的写锁，
+    /// 锁不可重入，writer 存在期间不要在同一线程中读写同一分片的文档。
+    ///
+    /// `root` 之前没有完成的写入（包括暂停的流式导入）在开始前删除。
+    pub fn document_writer(&self, root: &[u8]) -> Result<DocumentWriter<'_>, DBError> {
+        let writer = self.lock_writer(root)?;
+        writer.discard()?;
+        Ok(writer)
+    }
+
+    /// 锁住 `root` 并创建 writer，不处理之前留下的节点
+    fn lock_writer(&self, root: &[u8]) -> Result<DocumentWriter<'_>, DBError> {
+        let root_key = Key::decode(root)?;
+        if !root_key.field_key.is_root() {
+            return Err(DBError::RootNotFound);
+        }
+        let lock = self.locks.write(root);
+        if self.has_root(root)? {
+            return Err(DBError::DuplicateRootKey);
+        }
+        Ok(DocumentWriter {
+            db: self,
+            _lock: lock,
+            root: root.to_vec(),
+            root_key,
+            stack: Vec::new(),
+            started: false,
+            batch: sled::Batch::default(),
+            pending: 0,
+            batch_size: DEFAULT_BATCH_SIZE,
+            offset: None,
+            finished: false,
+        })
+    }
+}
+
+impl DocumentWriter<'_> {
+    /// 删除这个 root 已经写入的节点、字段名索引、下标索引和导入检查点
+    ///
+    /// root 还没有注册，它之下的 key 都是没有完成的写入留下的。
+    fn discard(&self) -> Result<(), DBError> {
+        let prefix = self.root_key.id_prefix();
+        let tree = &self.db.store.tree;
+        let mut batch = sled::Batch::default();
+        batch.remove(checkpoint_key(&self.root));
+        let index_prefix = |index: &[u8]| [index, &prefix].concat();
+        let nodes = tree.scan_prefix(&prefix).keys();
+        let keys = nodes
+            .chain(tree.scan_prefix(index_prefix(FIELD_INDEX_PREFIX)).keys())
+            .chain(tree.scan_prefix(index_prefix(ELEMENT_INDEX_PREFIX)).keys());
+        for (i, key) in keys.enumerate() {
+            batch.remove(key?);
+            if (i + 1) % DEFAULT_BATCH_SIZE == 0 {
+                tree.apply_batch(std::mem::take(&mut batch))?;
+            }
+        }
+        tree.apply_batch(batch)?;
+        Ok(())
+    }
+
+    /// 设置每个 batch 包含的节点数，默认为 4096
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn begin_object(&mut self) -> Result<(), DBError> {
+        self.begin(true)
+    }
+
+    pub fn begin_array(&mut self) -> Result<(), DBError> {
+        self.begin(false)
+    }
+
+    /// 给出 object 下一个成员的名字，之后必须写入该成员的值
+    pub fn key(&mut self, name: &str) -> Result<(), DBError> {
+        match self.stack.last_mut() {
+            Some(frame) if frame.object && frame.field.is_none() => {
+                frame.field = Some(Bytes::copy_from_slice(name.as_bytes()));
+                Ok(())
+            }
+            _ => Err(DBError::InvalidWriterState(format!(
+                "unexpected key {:?}",
+                name
+            ))),
+        }
+    }
+
+    /// 写入一个值，可以是标量，也可以是完整的 array 或 object
+    pub fn value(&mut self, value: impl Into<OwnedValue>) -> Result<(), DBError> {
+        match value.into() {
+            OwnedValue::Static(node) => {
+                self.write_node(static_value(node))?;
+                self.maybe_flush()?;
+            }
+            OwnedValue::String(s) => {
+                self.write_node(NodeValue::String(s.into_bytes().into()))?;
+                self.maybe_flush()?;
+            }
+            OwnedValue::Array(elements) => {
+                self.begin_array()?;
+                for element in *elements {
+                    self.value(element)?;
+                }
+                self.end()?;
+            }
+            OwnedValue::Object(members) => {
+                self.begin_object()?;
+                for (name, member) in *members {
+                    self.key(&name)?;
+                    self.value(member)?;
+                }
+                self.end()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 写入一个已经编码好的标量，流式导入用它保留解析得到的数值类型
+    pub(super) fn scalar(&mut self, value: NodeValue) -> Result<(), DBError> {
+        self.write_node(value)?;
+        self.maybe_flush()
+    }
+
+    /// 结束最近一个 `begin_object` 或 `begin_array`
+    pub fn end(&mut self) -> Result<(), DBError> {
+        match self.stack.last() {
+            Some(frame) if frame.field.is_none() => {
+                self.stack.pop();
+                Ok(())
+            }
+            Some(_) => Err(DBError::InvalidWriterState(
+                "missing value for key".to_string(),
+            )),
+            None => Err(DBError::InvalidWriterState("unbalanced end".to_string())),
+        }
+    }
+
+    /// 提交剩余的节点并注册 root，文档此后可见
+    pub fn finish(mut self) -> Result<(), DBError> {
+        if !self.started || !self.stack.is_empty() {
+            return Err(DBError::InvalidWriterState(
+                "document is incomplete".to_string(),
+            ));
+        }
+        self.db.ids.reserve(&self.db.store)?;
+        let mut batch = std::mem::take(&mut self.batch);
+        batch.insert(root_entry_key(&self.root), &[]);
+        if self.offset.is_some() {
+            batch.remove(checkpoint_key(&self.root));
+        }
+        let registered = [self.root.clone()];
+        self.db
+            .store
+            .tree
+            .transaction(|tx| {
+                update_roots(tx, &registered, &[])?;
+                tx.apply_batch(&batch)?;
+                Ok(())
+            })
+            .map_err(transaction_error)?;
+        self.finished = true;
+        Ok(())
+    }
+
+    fn begin(&mut self, object: bool) -> Result<(), DBError> {
+        let value = if object {
+            NodeValue::Object
+        } else {
+            NodeValue::Array
+        };
+        let key = self.write_node(value)?;
+        self.stack.push(Frame {
+            key,
+            object,
+            len: 0,
+            field: None,
+        });
+        self.maybe_flush()
+    }
+
+    /// 为下一个节点分配 key 并写入 batch
+    fn write_node(&mut self, value: NodeValue) -> Result<Key, DBError> {
+        let key = match self.stack.last_mut() {
+            None if !self.started => {
+                self.started = true;
+                self.root_key.clone()
+            }
+            None => {
+                return Err(DBError::InvalidWriterState(
+                    "document already complete".to_string(),
+                ))
+            }
+            Some(frame) => {
+                let index = if frame.object {
+                    KeyIndex::Field(frame.field.take().ok_or_else(|| {
+                        DBError::InvalidWriterState("missing key for object member".to_string())
+                    })?)
+                } else {
+                    KeyIndex::Id(VariableSizedId::new(frame.len))
+                };
+                frame.len += 1;
+                make_sub_key(&frame.key, &self.db.ids, index)
+            }
+        };
+        if let Some((index, id)) = key.index_entry() {
+            self.batch.insert(index, id);
+        }
+        self.batch.insert(key.encode(), value.encode().as_ref());
+        self.pending += 1;
+        Ok(key)
+    }
+
+    /// 在一次完整的写操作之后检查是否需要提交，保证检查点记录的状态和输入位置一致
+    fn maybe_flush(&mut self) -> Result<(), DBError> {
+        if self.pending >= self.batch_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// 提交当前 batch，提交前确认其中的 id 都已经预留
+    fn flush(&mut self) -> Result<(), DBError> {
+        self.db.ids.reserve(&self.db.store)?;
+        let mut batch = std::mem::take(&mut self.batch);
+        if let Some(offset) = self.offset {
+            batch.insert(checkpoint_key(&self.root), self.encode_checkpoint(offset));
+        }
+        self.db.store.tree.apply_batch(batch)?;
+        self.pending = 0;
+        Ok(())
+    }
+
+    /// 检查点：输入位置、根节点是否已写入，以及每层容器的 key 和已写入的成员个数
+    ///
+    /// 只在一次完整的写操作之后提交，此时不会有已给出名字但还没有值的成员。
+    fn encode_checkpoint(&self, offset: u64) -> Vec<u8> {
+        let mut data = offset.to_be_bytes().to_vec();
+        data.push(self.started as u8);
+        for frame in &self.stack {
+            let key = frame.key.encode();
+            data.push(frame.object as u8);
+            data.extend_from_slice(&frame.len.to_be_bytes());
+            data.extend_from_slice(&(key.len() as u32).to_be_bytes());
+            data.extend_from_slice(&key);
+        }
+        data
+    }
+}
+
+/// 流式导入检查点的 key 前缀，以 0x00 开头，不会和任何节点的 key 冲突
+pub(super) const CHECKPOINT_PREFIX: &[u8] = b"\x00ingest\x00";
+
+pub(super) fn checkpoint_key(root: &[u8]) -> Vec<u8> {
+    let mut key = CHECKPOINT_PREFIX.to_vec();
+    key.extend_from_slice(root);
+    key
+}
+
+impl Database {
+    /// 从 `root` 的检查点恢复一个 `DocumentWriter`，没有检查点时返回 None
+    pub(super) fn restore_writer(
+        &self,
+        root: &[u8],
+    ) -> Result<Option<DocumentWriter<'_>>, DBError> {
+        let data = match self.store.tree.get(checkpoint_key(root))? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        let (offset, started, stack) = decode_checkpoint(&data)
+            .ok_or_else(|| DBError::InvalidWriterState("corrupt checkpoint".to_string()))?;
+        let mut writer = self.lock_writer(root)?;
+        writer.offset = Some(offset);
+        writer.started = started;
+        writer.stack = stack;
+        Ok(Some(writer))
+    }
+}
+
+fn take<'a>(data: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if data.len() < n {
+        return None;
+    }
+    let (head, rest) = data.split_at(n);
+    *data = rest;
+    Some(head)
+}
+
+/// 用 `convert` 改写检查点中每层容器的 key，其余内容不变，检查点损坏时返回 None
+pub(super) fn rewrite_checkpoint(
+    data: &[u8],
+    convert: impl Fn(&[u8]) -> Option<Vec<u8>>,
+) -> Option<Vec<u8>> {
+    let mut data = data;
+    let mut out = take(&mut data, 9)?.to_vec();
+    while !data.is_empty() {
+        out.extend_from_slice(take(&mut data, 9)?);
+        let key_len = u32::from_be_bytes(take(&mut data, 4)?.try_into().ok()?);
+        let key = convert(take(&mut data, key_len as usize)?)?;
+        out.extend_from_slice(&(key.len() as u32).to_be_bytes());
+        out.extend_from_slice(&key);
+    }
+    Some(out)
+}
+
+fn decode_checkpoint(data: &[u8]) -> Option<(u64, bool, Vec<Frame>)> {
+    let mut data = data;
+    let offset = u64::from_be_bytes(take(&mut data, 8)?.try_into().ok()?);
+    let started = take(&mut data, 1)?[0] != 0;
+    let mut stack = Vec::new();
+    while !data.is_empty() {
+        let object = take(&mut data, 1)?[0] != 0;
+        let len = u64::from_be_bytes(take(&mut data, 8)?.try_into().ok()?);
+        let key_len = u32::from_be_bytes(take(&mut data, 4)?.try_into().ok()?);
+        let key = Key::decode(take(&mut data, key_len as usize)?).ok()?;
+        stack.push(Frame {
+            key,
+            object,
+            len,
+            field: None,
+        });
+    }
+    Some((offset, started, stack))
+}
+
+impl Drop for DocumentWriter<'_> {
+    /// 没有完成的文档删除已经提交的节点，流式导入的节点由检查点引用，留给之后继续
+    fn drop(&mut self) {
+        if !self.finished && self.offset.is_none() {
+            // drop 中无法返回错误，删除失败时节点仍然不属于任何已注册的文档
+            let _ = self.discard();
+        }
+    }
+}
+
+/// 和 `insert_json` 解析 json 文本的结果保持一致：非负整数存为 NumberU，负数存为 NumberI
+fn static_value(node: StaticNode) -> NodeValue {
+    match node {
+        StaticNode::Null => NodeValue::Null,
+        StaticNode::Bool(b) => NodeValue::Bool(b),
+        StaticNode::I64(i) => match u64::try_from(i) {
+            Ok(u) => NodeValue::NumberU(u),
+            Err(_) => NodeValue::NumberI(i),
+        },
+        StaticNode::U64(u) => NodeValue::NumberU(u),
+        StaticNode::F64(f) => NodeValue::Number(f),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kv::{Key, ELEMENT_INDEX_PREFIX, FIELD_INDEX_PREFIX};
+    use crate::test_util::{json, read_json, root_key, stored_leaves, temp_database};
+    use crate::DBError;
+
+    #[test]
+    fn test_document_writer() {
+        let db = temp_database("document_writer");
+        let root = root_key(0);
+        let mut w = db.document_writer(&root).unwrap().batch_size(3);
+        w.begin_object().unwrap();
+        w.key("items").unwrap();
+        w.begin_array().unwrap();
+        for i in 0..10u32 {
+            w.value(i).unwrap();
+        }
+        w.value(json(r#"{"nested": [null, "s"]}"#)).unwrap();
+        w.end().unwrap();
+        w.key("done").unwrap();
+        w.value(true).unwrap();
+        w.end().unwrap();
+        // 未 finish 前文档不可见，但节点已经分批写入
+        assert!(!w.db.has_root(&root).unwrap());
+        assert!(w.db.store.tree.len() > 10);
+        w.finish().unwrap();
+
+        assert_eq!(
+            read_json(&db, &root),
+            json(
+                r#"{"items": [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, {"nested": [null, "s"]}], "done": true}"#
+            )
+        );
+        assert!(matches!(
+            db.document_writer(&root),
+            Err(DBError::DuplicateRootKey)
+        ));
+    }
+
+    #[test]
+    fn test_document_writer_misuse() {
+        let db = temp_database("document_writer_misuse");
+        let mut w = db.document_writer(&root_key(0)).unwrap();
+        w.begin_object().unwrap();
+        assert!(matches!(w.value(1), Err(DBError::InvalidWriterState(_))));
+        w.key("a").unwrap();
+        assert!(matches!(w.key("b"), Err(DBError::InvalidWriterState(_))));
+        assert!(matches!(w.end(), Err(DBError::InvalidWriterState(_))));
+        w.value(1).unwrap();
+        assert!(matches!(w.finish(), Err(DBError::InvalidWriterState(_))));
+        assert_eq!(db.root_count().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_document_writer_number_types() {
+        let db = temp_database("document_writer_number_types");
+        let doc = r#"[0, 7, -7, 9223372036854775807, 18446744073709551615, 1.5]"#;
+        db.insert_json(&root_key(0), &mut doc.as_bytes().to_vec())
+            .unwrap();
+
+        let mut w = db.document_writer(&root_key(1)).unwrap();
+        w.value(json(doc)).unwrap();
+        w.finish().unwrap();
+        let mut w = db.document_writer(&root_key(2)).unwrap();
+        w.begin_array().unwrap();
+        w.value(0i64).unwrap();
+        w.value(7u8).unwrap();
+        w.value(-7i32).unwrap();
+        w.value(i64::MAX).unwrap();
+        w.value(u64::MAX).unwrap();
+        w.value(1.5).unwrap();
+        w.end().unwrap();
+        w.finish().unwrap();
+
+        let expected = stored_leaves(&db, &root_key(0));
+        assert_eq!(stored_leaves(&db, &root_key(1)), expected);
+        assert_eq!(stored_leaves(&db, &root_key(2)), expected);
+    }
+
+    #[test]
+    fn test_dropped_writer_leaves_nothing() {
+        let db = temp_database("dropped_writer");
+        let root = root_key(0);
+        let mut w = db.document_writer(&root).unwrap().batch_size(1);
+        w.begin_object().unwrap();
+        w.key("stale").unwrap();
+        w.value(json(r#"{"a": [1, 2]}"#)).unwrap();
+        drop(w);
+
+        let prefix = Key::decode(&root).unwrap().id_prefix();
+        assert_eq!(db.store.tree.scan_prefix(&prefix).count(), 0);
+        assert_eq!(db.store.tree.scan_prefix(FIELD_INDEX_PREFIX).count(), 0);
+        assert_eq!(db.store.tree.scan_prefix(ELEMENT_INDEX_PREFIX).count(), 0);
+        db.insert_json(&root, &mut br#"{"b": 1}"#.to_vec()).unwrap();
+        assert_eq!(read_json(&db, &root), json(r#"{"b": 1}"#));
+    }
+
+    #[test]
+    fn test_writer_holds_root_lock() {
+        let db = temp_database("writer_holds_root_lock");
+        let root = root_key(0);
+        let mut w = db.document_writer(&root).unwrap();
+        w.begin_array().unwrap();
+        std::thread::scope(|s| {
+            let second = s.spawn(|| db.document_writer(&root).map(|_| ()));
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            w.value(1).unwrap();
+            w.end().unwrap();
+            w.finish().unwrap();
+            // 第二个 writer 等第一个完成之后才拿到锁，此时 root 已经存在
+            assert!(matches!(
+                second.join().unwrap(),
+                Err(DBError::DuplicateRootKey)
+            ));
+        });
+        assert_eq!(read_json(&db, &root), json("[1]"));
+    }
+}
@@ -1,6 +1,3 @@
-mod error;
 mod parse_iter;
-mod value;
 
 pub use parse_iter::*;
-pub use value::*;
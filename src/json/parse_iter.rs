@@ -1,4 +1,9 @@
-use simd_json::{self, BorrowedValue, StaticNode};
+use std::collections::HashMap;
+
+use simd_json::{self, Node, OwnedValue, StaticNode};
+
+/// tape 中的一个值：值自身的节点加上它全部子孙节点，顺序和 json 文本一致
+pub type TapeValue<'a> = &'a [Node<'a>];
 
 #[derive(Debug)]
 pub enum ItemValue<'a> {
@@ -16,27 +21,123 @@ pub enum IterItem<'a> {
     Object,
     String(&'a str),
     Static(&'a StaticNode),
+    /// 被 `JsonDfsIter::prune` 剪掉的容器，不再展开它的子节点
+    Pruned(TapeValue<'a>),
+}
+
+/// 值在 tape 中占用的节点数，包括值自身
+fn node_count(value: TapeValue) -> usize {
+    match value.first() {
+        Some(Node::Object { count, .. } | Node::Array { count, .. }) => count + 1,
+        _ => 1,
+    }
+}
+
+/// 按 json 文本中的顺序列出 object 的成员，不是 object 时返回 None
+///
+/// 成员名重复时保留第一次出现的位置和最后一次出现的值。
+pub fn members<'a>(value: TapeValue<'a>) -> Option<Vec<(&'a str, TapeValue<'a>)>> {
+    let Some(Node::Object { len, .. }) = value.first() else {
+        return None;
+    };
+    let mut members: Vec<(&str, TapeValue)> = Vec::with_capacity(*len);
+    let mut positions = HashMap::with_capacity(*len);
+    let mut idx = 1;
+    for _ in 0..*len {
+        let Some(Node::String(name)) = value.get(idx) else {
+            break;
+        };
+        let member = &value[idx + 1..];
+        let member = &member[..node_count(member)];
+        idx += 1 + member.len();
+        match positions.get(name) {
+            Some(pos) => members[*pos] = (*name, member),
+            None => {
+                positions.insert(*name, members.len());
+                members.push((*name, member));
+            }
+        }
+    }
+    Some(members)
+}
+
+/// 按下标顺序列出数组元素，不是数组时返回 None
+pub fn elements<'a>(value: TapeValue<'a>) -> Option<Vec<TapeValue<'a>>> {
+    let Some(Node::Array { len, .. }) = value.first() else {
+        return None;
+    };
+    let mut elements = Vec::with_capacity(*len);
+    let mut idx = 1;
+    for _ in 0..*len {
+        let element = &value[idx..];
+        let element = &element[..node_count(element)];
+        idx += element.len();
+        elements.push(element);
+    }
+    Some(elements)
+}
+
+/// 转换为 `OwnedValue`，超过 32 个成员的 object 不保证成员顺序
+pub fn to_owned_value(value: TapeValue) -> OwnedValue {
+    match value.first() {
+        Some(Node::String(s)) => OwnedValue::String(s.to_string()),
+        Some(Node::Static(s)) => OwnedValue::Static(*s),
+        Some(Node::Array { .. }) => OwnedValue::Array(Box::new(
+            elements(value)
+                .unwrap_or_default()
+                .into_iter()
+                .map(to_owned_value)
+                .collect(),
+        )),
+        Some(Node::Object { .. }) => members(value)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), to_owned_value(v)))
+            .collect(),
+        None => OwnedValue::Static(StaticNode::Null),
+    }
+}
+
+fn item_value<'a>(value: TapeValue<'a>) -> ItemValue<'a> {
+    match value.first() {
+        Some(Node::Object { .. }) => ItemValue::Object,
+        Some(Node::Array { .. }) => ItemValue::Array,
+        Some(Node::String(s)) => ItemValue::String(s),
+        Some(Node::Static(s)) => ItemValue::Static(s),
+        None => ItemValue::Static(&StaticNode::Null),
+    }
 }
 
 /// 带状态的 DFS 迭代器，不再返回错误。
+///
+/// 遍历的是 `simd_json::to_tape` 得到的节点，同一个父节点下的子节点按 json 文本中的顺序
+/// 调用闭包，object 成员的顺序不受成员个数影响。
 pub struct JsonDfsIter<'a, T, F>
 where
     F: for<'b> FnMut(&'b IterItem<'b>, &T) -> T,
 {
-    stack: Vec<(&'a BorrowedValue<'a>, T)>,
+    stack: Vec<(TapeValue<'a>, T)>,
     iter_fn: F,
+    prune: Option<Box<dyn FnMut(TapeValue<'a>) -> bool + 'a>>,
 }
 
 impl<'a, T, F> JsonDfsIter<'a, T, F>
 where
     F: for<'b> FnMut(&'b IterItem<'b>, &T) -> T,
 {
-    pub fn new(root_value: &'a BorrowedValue<'a>, root_state: T, iter_fn: F) -> Self {
+    pub fn new(root_value: TapeValue<'a>, root_state: T, iter_fn: F) -> Self {
         Self {
             stack: vec![(root_value, root_state)],
             iter_fn,
+            prune: None,
         }
     }
+
+    /// 展开容器之前先调用 `prune`，返回 true 时整个容器作为 `IterItem::Pruned` 产出
+    pub fn prune(mut self, prune: impl FnMut(TapeValue<'a>) -> bool + 'a) -> Self {
+        self.prune = Some(Box::new(prune));
+        self
+    }
 }
 impl<'a, T, F> Iterator for JsonDfsIter<'a, T, F>
 where
@@ -45,26 +146,26 @@ where
     // 不返回错误，直接产出一个 (IterItem2, T)
     type Item = (IterItem<'a>, T);
 
+    #[allow(clippy::needless_borrow)]
     fn next(&mut self) -> Option<Self::Item> {
         // 如果 stack 里没东西，就结束
         let (node, state) = self.stack.pop()?;
 
+        if matches!(node.first()?, Node::Object { .. } | Node::Array { .. })
+            && self.prune.as_mut().is_some_and(|prune| prune(node))
+        {
+            return Some((IterItem::Pruned(node), state));
+        }
+
         // 闭包的辅助调用函数，不再处理任何错误，直接返回新状态
         let mut call_iter_fn = |item: IterItem<'a>, s: &T| -> T { (self.iter_fn)(&item, s) };
 
-        match node {
-            BorrowedValue::Object(obj) => {
+        match node.first()? {
+            Node::Object { .. } => {
                 // 如果是 root，就先对“Object”调用闭包
 
-                for (k, v) in obj.iter() {
-                    let value = match v {
-                        BorrowedValue::Object(_) => ItemValue::Object,
-                        BorrowedValue::Array(_) => ItemValue::Array,
-                        BorrowedValue::String(s) => ItemValue::String(s),
-                        BorrowedValue::Static(s) => ItemValue::Static(s),
-                    };
-
-                    let kv_item = IterItem::KV(k, value);
+                for (k, v) in members(node).unwrap_or_default() {
+                    let kv_item = IterItem::KV(k, item_value(v));
                     // 为子节点生成新状态
                     let child_state = call_iter_fn(kv_item, &state);
 
@@ -76,15 +177,9 @@ where
                 Some((IterItem::Object, state))
             }
 
-            BorrowedValue::Array(arr) => {
-                for (idx, v) in arr.iter().enumerate() {
-                    let value = match v {
-                        BorrowedValue::Object(_) => ItemValue::Object,
-                        BorrowedValue::Array(_) => ItemValue::Array,
-                        BorrowedValue::String(s) => ItemValue::String(s),
-                        BorrowedValue::Static(s) => ItemValue::Static(s),
-                    };
-                    let iv_item = IterItem::IV(idx, value);
+            Node::Array { .. } => {
+                for (idx, v) in elements(node).unwrap_or_default().into_iter().enumerate() {
+                    let iv_item = IterItem::IV(idx, item_value(v));
                     let child_state = call_iter_fn(iv_item, &state);
 
                     self.stack.push((v, child_state));
@@ -92,9 +187,9 @@ where
                 Some((IterItem::Array, state))
             }
 
-            BorrowedValue::String(s) => Some((IterItem::String(s), state)),
+            Node::String(s) => Some((IterItem::String(s), state)),
 
-            BorrowedValue::Static(s) => Some((IterItem::Static(&s), state)),
+            Node::Static(s) => Some((IterItem::Static(s), state)),
         }
     }
 }
@@ -105,6 +200,7 @@ mod tests {
     use std::cell::Cell;
 
     #[test]
+    #[allow(clippy::let_and_return)]
     fn test_json_dfs_iter() {
         let mut d: Vec<u8> = br#"{
             "library": {
@@ -120,8 +216,8 @@ mod tests {
         }"#
         .to_vec();
         let index = Cell::new(0_u32);
-        let value = simd_json::to_borrowed_value(d.as_mut_slice()).unwrap();
-        let json_iter = JsonDfsIter::new(&value, vec![index.get()], |iter_item, key| {
+        let tape = simd_json::to_tape(d.as_mut_slice()).unwrap();
+        let json_iter = JsonDfsIter::new(&tape.0, vec![index.get()], |iter_item, key| {
             let result = match iter_item {
                 IterItem::KV(_, _) => {
                     index.set(index.get() + 1);
@@ -154,7 +250,7 @@ mod tests {
                     ids.extend_from_slice(key);
                     ids
                 }
-                IterItem::Static(_) => {
+                IterItem::Static(_) | IterItem::Pruned(_) => {
                     let mut ids = Vec::with_capacity(key.len());
                     ids.extend_from_slice(key);
                     ids
@@ -167,4 +263,57 @@ mod tests {
             println!("{:?} - {:?}", key, item);
         }
     }
+
+    #[test]
+    fn test_members_keep_document_order() {
+        // 超过 32 个成员时 BorrowedValue 会换成哈希表，tape 仍然保持文本顺序
+        let names: Vec<String> = (0..40).rev().map(|i| format!("k{}", i)).collect();
+        let body: Vec<String> = names.iter().map(|n| format!(r#""{}": 1"#, n)).collect();
+        let mut d = format!("{{{}}}", body.join(", ")).into_bytes();
+        let tape = simd_json::to_tape(d.as_mut_slice()).unwrap();
+        let found: Vec<&str> = members(&tape.0)
+            .unwrap()
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(found, names);
+    }
+
+    #[test]
+    fn test_duplicate_members() {
+        let mut d = br#"{"a": 1, "b": [2, {"c": 3}], "a": {"d": 4}}"#.to_vec();
+        let tape = simd_json::to_tape(d.as_mut_slice()).unwrap();
+        let members = members(&tape.0).unwrap();
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[0].0, "a");
+        assert_eq!(to_owned_value(members[0].1), simd_json::json!({"d": 4}));
+        assert_eq!(
+            to_owned_value(members[1].1),
+            simd_json::json!([2, {"c": 3}])
+        );
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut d = br#"{"a": [1, 2], "b": {"c": [3]}, "d": 4}"#.to_vec();
+        let tape = simd_json::to_tape(d.as_mut_slice()).unwrap();
+        let pruned: Vec<Option<OwnedValue>> = JsonDfsIter::new(&tape.0, (), |_, _| ())
+            .prune(|value| matches!(value.first(), Some(Node::Array { .. })))
+            .map(|(item, _)| match item {
+                IterItem::Pruned(value) => Some(to_owned_value(value)),
+                _ => None,
+            })
+            .collect();
+        // 两个数组都没有展开，只剩下两个 object 和 "d" 的值
+        assert_eq!(
+            pruned,
+            vec![
+                None,
+                None,
+                None,
+                Some(simd_json::json!([3])),
+                Some(simd_json::json!([1, 2])),
+            ]
+        );
+    }
 }
@@ -1,7 +1,9 @@
 mod error;
 mod node;
+mod staged;
 mod store;
 
 pub use error::*;
 pub use node::*;
+pub use staged::*;
 pub use store::*;
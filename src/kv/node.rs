@@ -9,8 +9,19 @@ pub struct AutoIncrementId(AtomicU64);
 
 // 使用CAS自旋锁实现自增ID
 impl AutoIncrementId {
-    pub fn new() -> Self {
-        Self(AtomicU64::new(0))
+    /// 从 `value` 开始，下一个 id 为 `value + 1`
+    pub fn with_value(value: u64) -> Self {
+        Self(AtomicU64::new(value))
+    }
+
+    /// 最近一次分配的 id
+    pub fn current(&self) -> u64 {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// 保证之后分配的 id 都大于 `value`
+    pub fn skip_to(&self, value: u64) {
+        self.0.fetch_max(value, Ordering::AcqRel);
     }
 
     pub fn next(&self) -> u64 {
@@ -59,6 +70,7 @@ impl VariableSizedId {
         Self { value: bytes }
     }
 
+    #[allow(dead_code)]
     /// 返回内部字节长度
     pub fn bytes_len(&self) -> usize {
         self.value.len()
@@ -90,47 +102,7 @@ impl VariableSizedId {
         Err(EncodeError::InvalidLength)
     }
 
-    /// 将 `VariableSizedId` 编码为二进制格式：先写入 1 字节长度，再写入实际数据
-    pub fn encode(&self) -> Bytes {
-        let mut id = BytesMut::with_capacity(1 + self.value.len());
-        id.extend_from_slice(&[self.value.len() as u8]);
-        id.extend_from_slice(&self.value);
-        id.freeze()
-    }
-
-    /// 从二进制切片中解析出 `VariableSizedId`
-    pub fn decode(id: &[u8]) -> Result<Self, EncodeError> {
-        if id.is_empty() {
-            return Err(EncodeError::InvalidLength);
-        }
-        let size = id[0] as usize;
-        if id.len() < size + 1 {
-            return Err(EncodeError::InvalidLength);
-        }
-        Ok(Self {
-            value: id[1..=size].to_vec(),
-        })
-    }
-
-    /// 与 `u64` 做加法，返回新的 `VariableSizedId`，若溢出则返回错误
-    pub fn checked_plus(&self, rhs: u64) -> Result<Self, EncodeError> {
-        let lhs_val = self.to_u64()?;
-        // 检查加法是否溢出
-        let (sum, overflow) = lhs_val.overflowing_add(rhs);
-        if overflow {
-            return Err(EncodeError::Overflow);
-        }
-        // 重新编码
-        Ok(Self::new(sum))
-    }
-
-    /// 与 `u64` 做加法，返回新的 `VariableSizedId`
-    pub fn unchecked_plus(&self, rhs: u64) -> Self {
-        let lhs_val = self.to_u64().unwrap();
-        let sum = lhs_val + rhs;
-        Self::new(sum)
-    }
-
+    #[allow(dead_code)]
     pub fn unchecked_minus(&self, rhs: u64) -> Self {
         let lhs_val = self.to_u64().unwrap();
         let sum = lhs_val - rhs;
@@ -162,8 +134,74 @@ pub fn read_variable_sized_id(data: &[u8]) -> Result<(VariableSizedId, usize), E
     Err(EncodeError::InvalidLength)
 }
 
+/// 按保序格式把 id 写入 key：1 字节长度 n（1..=8），之后是 n 字节大端序的值
+///
+/// 长度短的值总是更小，长度相同时按大端序逐字节比较，所以编码后的字节序和数值顺序一致，
+/// sled 中同一父节点下的子节点按 id 从小到大排列。长度字节不会是 0，不会和分隔符冲突。
+pub fn write_ordered_id(buf: &mut Vec<u8>, value: u64) {
+    let size = (8 - value.leading_zeros() as usize / 8).max(1);
+    buf.push(size as u8);
+    buf.extend_from_slice(&value.to_be_bytes()[8 - size..]);
+}
+
+/// 从字节流中读取一个 `write_ordered_id` 写入的 id，返回 (id, 消费了多少字节)
+pub fn read_ordered_id(data: &[u8]) -> Result<(u64, usize), EncodeError> {
+    let size = *data.first().ok_or(EncodeError::InvalidLength)? as usize;
+    if !(1..=8).contains(&size) || data.len() < size + 1 {
+        return Err(EncodeError::InvalidLength);
+    }
+    let mut bytes = [0u8; 8];
+    bytes[8 - size..].copy_from_slice(&data[1..=size]);
+    Ok((u64::from_be_bytes(bytes), size + 1))
+}
+
+fn write_id(buf: &mut Vec<u8>, id: &VariableSizedId) {
+    let id = id.to_u64().expect("VariableSizedId is always a valid u64");
+    write_ordered_id(buf, id);
+}
+
 const SPLITOR: u8 = 0x00;
 
+/// field 名中 0x00 之后追加的转义字节
+const FIELD_ESCAPE: u8 = 0xFF;
+/// 0x00 之后跟着它表示 field 名结束
+const FIELD_END: u8 = 0x01;
+
+/// 按转义格式写入 field 名：0x00 写为 0x00 0xFF，最后写入 0x00 0x01 作为结束标记
+///
+/// field 名可以是任意字节（包括 0x00 和非法的 UTF-8），编码后能从结束标记处确定边界，
+/// 字节序也和原始字节的字典序一致，较短的名字排在以它为前缀的名字之前。
+pub fn write_field(buf: &mut Vec<u8>, name: &[u8]) {
+    for &b in name {
+        buf.push(b);
+        if b == 0x00 {
+            buf.push(FIELD_ESCAPE);
+        }
+    }
+    buf.extend_from_slice(&[0x00, FIELD_END]);
+}
+
+/// 读取一个 `write_field` 写入的 field 名，返回 (field 名, 消费了多少字节)
+pub fn read_field(data: &[u8]) -> Result<(Bytes, usize), EncodeError> {
+    let mut name = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while let Some(&b) = data.get(i) {
+        if b != 0x00 {
+            name.push(b);
+            i += 1;
+            continue;
+        }
+        match data.get(i + 1) {
+            Some(&FIELD_ESCAPE) => name.push(0x00),
+            Some(&FIELD_END) => return Ok((Bytes::from(name), i + 2)),
+            Some(_) => return Err(EncodeError::InvalidType),
+            None => break,
+        }
+        i += 2;
+    }
+    Err(EncodeError::InvalidLength)
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum KeyIndex {
     Id(VariableSizedId),
@@ -172,6 +210,7 @@ pub enum KeyIndex {
 }
 
 impl KeyIndex {
+    #[allow(clippy::match_like_matches_macro)]
     pub fn is_root(&self) -> bool {
         match &self {
             Self::Root => true,
@@ -179,6 +218,7 @@ impl KeyIndex {
         }
     }
 
+    #[allow(clippy::match_like_matches_macro)]
     pub fn is_id(&self) -> bool {
         match &self {
             Self::Id(_) => true,
@@ -186,6 +226,7 @@ impl KeyIndex {
         }
     }
 
+    #[allow(clippy::match_like_matches_macro)]
     pub fn is_field(&self) -> bool {
         match &self {
             Self::Field(_) => true,
@@ -197,31 +238,34 @@ impl KeyIndex {
         match self {
             KeyIndex::Id(id) => {
                 let mut bytes = vec![0x02];
-                bytes.extend_from_slice(&id.value);
+                write_id(&mut bytes, id);
                 bytes
             }
             KeyIndex::Field(field) => {
                 let mut bytes = vec![0x01];
-                bytes.extend_from_slice(field.as_ref());
+                write_field(&mut bytes, field);
                 bytes
             }
             KeyIndex::Root => vec![0x03],
         }
     }
 
+    #[allow(clippy::redundant_closure)]
     pub fn decode(data: &[u8]) -> Result<Self, EncodeError> {
         if data.is_empty() {
             return Err(EncodeError::InvalidLength);
         }
         match data[0] {
             0x01 => {
-                let field =
-                    std::str::from_utf8(&data[1..]).map_err(|e| EncodeError::InvalidUtf8(e))?;
-                Ok(KeyIndex::Field(Bytes::copy_from_slice(field.as_bytes())))
+                let (field, consumed) = read_field(&data[1..])?;
+                if consumed != data.len() - 1 {
+                    return Err(EncodeError::InvalidLength);
+                }
+                Ok(KeyIndex::Field(field))
             }
             0x02 => {
-                let id = VariableSizedId::decode(&data[1..])?;
-                Ok(KeyIndex::Id(id))
+                let (id, _) = read_ordered_id(&data[1..])?;
+                Ok(KeyIndex::Id(VariableSizedId::new(id)))
             }
             0x03 => Ok(KeyIndex::Root),
             _ => Err(EncodeError::InvalidType),
@@ -230,20 +274,20 @@ impl KeyIndex {
 }
 
 /// 这里的 Key 包含：多个 ID 和一个 Field Key, ids = <super node id> + <current id>
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Key {
     pub ids: Vec<VariableSizedId>,
     pub field_key: KeyIndex,
 }
 
 impl Key {
-    /// 编码：将所有 ID 依次写入（每个都是自描述的 varint），然后写分隔符，再写 field_key
+    /// 编码：将所有 ID 依次按保序格式写入，然后写分隔符，再写 field_key
     pub fn encode(&self) -> Vec<u8> {
         let mut buf = Vec::new();
 
         // 写 n 个 self.ids
         for id in &self.ids {
-            buf.extend_from_slice(&id.unchecked_plus(1).value);
+            write_id(&mut buf, id);
         }
 
         // 写分隔符
@@ -255,7 +299,7 @@ impl Key {
         buf
     }
 
-    /// 解码：反复读 varint，直到遇到分隔符；剩余部分为 field_key
+    /// 解码：反复读 id，直到遇到分隔符；剩余部分为 field_key
     pub fn decode(bytes: &[u8]) -> Result<Self, EncodeError> {
         let mut ids = Vec::new();
         let mut offset = 0;
@@ -268,10 +312,10 @@ impl Key {
                 break;
             }
 
-            // 否则解析一个完整的 varint
-            let (vid, consumed) = read_variable_sized_id(&bytes[offset..])?;
+            // 否则解析一个完整的 id
+            let (id, consumed) = read_ordered_id(&bytes[offset..])?;
             offset += consumed;
-            ids.push(vid.unchecked_minus(1));
+            ids.push(VariableSizedId::new(id));
         }
 
         // 现在 offset 指向 field_key 或超出边界
@@ -290,23 +334,33 @@ impl Key {
 
     pub fn super_id_prefix(&self) -> Vec<u8> {
         // 只取前 n-1 个 id + SPLITOR
-        // 1. 计算长度
-        let len = self
-            .ids
-            .iter()
-            .take(self.ids.len() - 1)
-            .map(|id| id.bytes_len())
-            .sum::<usize>();
-        let mut buf = Vec::with_capacity(len + 1);
-        // 2. 写入前 n-1 个 id
+        let mut buf = Vec::new();
+        // 1. 写入前 n-1 个 id，和 encode 保持一致
         for id in self.ids.iter().take(self.ids.len() - 1) {
-            buf.extend_from_slice(&id.value);
+            write_id(&mut buf, id);
         }
-        // 3. 写入分隔符
+        // 2. 写入分隔符
         buf.push(SPLITOR);
         buf
     }
 
+    /// 当前节点所有 id 的编码，当前节点及其所有子孙节点的 key 都以它为前缀
+    pub fn id_prefix(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for id in &self.ids {
+            write_id(&mut buf, id);
+        }
+        buf
+    }
+
+    /// 替换 field_key，ids 保持不变
+    pub fn with_field_key(&self, index: KeyIndex) -> Self {
+        Self {
+            ids: self.ids.clone(),
+            field_key: index,
+        }
+    }
+
     pub fn sub_key(&self, id: VariableSizedId, index: KeyIndex) -> Self {
         // 1. 生成新的 ids
         let mut ids = self.ids.clone();
@@ -315,8 +369,85 @@ impl Key {
         let field_key = index;
         Self { ids, field_key }
     }
+
+    /// 作为 object 成员时在字段名索引中的条目：(索引 key, 自身 id 的编码)，不是成员时返回 None
+    pub fn field_index_entry(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let KeyIndex::Field(name) = &self.field_key else {
+            return None;
+        };
+        let (id, parent) = self.ids.split_last()?;
+        let mut value = Vec::new();
+        write_id(&mut value, id);
+        Some((field_index_key(parent, name), value))
+    }
+
+    /// 作为数组元素时在下标索引中的条目：(索引 key, 自身 id 的编码)，不是元素时返回 None
+    pub fn element_index_entry(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let KeyIndex::Id(index) = &self.field_key else {
+            return None;
+        };
+        let (id, parent) = self.ids.split_last()?;
+        let mut value = Vec::new();
+        write_id(&mut value, id);
+        Some((element_index_key(parent, index.to_u64().ok()?), value))
+    }
+
+    /// 写入和删除节点时需要一起维护的索引条目，object 成员在字段名索引中，数组元素在下标索引中
+    pub fn index_entry(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        self.field_index_entry()
+            .or_else(|| self.element_index_entry())
+    }
+}
+
+/// 字段名索引的 key 前缀，节点 key 的首字节是 id 的长度，不会是 0x00
+pub const FIELD_INDEX_PREFIX: &[u8] = b"\x00fields\x00";
+
+/// 字段名索引的 key：`field_index_prefix(parent)` 之后是转义后的成员名
+///
+/// 值是成员自身的 id，加上父节点的 ids 和成员名就是成员的完整 key，
+/// 按名字查找成员只需要两次点查，和父节点有多少个成员无关。
+pub fn field_index_key(parent: &[VariableSizedId], name: &[u8]) -> Vec<u8> {
+    let mut buf = field_index_prefix(parent);
+    write_field(&mut buf, name);
+    buf
+}
+
+/// 字段名索引中 `parent` 的所有成员共同的前缀：`FIELD_INDEX_PREFIX + 父节点的 ids + 分隔符`，
+/// 之后的成员名按原始字节的字典序排列
+pub fn field_index_prefix(parent: &[VariableSizedId]) -> Vec<u8> {
+    let mut buf = FIELD_INDEX_PREFIX.to_vec();
+    for id in parent {
+        write_id(&mut buf, id);
+    }
+    buf.push(SPLITOR);
+    buf
 }
-/// 0 - Null， 1 - Bool， 2 - Number，3 NumberI, 4, NumberU 5 - String， 6 - Array， 7 - Object
+
+/// 数组下标索引的 key 前缀，节点 key 的首字节是 id 的长度，不会是 0x00
+pub const ELEMENT_INDEX_PREFIX: &[u8] = b"\x00elements\x00";
+
+/// 下标索引中 `parent` 的所有元素共同的前缀：`ELEMENT_INDEX_PREFIX + 父节点的 ids + 分隔符`
+pub fn element_index_prefix(parent: &[VariableSizedId]) -> Vec<u8> {
+    let mut buf = ELEMENT_INDEX_PREFIX.to_vec();
+    for id in parent {
+        write_id(&mut buf, id);
+    }
+    buf.push(SPLITOR);
+    buf
+}
+
+/// 下标索引的 key：`element_index_prefix(parent)` 之后是保序编码的下标
+///
+/// 元素节点的 key 按分配的 id 排序，插入或删除元素之后和下标顺序不一致；索引按下标排序，
+/// 值是元素自身的 id，按下标顺序读取一段元素只需要扫描索引中对应的范围。
+pub fn element_index_key(parent: &[VariableSizedId], index: u64) -> Vec<u8> {
+    let mut buf = element_index_prefix(parent);
+    write_ordered_id(&mut buf, index);
+    buf
+}
+/// 0 - Null， 1 - Bool， 2 - Number，3 - String， 4 - Array， 5 - Object，6 - NumberI，7 - NumberU，
+/// 8 - Packed
+#[derive(Debug, Clone, PartialEq)]
 pub enum NodeValue {
     Null,
     Bool(bool),
@@ -326,14 +457,24 @@ pub enum NodeValue {
     String(Bytes),
     Array,
     Object,
+    /// 整个子树编码成的一个值，格式见 `PackedWriter`，第一个字节是容器的类型编号
+    Packed(Bytes),
 }
 
 impl NodeValue {
     pub fn is_object(&self) -> bool {
-        matches!(self, NodeValue::Object)
+        match self {
+            NodeValue::Object => true,
+            NodeValue::Packed(data) => data.first() == Some(&PACKED_OBJECT),
+            _ => false,
+        }
     }
     pub fn is_array(&self) -> bool {
-        matches!(self, NodeValue::Array)
+        match self {
+            NodeValue::Array => true,
+            NodeValue::Packed(data) => data.first() == Some(&PACKED_ARRAY),
+            _ => false,
+        }
     }
     pub fn encode(&self) -> Bytes {
         match self {
@@ -347,13 +488,13 @@ impl NodeValue {
             }
             NodeValue::NumberI(i) => {
                 let mut bytes = BytesMut::with_capacity(9);
-                bytes.extend_from_slice(&[3]);
+                bytes.extend_from_slice(&[6]);
                 bytes.extend_from_slice(&i.to_be_bytes());
                 bytes.freeze()
             }
             NodeValue::NumberU(u) => {
                 let mut bytes = BytesMut::with_capacity(9);
-                bytes.extend_from_slice(&[4]);
+                bytes.extend_from_slice(&[7]);
                 bytes.extend_from_slice(&u.to_be_bytes());
                 bytes.freeze()
             }
@@ -373,6 +514,12 @@ impl NodeValue {
                 bytes.extend_from_slice(&[5]);
                 bytes.freeze()
             }
+            NodeValue::Packed(data) => {
+                let mut bytes = BytesMut::with_capacity(1 + data.len());
+                bytes.extend_from_slice(&[8]);
+                bytes.extend_from_slice(data);
+                bytes.freeze()
+            }
         }
     }
 
@@ -382,7 +529,12 @@ impl NodeValue {
         }
         match data[0] {
             0 => Ok(NodeValue::Null),
-            1 => Ok(NodeValue::Bool(data[1] != 0)),
+            1 => {
+                if data.len() < 2 {
+                    return Err(EncodeError::InvalidLength);
+                }
+                Ok(NodeValue::Bool(data[1] != 0))
+            }
             2 => {
                 if data.len() < 9 {
                     return Err(EncodeError::InvalidLength);
@@ -392,7 +544,10 @@ impl NodeValue {
                 ]);
                 Ok(NodeValue::Number(n))
             }
-            3 => {
+            3 => Ok(NodeValue::String(data.slice(1..))),
+            4 => Ok(NodeValue::Array),
+            5 => Ok(NodeValue::Object),
+            6 => {
                 if data.len() < 9 {
                     return Err(EncodeError::InvalidLength);
                 }
@@ -401,7 +556,7 @@ impl NodeValue {
                 ]);
                 Ok(NodeValue::NumberI(n))
             }
-            4 => {
+            7 => {
                 if data.len() < 9 {
                     return Err(EncodeError::InvalidLength);
                 }
@@ -410,14 +565,151 @@ impl NodeValue {
                 ]);
                 Ok(NodeValue::NumberU(n))
             }
-            5 => Ok(NodeValue::String(data.slice(1..))),
-            6 => Ok(NodeValue::Array),
-            7 => Ok(NodeValue::Object),
+            8 => match data.get(1) {
+                Some(&PACKED_ARRAY | &PACKED_OBJECT) => Ok(NodeValue::Packed(data.slice(1..))),
+                Some(_) => Err(EncodeError::InvalidType),
+                None => Err(EncodeError::InvalidLength),
+            },
             _ => Err(EncodeError::InvalidType),
         }
     }
 }
 
+const PACKED_STRING: u8 = 3;
+const PACKED_ARRAY: u8 = 4;
+const PACKED_OBJECT: u8 = 5;
+
+/// 把一个 object 或数组连同子孙编码成一个值，存为 `NodeValue::Packed`
+///
+/// 标量的编码和 `NodeValue::encode` 相同，只是字符串在类型字节之后多一个长度；
+/// 容器是类型字节加子节点个数，之后依次是每个子节点，object 成员在值之前先写成员名。
+/// 长度和个数都用 `write_ordered_id` 的格式。
+#[derive(Default)]
+pub struct PackedWriter {
+    buf: Vec<u8>,
+}
+
+impl PackedWriter {
+    pub fn begin_array(&mut self, len: usize) {
+        self.buf.push(PACKED_ARRAY);
+        write_ordered_id(&mut self.buf, len as u64);
+    }
+
+    pub fn begin_object(&mut self, len: usize) {
+        self.buf.push(PACKED_OBJECT);
+        write_ordered_id(&mut self.buf, len as u64);
+    }
+
+    /// object 成员名，之后紧跟成员的值
+    pub fn name(&mut self, name: &[u8]) {
+        write_ordered_id(&mut self.buf, name.len() as u64);
+        self.buf.extend_from_slice(name);
+    }
+
+    /// 写入一个标量，或者原样嵌入一个 packed 值；`Array` 和 `Object` 需要用 `begin_*` 写入
+    pub fn value(&mut self, value: &NodeValue) {
+        match value {
+            NodeValue::String(s) => {
+                self.buf.push(PACKED_STRING);
+                write_ordered_id(&mut self.buf, s.len() as u64);
+                self.buf.extend_from_slice(s);
+            }
+            NodeValue::Packed(data) => self.buf.extend_from_slice(data),
+            NodeValue::Array | NodeValue::Object => {
+                debug_assert!(
+                    false,
+                    "containers are written with begin_array/begin_object"
+                )
+            }
+            _ => self.buf.extend_from_slice(&value.encode()),
+        }
+    }
+
+    /// 已经写入的字节数
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn finish(self) -> Bytes {
+        Bytes::from(self.buf)
+    }
+}
+
+/// 列出 packed 值的直接子节点：数组元素的下标为 `KeyIndex::Id`，object 成员为 `KeyIndex::Field`
+///
+/// 子节点中的容器仍然是 `NodeValue::Packed`，和 `data` 共享内存。
+pub fn packed_children(data: &Bytes) -> Result<Vec<(KeyIndex, NodeValue)>, EncodeError> {
+    let object = match data.first() {
+        Some(&PACKED_ARRAY) => false,
+        Some(&PACKED_OBJECT) => true,
+        Some(_) => return Err(EncodeError::InvalidType),
+        None => return Err(EncodeError::InvalidLength),
+    };
+    let (len, size) = read_ordered_id(&data[1..])?;
+    let mut pos = 1 + size;
+    let mut children = Vec::new();
+    for idx in 0..len {
+        let index = if object {
+            let (name_len, size) = read_ordered_id(&data[pos..])?;
+            let start = pos + size;
+            pos = start
+                .checked_add(name_len as usize)
+                .filter(|end| *end <= data.len())
+                .ok_or(EncodeError::InvalidLength)?;
+            KeyIndex::Field(data.slice(start..pos))
+        } else {
+            KeyIndex::Id(VariableSizedId::new(idx))
+        };
+        let end = pos + packed_len(&data[pos..])?;
+        let value = match data[pos] {
+            PACKED_STRING => {
+                let (_, size) = read_ordered_id(&data[pos + 1..])?;
+                NodeValue::String(data.slice(pos + 1 + size..end))
+            }
+            PACKED_ARRAY | PACKED_OBJECT => NodeValue::Packed(data.slice(pos..end)),
+            _ => NodeValue::decode(&data.slice(pos..end))?,
+        };
+        children.push((index, value));
+        pos = end;
+    }
+    Ok(children)
+}
+
+/// 开头的一个 packed 值占用的字节数
+fn packed_len(data: &[u8]) -> Result<usize, EncodeError> {
+    let len = match data.first().ok_or(EncodeError::InvalidLength)? {
+        0 => 1,
+        1 => 2,
+        2 | 6 | 7 => 9,
+        &PACKED_STRING => {
+            let (len, size) = read_ordered_id(&data[1..])?;
+            (1 + size)
+                .checked_add(len as usize)
+                .ok_or(EncodeError::InvalidLength)?
+        }
+        &(PACKED_ARRAY | PACKED_OBJECT) => {
+            let object = data[0] == PACKED_OBJECT;
+            let (count, size) = read_ordered_id(&data[1..])?;
+            let mut pos = 1 + size;
+            for _ in 0..count {
+                if object {
+                    let (name_len, size) = read_ordered_id(data.get(pos..).unwrap_or_default())?;
+                    pos = (pos + size)
+                        .checked_add(name_len as usize)
+                        .ok_or(EncodeError::InvalidLength)?;
+                }
+                pos += packed_len(data.get(pos..).unwrap_or_default())?;
+            }
+            pos
+        }
+        _ => return Err(EncodeError::InvalidType),
+    };
+    if len > data.len() {
+        return Err(EncodeError::InvalidLength);
+    }
+    Ok(len)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -442,33 +734,6 @@ mod tests {
         assert_eq!(vid.bytes_len(), 2);
     }
 
-    #[test]
-    fn test_encode_decode() {
-        let vid = VariableSizedId::new(300);
-        let encoded = vid.encode();
-        // encoded = [长度, vid.value...]
-        // 300 => 0xAC 0x02 (172,2) 变长编码
-        // vid.value = [0xAC, 0x02], 长度 2 => encoded = [2, 0xAC, 0x02]
-        assert_eq!(encoded.len(), 3);
-        assert_eq!(encoded[0], 2);
-        assert_eq!(&encoded[1..], &[0xAC, 0x02]);
-
-        let decoded = VariableSizedId::decode(&encoded).unwrap();
-        assert_eq!(decoded.value, vid.value);
-    }
-
-    #[test]
-    fn test_decode_invalid_length() {
-        // 长度声明 2，但实际只有一个字节
-        let encoded = [2, 0xAC];
-        let result = VariableSizedId::decode(&encoded);
-        assert_eq!(result, Err(EncodeError::InvalidLength));
-
-        // 空数组
-        let result = VariableSizedId::decode(&[]);
-        assert_eq!(result, Err(EncodeError::InvalidLength));
-    }
-
     #[test]
     fn test_to_u64() {
         let vid = VariableSizedId::new(0x1234_5678);
@@ -494,32 +759,6 @@ mod tests {
         assert_eq!(result, Err(EncodeError::Overflow));
     }
 
-    #[test]
-    fn test_checked_add_no_overflow() {
-        let vid = VariableSizedId::new(100);
-        let vid_added = vid.checked_plus(27).unwrap();
-        assert_eq!(vid_added.to_u64().unwrap(), 127);
-    }
-
-    #[test]
-    fn test_checked_add_overflow() {
-        let vid = VariableSizedId::new(u64::MAX);
-        // 再加 1 肯定溢出
-        let result = vid.checked_plus(1);
-        assert_eq!(result, Err(EncodeError::Overflow));
-    }
-
-    #[test]
-    fn test_checked_add_large_values() {
-        let vid = VariableSizedId::new(u64::MAX - 10);
-        let vid_added = vid.checked_plus(10).unwrap();
-        assert_eq!(vid_added.to_u64().unwrap(), u64::MAX);
-
-        // 再多加一点就溢出了
-        let result = vid_added.checked_plus(1);
-        assert_eq!(result, Err(EncodeError::Overflow));
-    }
-
     #[test]
     fn test_encode_decode_no_ids() {
         let k = Key {
@@ -556,11 +795,11 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_decode_key_invalid_length() {
-        // 构造不完整：只写了一个 varint 的第一个字节(带最高位=1)，没写完
+        // 构造不完整：id 声明了 2 个字节，但只写了 1 个
         let mut buf = BytesMut::new();
-        // 0x81 => 0x01 + 最高位=1，表示还有后续字节，但我们不写后续了
-        buf.extend_from_slice(&[0x81]);
+        buf.extend_from_slice(&[0x02, 0x01]);
         let buf = buf.freeze();
 
         let decoded = Key::decode(&buf);
@@ -569,4 +808,175 @@ mod tests {
             _ => assert!(false),
         }
     }
+
+    #[test]
+    fn test_encode_decode_id_key() {
+        let k = Key {
+            ids: vec![VariableSizedId::new(1), VariableSizedId::new(200)],
+            field_key: KeyIndex::Id(VariableSizedId::new(300)),
+        };
+        let decoded = Key::decode(&k.encode()).unwrap();
+        assert_eq!(decoded, k);
+    }
+
+    #[test]
+    fn test_ordered_id_encoding() {
+        let values = [0, 1, 127, 128, 255, 256, 300, 65536, u64::MAX];
+        let mut encoded = Vec::new();
+        for &value in &values {
+            let mut buf = Vec::new();
+            write_ordered_id(&mut buf, value);
+            assert_ne!(buf[0], SPLITOR);
+            assert_eq!(read_ordered_id(&buf).unwrap(), (value, buf.len()));
+            encoded.push(buf);
+        }
+        // 编码后的字节序和数值顺序一致
+        assert!(encoded.windows(2).all(|w| w[0] < w[1]));
+
+        // 同一父节点下的数组元素 key 按 id 排序，不会出现 128 排在 2 前面的情况
+        let parent = Key {
+            ids: vec![VariableSizedId::new(0)],
+            field_key: KeyIndex::Root,
+        };
+        let mut keys: Vec<Vec<u8>> = (0..1000u64)
+            .map(|i| {
+                parent
+                    .sub_key(
+                        VariableSizedId::new(i),
+                        KeyIndex::Id(VariableSizedId::new(i)),
+                    )
+                    .encode()
+            })
+            .collect();
+        let expected = keys.clone();
+        keys.sort();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn test_binary_field_names() {
+        let names: [&[u8]; 6] = [b"", b"\0", b"a\0b", b"\0\0\xff", b"\xff\xfe", b"\xc3\x28"];
+        for name in names {
+            let k = Key {
+                ids: vec![VariableSizedId::new(0), VariableSizedId::new(1)],
+                field_key: KeyIndex::Field(Bytes::copy_from_slice(name)),
+            };
+            assert_eq!(Key::decode(&k.encode()).unwrap(), k);
+        }
+
+        // 转义后的字节序和原始字节的字典序一致
+        let mut names: Vec<&[u8]> = vec![b"a", b"a\0", b"a\0\0", b"a\x01", b"ab", b"\0", b""];
+        let mut encoded: Vec<Vec<u8>> = names
+            .iter()
+            .map(|name| {
+                let mut buf = Vec::new();
+                write_field(&mut buf, name);
+                buf
+            })
+            .collect();
+        names.sort();
+        encoded.sort();
+        let decoded: Vec<Bytes> = encoded.iter().map(|e| read_field(e).unwrap().0).collect();
+        assert_eq!(decoded, names);
+
+        // 缺少结束标记或结束标记之后还有数据
+        assert_eq!(KeyIndex::decode(b"\x01ab"), Err(EncodeError::InvalidLength));
+        assert_eq!(
+            KeyIndex::decode(b"\x01a\0\x01b"),
+            Err(EncodeError::InvalidLength)
+        );
+        assert_eq!(
+            KeyIndex::decode(b"\x01a\0\x02"),
+            Err(EncodeError::InvalidType)
+        );
+    }
+
+    #[test]
+    fn test_node_value_encode_decode() {
+        let values = vec![
+            NodeValue::Null,
+            NodeValue::Bool(true),
+            NodeValue::Number(1.5),
+            NodeValue::NumberI(-3),
+            NodeValue::NumberU(u64::MAX),
+            NodeValue::String(Bytes::from_static(b"hello")),
+            NodeValue::Array,
+            NodeValue::Object,
+            NodeValue::Packed(Bytes::from_static(&[PACKED_ARRAY, 1, 0])),
+        ];
+        for value in values {
+            assert_eq!(NodeValue::decode(&value.encode()).unwrap(), value);
+        }
+        // 最初版本写入的类型编号保持不变
+        assert_eq!(
+            &NodeValue::String(Bytes::from_static(b"a")).encode()[..],
+            b"\x03a"
+        );
+        assert_eq!(&NodeValue::Array.encode()[..], &[4]);
+        assert_eq!(&NodeValue::Object.encode()[..], &[5]);
+    }
+
+    #[test]
+    fn test_packed_children() {
+        let mut inner = PackedWriter::default();
+        inner.begin_array(2);
+        inner.value(&NodeValue::NumberU(1));
+        inner.value(&NodeValue::String(Bytes::from_static(b"two")));
+        let inner = NodeValue::Packed(inner.finish());
+
+        let mut writer = PackedWriter::default();
+        writer.begin_object(3);
+        writer.name(b"a");
+        writer.value(&NodeValue::Null);
+        writer.name(b"b\x00c");
+        writer.value(&inner);
+        writer.name(b"");
+        writer.value(&NodeValue::Bool(true));
+        let packed = NodeValue::Packed(writer.finish());
+        assert!(packed.is_object());
+        assert!(inner.is_array());
+
+        let NodeValue::Packed(data) = NodeValue::decode(&packed.encode()).unwrap() else {
+            panic!("not packed");
+        };
+        let field = |name: &'static [u8]| KeyIndex::Field(Bytes::from_static(name));
+        assert_eq!(
+            packed_children(&data).unwrap(),
+            vec![
+                (field(b"a"), NodeValue::Null),
+                (field(b"b\x00c"), inner.clone()),
+                (field(b""), NodeValue::Bool(true)),
+            ]
+        );
+        let NodeValue::Packed(inner) = inner else {
+            unreachable!()
+        };
+        assert_eq!(
+            packed_children(&inner).unwrap(),
+            vec![
+                (KeyIndex::Id(VariableSizedId::new(0)), NodeValue::NumberU(1)),
+                (
+                    KeyIndex::Id(VariableSizedId::new(1)),
+                    NodeValue::String(Bytes::from_static(b"two"))
+                ),
+            ]
+        );
+        // 截断的数据返回错误而不是越界
+        assert!(packed_children(&data.slice(..data.len() - 1)).is_err());
+    }
+
+    #[test]
+    fn test_id_prefix() {
+        let parent = Key {
+            ids: vec![VariableSizedId::new(0), VariableSizedId::new(5)],
+            field_key: KeyIndex::Field(Bytes::from_static(b"a")),
+        };
+        let child = parent.sub_key(VariableSizedId::new(6), KeyIndex::Root);
+        assert!(child.encode().starts_with(&parent.id_prefix()));
+        assert_eq!(child.super_id_prefix(), {
+            let mut p = parent.id_prefix();
+            p.push(SPLITOR);
+            p
+        });
+    }
 }
@@ -0,0 +1,296 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ops::{Bound, Range, RangeBounds};
+
+use bytes::Bytes;
+use sled::transaction::{ConflictableTransactionResult, TransactionError, TransactionalTree};
+
+use super::{
+    element_index_key, element_index_prefix, field_index_key, read_ordered_id, Key, KeyIndex,
+    NodeValue, Store, StoreError, VariableSizedId, ELEMENT_INDEX_PREFIX, FIELD_INDEX_PREFIX,
+};
+
+/// 在 `Store` 之上暂存的一组写操作
+///
+/// 读操作会优先看到尚未提交的写入（read-your-writes），
+/// 所有写入最终通过 `into_batch` 转成一个 `sled::Batch` 原子提交，
+/// 中途放弃时直接丢弃即可，不会对 `Store` 产生任何影响。
+///
+/// 写入和删除 object 成员和数组元素的节点时同时维护字段名索引（见 `field_index_key`）
+/// 和下标索引（见 `element_index_key`），索引和节点在同一个 batch 中提交。
+///
+/// 写入只需要 `&self`，读路径上拆开 packed 节点时也可以暂存写入。
+///
+/// 通过 `tracking` 创建时还会记录从 `Store` 读到的每个键值和每次范围扫描，
+/// `commit_transaction` 提交前会校验它们没有被修改过（乐观并发控制）。
+pub struct StagedStore<'a> {
+    store: &'a Store,
+    // None 表示删除
+    writes: RefCell<BTreeMap<Vec<u8>, Option<Bytes>>>,
+    // None 表示读的时候 key 不存在
+    reads: Option<RefCell<BTreeMap<Vec<u8>, Option<Bytes>>>>,
+    // 只在记录读集合时使用
+    scans: RefCell<Vec<Scan>>,
+}
+
+/// 一次范围扫描和当时在 `Store` 中扫描到的 key，读到的值记录在读集合中
+struct Scan {
+    range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    prefix: Vec<u8>,
+    keys: Vec<Vec<u8>>,
+}
+
+impl Scan {
+    /// 在 `Store` 中重新扫描，返回现在范围内的 key
+    fn rescan(&self, store: &Store) -> Result<Vec<Vec<u8>>, sled::Error> {
+        let mut keys = Vec::new();
+        for k in store.tree.range(self.range.clone()).keys() {
+            let k = k?;
+            if !k.starts_with(&self.prefix) {
+                break;
+            }
+            keys.push(k.to_vec());
+        }
+        Ok(keys)
+    }
+}
+
+impl<'a> StagedStore<'a> {
+    pub fn new(store: &'a Store) -> Self {
+        Self {
+            store,
+            writes: RefCell::default(),
+            reads: None,
+            scans: RefCell::default(),
+        }
+    }
+
+    /// 创建一个记录读集合的 `StagedStore`，用于 `commit_transaction`
+    pub fn tracking(store: &'a Store) -> Self {
+        Self {
+            store,
+            writes: RefCell::default(),
+            reads: Some(RefCell::new(BTreeMap::new())),
+            scans: RefCell::default(),
+        }
+    }
+
+    /// 手动登记一次读取，用于不是通过 `StagedStore` 读到的数据
+    pub fn record_read(&self, key: &[u8], value: Option<Bytes>) {
+        if let Some(reads) = &self.reads {
+            reads.borrow_mut().entry(key.to_vec()).or_insert(value);
+        }
+    }
+
+    pub fn get_raw(&self, key: &[u8]) -> Result<Option<Bytes>, StoreError> {
+        if let Some(value) = self.writes.borrow().get(key) {
+            return Ok(value.clone());
+        }
+        let value = self.store.get_raw(key)?;
+        self.record_read(key, value.clone());
+        Ok(value)
+    }
+
+    pub fn get(&self, key: &Key) -> Result<Option<NodeValue>, StoreError> {
+        Ok(self
+            .get_raw(&key.encode())?
+            .map(|v| NodeValue::decode(&v))
+            .transpose()?)
+    }
+
+    pub fn insert_raw(&self, key: Vec<u8>, value: Bytes) {
+        self.writes.borrow_mut().insert(key, Some(value));
+    }
+
+    pub fn insert(&self, key: &Key, value: &NodeValue) {
+        if let Some((index, id)) = key.index_entry() {
+            self.insert_raw(index, Bytes::from(id));
+        }
+        self.insert_raw(key.encode(), value.encode());
+    }
+
+    pub fn remove_raw(&self, key: Vec<u8>) {
+        let mut writes = self.writes.borrow_mut();
+        if let Some((index, id)) = Key::decode(&key).ok().and_then(|k| k.index_entry()) {
+            // 同一批写入中已经写入了同名的另一个成员、或者移到同一下标的另一个元素时保留它的索引
+            let replaced = matches!(writes.get(&index), Some(Some(staged)) if staged[..] != id[..]);
+            if !replaced {
+                writes.insert(index, None);
+            }
+        }
+        writes.insert(key, None);
+    }
+
+    /// 通过字段名索引查找 `parent` 下名为 `name` 的成员
+    pub fn member(
+        &self,
+        parent: &Key,
+        name: &Bytes,
+    ) -> Result<Option<(Key, NodeValue)>, StoreError> {
+        let Some(id) = self.get_raw(&field_index_key(&parent.ids, name))? else {
+            return Ok(None);
+        };
+        let (id, _) = read_ordered_id(&id)?;
+        let key = parent.sub_key(VariableSizedId::new(id), KeyIndex::Field(name.clone()));
+        Ok(self.get(&key)?.map(|value| (key, value)))
+    }
+
+    /// 通过下标索引查找数组 `parent` 的第 `index` 个元素
+    pub fn element(
+        &self,
+        parent: &Key,
+        index: u64,
+    ) -> Result<Option<(Key, NodeValue)>, StoreError> {
+        let Some(id) = self.get_raw(&element_index_key(&parent.ids, index))? else {
+            return Ok(None);
+        };
+        let (id, _) = read_ordered_id(&id)?;
+        let key = parent.sub_key(
+            VariableSizedId::new(id),
+            KeyIndex::Id(VariableSizedId::new(index)),
+        );
+        Ok(self.get(&key)?.map(|value| (key, value)))
+    }
+
+    /// 按下标顺序返回数组 `parent` 中下标在 `range` 内的元素，只扫描下标索引中对应的一段
+    pub fn elements(
+        &self,
+        parent: &Key,
+        range: Range<u64>,
+    ) -> Result<Vec<(Key, NodeValue)>, StoreError> {
+        let prefix = element_index_prefix(&parent.ids);
+        let start = element_index_key(&parent.ids, range.start);
+        let end = element_index_key(&parent.ids, range.end.max(range.start));
+        let mut elements = Vec::new();
+        for (k, id) in self.scan_range(start..end, &prefix)? {
+            let (index, _) = read_ordered_id(&k[prefix.len()..])?;
+            let (id, _) = read_ordered_id(&id)?;
+            let key = parent.sub_key(
+                VariableSizedId::new(id),
+                KeyIndex::Id(VariableSizedId::new(index)),
+            );
+            if let Some(value) = self.get(&key)? {
+                elements.push((key, value));
+            }
+        }
+        Ok(elements)
+    }
+
+    /// 是否暂存了写入
+    pub fn is_dirty(&self) -> bool {
+        !self.writes.borrow().is_empty()
+    }
+
+    /// 暂存的写入涉及的所有 key，包括删除
+    pub fn written_keys(&self) -> Vec<Vec<u8>> {
+        self.writes.borrow().keys().cloned().collect()
+    }
+
+    /// 记录过的范围扫描所在的文档，每个返回值都以文档的 root id 开头，提交时用来加锁
+    pub fn scanned_keys(&self) -> Vec<Vec<u8>> {
+        self.scans
+            .borrow()
+            .iter()
+            .map(|scan| {
+                let prefix = &scan.prefix[..];
+                [FIELD_INDEX_PREFIX, ELEMENT_INDEX_PREFIX]
+                    .iter()
+                    .find_map(|index| prefix.strip_prefix(*index))
+                    .unwrap_or(prefix)
+                    .to_vec()
+            })
+            .collect()
+    }
+
+    /// 按 key 顺序返回所有以 `prefix` 开头的键值对，已合并暂存的写入
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Bytes)>, StoreError> {
+        self.scan_range(prefix.to_vec().., prefix)
+    }
+
+    /// 按 key 顺序返回 `range` 中以 `prefix` 开头的键值对，已合并暂存的写入
+    fn scan_range<R>(&self, range: R, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Bytes)>, StoreError>
+    where
+        R: RangeBounds<Vec<u8>> + Clone,
+    {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        let mut merged = BTreeMap::new();
+        let mut keys = Vec::new();
+        for kv in self.store.tree.range(range.clone()) {
+            let (k, v) = kv?;
+            if !k.starts_with(prefix) {
+                break;
+            }
+            let v = Bytes::copy_from_slice(&v);
+            self.record_read(&k, Some(v.clone()));
+            keys.push(k.to_vec());
+            merged.insert(k.to_vec(), v);
+        }
+        if self.reads.is_some() {
+            self.scans.borrow_mut().push(Scan {
+                range: range.clone(),
+                prefix: prefix.to_vec(),
+                keys,
+            });
+        }
+        let writes = self.writes.borrow();
+        let pending = writes
+            .range(range)
+            .take_while(|(k, _)| k.starts_with(prefix));
+        for (k, v) in pending {
+            match v {
+                Some(v) => merged.insert(k.clone(), v.clone()),
+                None => merged.remove(k),
+            };
+        }
+        Ok(merged.into_iter().collect())
+    }
+
+    /// 在 sled 事务中校验读集合并写入所有修改
+    ///
+    /// 读到的任何键值在提交时已被修改则返回 `Ok(false)`，不写入任何数据，调用方可以重试。
+    /// 校验通过后先执行 `before_write`，它返回 `Abort` 时同样不写入任何数据。
+    /// sled 的事务与普通写操作互斥，所以校验和写入之间不会有其它修改插入。
+    ///
+    /// sled 事务中不能做范围读取，范围扫描在进入事务之前重新扫描一次，范围内的 key
+    /// 有增减时同样返回 `Ok(false)`。调用方需要在提交期间持有这些范围所在文档的锁
+    /// （见 `scanned_keys`），保证重新扫描和写入之间没有其它修改。
+    pub fn commit_transaction<E, F>(self, before_write: F) -> Result<bool, TransactionError<E>>
+    where
+        F: Fn(&TransactionalTree) -> ConflictableTransactionResult<(), E>,
+    {
+        for scan in self.scans.borrow().iter() {
+            if scan.rescan(self.store).map_err(TransactionError::Storage)? != scan.keys {
+                return Ok(false);
+            }
+        }
+        let reads = self.reads.map(RefCell::into_inner).unwrap_or_default();
+        let writes = self.writes.into_inner();
+        self.store.tree.transaction(|tx| {
+            for (k, expected) in &reads {
+                let current = tx.get(k)?;
+                if current.as_deref() != expected.as_deref() {
+                    return Ok(false);
+                }
+            }
+            before_write(tx)?;
+            for (k, v) in &writes {
+                match v {
+                    Some(v) => tx.insert(k.as_slice(), v.as_ref())?,
+                    None => tx.remove(k.as_slice())?,
+                };
+            }
+            Ok(true)
+        })
+    }
+
+    pub fn into_batch(self) -> sled::Batch {
+        let mut batch = sled::Batch::default();
+        for (k, v) in self.writes.into_inner() {
+            match v {
+                Some(v) => batch.insert(k, v.as_ref()),
+                None => batch.remove(k),
+            }
+        }
+        batch
+    }
+}
@@ -8,12 +8,6 @@ pub struct Store {
     pub(crate) tree: sled::Db,
 }
 
-pub fn init_store<P: AsRef<Path>>(file: &P) -> Result<Store> {
-    let tree = sled::open(file)?;
-
-    Ok(Store { tree })
-}
-
 impl Store {
     pub fn new<P: AsRef<Path>>(file: &P) -> Result<Self, sled::Error> {
         let tree = sled::open(file)?;
@@ -21,21 +15,7 @@ impl Store {
         Ok(Store { tree })
     }
 
-    pub fn get(&self, key: &[u8]) -> Result<Option<NodeValue>> {
-        let value = self.tree.get(key)?;
-        Ok(value
-            .map(|v| v.to_vec())
-            .map(|v| Bytes::copy_from_slice(&v))
-            .map(|b| NodeValue::decode(&b))
-            .transpose()?)
-    }
-
-    pub fn set(&self, key: &[u8], value: &NodeValue) -> Result<()> {
-        let value = value.encode();
-        self.tree.insert(key, value.as_ref())?;
-        Ok(())
-    }
-
+    #[allow(clippy::redundant_closure)]
     pub fn get_super_node(&self, current: &Key) -> Result<Option<(Key, NodeValue)>, StoreError> {
         let current_key_raw = current.super_id_prefix();
         let mut iter = self.tree.range(current_key_raw..);
@@ -1,19 +1,26 @@
+extern crate self as dm_cache;
+
 mod db;
 mod json;
 mod kv;
+#[cfg(test)]
+mod test_util;
 
 use anyhow::Result;
-use bytes::Bytes;
-use db::Metadata;
-use json::ItemValue;
-use kv::{Key, KeyIndex, NodeValue, VariableSizedId};
-use parking_lot::RwLock;
-use simd_json::StaticNode;
+use db::Editor;
+use kv::Key;
+use parking_lot::Mutex;
+use simd_json::OwnedValue;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::OnceLock;
 use thiserror::Error;
 
 // 重新导出 JSONPath 解析相关的类型和函数
-pub use db::{parse, JsonPathSegment, JsonPathParseError};
+pub use db::{json_path_key, parse, parse_pointer, JsonPathParseError, JsonPathSegment};
+pub use db::{CasConflict, Increment, NodeCursor, NodeKind, Transaction};
+pub use db::{DocumentWriter, EventReader, JsonEvent, WriteOptions};
+pub use db::{MigrationProgress, FORMAT_VERSION};
+pub use dm_cache_derive::CachedDocument;
 
 #[derive(Error, Debug, Clone)]
 pub enum DBError {
@@ -31,26 +38,95 @@ pub enum DBError {
     DatabaseJsonError,
     #[error("Database transaction error {0}")]
     DatabaseUnabortableTransaction(#[from] sled::transaction::UnabortableTransactionError),
-    #[error("Database trac")]
+    #[error("Database transaction failed: {0}")]
     DatabaseTransationError(#[from] sled::transaction::TransactionError),
     #[error("Duplicate root key")]
     DuplicateRootKey,
     #[error("No super node")]
     NoSuperNode,
-    #[error("Invalid type of super node")]
-    InvalidSuperNodeType,
+    #[error("Invalid type of super node: {0}")]
+    InvalidSuperNodeType(String),
+    #[error("Root not found")]
+    RootNotFound,
+    #[error("Path not found: {0}")]
+    PathNotFound(String),
+    #[error("Path parse error: {0}")]
+    PathParseError(#[from] JsonPathParseError),
+    #[error("Invalid patch: {0}")]
+    InvalidPatch(String),
+    #[error("Patch test failed: {0}")]
+    PatchTestFailed(String),
+    #[error("Invalid node type at {0}")]
+    InvalidNodeType(String),
+    #[error("Index out of range: {0}")]
+    IndexOutOfRange(usize),
+    #[error("Numeric overflow at {0}")]
+    NumericOverflow(String),
+    #[error("Invalid path: {0}")]
+    InvalidPath(String),
+    #[error("Duplicate field: {0}")]
+    DuplicateField(String),
+    #[error("Serde error: {0}")]
+    SerdeError(String),
+    #[error("IO error: {0}")]
+    IoError(String),
+    #[error("Invalid writer state: {0}")]
+    InvalidWriterState(String),
+    #[error("Unsupported format version {found}, this build supports up to {supported}")]
+    UnsupportedVersion { found: u64, supported: u64 },
 }
 
+/// # 成员顺序
+///
+/// 同一个父节点下的子节点按 id 排序，id 按写入的先后分配，所以 object 成员在 `write_json`、
+/// `EventReader`、`NodeCursor` 中按写入顺序读出：
+/// * 写入的 json 文本中的成员顺序原样保留，成员名重复时保留第一次出现的位置和最后一次出现的值；
+/// * 之后新增的成员（`insert_at`、`merge_patch`、`apply_patch`、`Transaction::set_path` 等）
+///   排在已有成员之后，一次写入多个新成员时按它们在 json 文本中的顺序；
+/// * 替换已有成员（包括 `move_path`、`copy_path` 的目标已存在时）和 `rename_key`
+///   不改变成员的位置。
+///
+/// 数组元素的 key 同样按 id 排序，插入或删除元素之后和下标顺序不一致，所以另外维护一份按下标
+/// 排序的索引，读取数组时按索引的顺序，`NodeCursor::slice` 只扫描索引中对应的一段。
+///
+/// `insert_json` 由调用方给出子节点 key 时，位置由 key 中的 id 决定。返回 `OwnedValue` 的接口
+/// 受 simd_json 的限制，超过 32 个成员的 object 不保证顺序，需要和写入的文本逐个成员对应时
+/// 使用 `write_json`；`WriteOptions::sort_keys` 会按成员名重新排序。
+///
+/// # 小子树内联
+///
+/// 每个节点单独占一个 key，key 中带着全部祖先的 id，小 object 和短数组的 key 往往比值还大。
+/// `set_inline_threshold` 设置阈值之后，`insert_json`、`insert_at`、`array_push`、`merge_patch`
+/// 等通过 json 文本写入时，编码后不超过阈值的 object 或数组整体存为一个 packed 值，
+/// 子孙节点不再单独占用 key。读取时透明地展开；写操作落到 packed 子树内部时，
+/// 沿路径经过的 packed 节点先拆回普通节点（每次只拆一层，子节点中的容器仍然是 packed），
+/// 再照常修改。`insert_value`、`DocumentWriter` 和 `ingest_json` 逐个节点写入，不会打包。
 pub struct Database {
     store: kv::Store,
+    /// 打开时读取的 metadata，升级完成后只用到其中的版本号，计数器以 sled 中的为准
     metadata: db::Metadata,
+    ids: db::IdAllocator,
+    locks: db::RootLocks,
+    /// packed 值的最大字节数，0 表示不打包
+    inline_threshold: AtomicUsize,
 }
 
 // 全局变量
 static INIT_PATH: OnceLock<String> = OnceLock::new();
-static DATABASE: OnceLock<Result<RwLock<Database>, DBError>> = OnceLock::new();
+static MIGRATION_PROGRESS: Mutex<Option<fn(&MigrationProgress)>> = Mutex::new(None);
+static DATABASE: OnceLock<Result<Database, DBError>> = OnceLock::new();
+#[allow(clippy::redundant_static_lifetimes)]
 const METADAT_KEY: &'static [u8] = b"~~METADATA~~";
 
+/// 编码 id 为 `id` 的 root key，即各个接口中的 `root` 参数
+pub fn root_key(id: u64) -> Vec<u8> {
+    Key {
+        ids: vec![kv::VariableSizedId::new(id)],
+        field_key: kv::KeyIndex::Root,
+    }
+    .encode()
+}
+
 // 设置数据库路径
 pub fn set_database_path(path: &str) -> Result<(), DBError> {
     INIT_PATH
@@ -58,11 +134,200 @@ pub fn set_database_path(path: &str) -> Result<(), DBError> {
         .map_err(|_| DBError::PathAlreadySet)
 }
 
-// 获取数据库实例
-pub fn get_database() -> Result<&'static RwLock<Database>, DBError> {
+/// 设置第一次 `get_database` 打开旧版本数据库时的升级进度回调
+pub fn set_migration_progress(progress: fn(&MigrationProgress)) {
+    *MIGRATION_PROGRESS.lock() = Some(progress);
+}
+
+/// 设置小子树内联的阈值，见 [`Database::set_inline_threshold`]
+pub fn set_inline_threshold(bytes: usize) -> Result<(), DBError> {
+    get_database()?.set_inline_threshold(bytes);
+    Ok(())
+}
+
+// 获取数据库实例，数据库版本比当前程序支持的更新时返回 `DBError::UnsupportedVersion`
+//
+// `Database` 内部按 root 加锁，所有操作都只需要共享引用，不同文档的读写可以并行。
+pub fn get_database() -> Result<&'static Database, DBError> {
     let db_result = DATABASE.get_or_init(|| {
         let path = INIT_PATH.get().ok_or(DBError::PathNotSet)?;
-        let store = kv::Store::new(path).map_err(|e| DBError::DatabaseInitError(e))?;
+        let progress = *MIGRATION_PROGRESS.lock();
+        Database::open_with_progress(path, |p| {
+            if let Some(progress) = progress {
+                progress(p)
+            }
+        })
+    });
+
+    match db_result {
+        Ok(db) => Ok(db),
+        Err(err) => Err(err.clone()),
+    }
+}
+
+pub fn insert_json(key: &[u8], value: &mut [u8]) -> Result<(), DBError> {
+    get_database()?.insert_json(key, value)
+}
+
+/// 按路径插入子树，见 [`Database::insert_at`]
+pub fn insert_at(root: &[u8], path: &str, value: &mut [u8]) -> Result<(), DBError> {
+    get_database()?.insert_at(root, path, value)
+}
+
+/// 把实现了 `Serialize` 的值插入为一个新文档，见 [`Database::insert_value`]
+pub fn insert_value<T: serde::Serialize + ?Sized>(root: &[u8], value: &T) -> Result<(), DBError> {
+    get_database()?.insert_value(root, value)
+}
+
+/// 把 `path` 指向的子树读取为 `T`，见 [`Database::get_value`]
+pub fn get_value<T: serde::de::DeserializeOwned>(root: &[u8], path: &str) -> Result<T, DBError> {
+    get_database()?.get_value(root, path)
+}
+
+/// 把 `path` 指向的子树以 json 文本写入 `out`，见 [`Database::write_json`]
+pub fn write_json<W: std::io::Write>(
+    root: &[u8],
+    path: &str,
+    out: &mut W,
+    opts: &WriteOptions,
+) -> Result<(), DBError> {
+    get_database()?.write_json(root, path, out, opts)
+}
+
+/// 将 RFC 6902 JSON Patch 应用到 `root` 对应的文档上，见 [`Database::apply_patch`]
+pub fn apply_patch(root: &[u8], patch: &mut [u8]) -> Result<(), DBError> {
+    get_database()?.apply_patch(root, patch)
+}
+
+/// 将 RFC 7396 JSON Merge Patch 合并到 `path` 指向的节点上，见 [`Database::merge_patch`]
+pub fn merge_patch(root: &[u8], path: &str, patch: &mut [u8]) -> Result<(), DBError> {
+    get_database()?.merge_patch(root, path, patch)
+}
+
+/// 在数组末尾追加一个元素，见 [`Database::array_push`]
+pub fn array_push(root: &[u8], path: &str, value: &mut [u8]) -> Result<(), DBError> {
+    get_database()?.array_push(root, path, value)
+}
+
+/// 在数组的 `at` 位置插入一个元素，见 [`Database::array_insert`]
+pub fn array_insert(root: &[u8], path: &str, at: usize, value: &mut [u8]) -> Result<(), DBError> {
+    get_database()?.array_insert(root, path, at, value)
+}
+
+/// 删除并返回数组 `at` 位置的元素，见 [`Database::array_remove`]
+pub fn array_remove(root: &[u8], path: &str, at: usize) -> Result<OwnedValue, DBError> {
+    get_database()?.array_remove(root, path, at)
+}
+
+/// 删除并返回数组的最后一个元素，见 [`Database::array_pop`]
+pub fn array_pop(root: &[u8], path: &str) -> Result<Option<OwnedValue>, DBError> {
+    get_database()?.array_pop(root, path)
+}
+
+/// 返回数组的元素个数，见 [`Database::array_len`]
+pub fn array_len(root: &[u8], path: &str) -> Result<usize, DBError> {
+    get_database()?.array_len(root, path)
+}
+
+/// 子树等于 `expected` 时才替换为 `new`，见 [`Database::cas_path`]
+pub fn cas_path(
+    root: &[u8],
+    path: &str,
+    expected: &mut [u8],
+    new: &mut [u8],
+) -> Result<Result<(), CasConflict>, DBError> {
+    get_database()?.cas_path(root, path, expected, new)
+}
+
+/// 对数字节点做原子加法，见 [`Database::incr_path`]
+pub fn incr_path(
+    root: &[u8],
+    path: &str,
+    delta: impl Into<Increment>,
+) -> Result<OwnedValue, DBError> {
+    get_database()?.incr_path(root, path, delta)
+}
+
+/// 在字符串节点末尾原子地追加内容，见 [`Database::append_str`]
+pub fn append_str(root: &[u8], path: &str, s: &str) -> Result<usize, DBError> {
+    get_database()?.append_str(root, path, s)
+}
+
+/// 把 object 成员改名，见 [`Database::rename_key`]
+pub fn rename_key(root: &[u8], path: &str, new_name: &str) -> Result<(), DBError> {
+    get_database()?.rename_key(root, path, new_name)
+}
+
+/// 在文档内或文档间移动子树，见 [`Database::move_path`]
+pub fn move_path(
+    src_root: &[u8],
+    src_path: &str,
+    dst_root: &[u8],
+    dst_path: &str,
+) -> Result<(), DBError> {
+    get_database()?.move_path(src_root, src_path, dst_root, dst_path)
+}
+
+/// 复制子树，见 [`Database::copy_path`]
+pub fn copy_path(
+    src_root: &[u8],
+    src_path: &str,
+    dst_root: &[u8],
+    dst_path: &str,
+) -> Result<(), DBError> {
+    get_database()?.copy_path(src_root, src_path, dst_root, dst_path)
+}
+
+/// 把整个文档复制为新文档，见 [`Database::clone_root`]
+pub fn clone_root(src: &[u8], dst: &[u8]) -> Result<(), DBError> {
+    get_database()?.clone_root(src, dst)
+}
+
+/// 从 `reader` 流式导入 json 文档，见 [`Database::ingest_json`]
+pub fn ingest_json<R: std::io::Read>(
+    root: &[u8],
+    reader: R,
+    batch_size: usize,
+) -> Result<(), DBError> {
+    get_database()?.ingest_json(root, reader, batch_size)
+}
+
+/// 未完成的导入已经提交到的输入位置，见 [`Database::ingest_checkpoint`]
+pub fn ingest_checkpoint(root: &[u8]) -> Result<Option<u64>, DBError> {
+    get_database()?.ingest_checkpoint(root)
+}
+
+/// 从检查点继续导入，见 [`Database::resume_ingest`]
+pub fn resume_ingest<R: std::io::Read>(
+    root: &[u8],
+    reader: R,
+    batch_size: usize,
+) -> Result<(), DBError> {
+    get_database()?.resume_ingest(root, reader, batch_size)
+}
+
+/// 在一个跨文档的事务中执行 `f`，见 [`Database::transaction`]
+pub fn transaction<F, T>(f: F) -> Result<T, DBError>
+where
+    F: Fn(&mut Transaction) -> Result<T, DBError>,
+{
+    get_database()?.transaction(f)
+}
+
+impl Database {
+    // 打开数据库，不存在 metadata 时写入初始 metadata
+    pub fn open(path: &str) -> Result<Self, DBError> {
+        Self::open_with_progress(path, |_| {})
+    }
+
+    /// 打开数据库，旧版本的数据库会先原地升级到当前格式，升级进度通过 `progress` 报告
+    ///
+    /// 数据库版本比当前程序支持的更新时返回 `DBError::UnsupportedVersion`。
+    pub fn open_with_progress(
+        path: &str,
+        mut progress: impl FnMut(&MigrationProgress),
+    ) -> Result<Self, DBError> {
+        let store = kv::Store::new(&path).map_err(DBError::DatabaseInitError)?;
         let (metadata, loaded) = match store.get_raw(METADAT_KEY) {
             Ok(Some(v)) => (db::Metadata::decode(&v)?, true),
             Ok(None) => (db::Metadata::new(), false),
@@ -71,115 +336,122 @@ pub fn get_database() -> Result<&'static RwLock<Database>, DBError> {
         if !loaded {
             store.set_raw(METADAT_KEY, &metadata.encode())?;
         }
-        Ok(RwLock::new(Database { store, metadata }))
-    });
+        let ids = db::IdAllocator::new(metadata.last_id);
+        let mut db = Database {
+            store,
+            metadata,
+            ids,
+            locks: db::RootLocks::new(),
+            inline_threshold: AtomicUsize::new(0),
+        };
+        db.migrate(&mut progress)?;
+        db.ids = db::IdAllocator::new(db.metadata.last_id);
+        Ok(db)
+    }
 
-    match db_result {
-        Ok(db) => Ok(db),
-        Err(err) => match err {
-            DBError::DatabaseInitError(_) => Err(err.clone()),
-            _ => Err(err.clone()),
-        },
+    /// 编码后不超过 `bytes` 字节的 object 和数组整体存为一个值，0（默认）表示不打包
+    ///
+    /// 只影响之后的写入，已有的数据不会重新打包或拆开。
+    pub fn set_inline_threshold(&self, bytes: usize) {
+        self.inline_threshold.store(bytes, Ordering::Relaxed);
     }
-}
 
-fn make_sub_key(node_key: &Key, metadata: &mut Metadata, kind: KeyIndex) -> Key {
-    metadata.last_id += 1;
-    node_key.sub_key(VariableSizedId::new(metadata.last_id), kind)
-}
+    pub fn inline_threshold(&self) -> usize {
+        self.inline_threshold.load(Ordering::Relaxed)
+    }
 
-pub fn insert_json(key: &[u8], value: &mut [u8]) -> Result<(), DBError> {
-    let k = Key::decode(key)?;
-    let mut db = get_database()?.write();
-    let mut metadata = db.metadata.clone();
-    if k.field_key.is_root() {
-        if metadata.roots.contains(key) {
-            return Err(DBError::DuplicateRootKey)?;
-        }
-        metadata.roots.insert(key.to_vec());
-    } else {
-        // 如果不是root，查找它的父节点，如果不存在报错
-        if k.ids.len() < 2 {
-            return Err(DBError::NoSuperNode)?;
-        }
-        let (_, super_value) = if let Some(kv) = db.store.get_super_node(&k)? {
-            kv
+    /// `root` 是否已注册，`root` 为编码后的 root key
+    pub fn has_root(&self, root: &[u8]) -> Result<bool, DBError> {
+        Ok(self.store.get_raw(&db::root_entry_key(root))?.is_some())
+    }
+
+    /// 已注册的 root 个数
+    pub fn root_count(&self) -> Result<u64, DBError> {
+        Ok(self.stored_metadata()?.root_count)
+    }
+
+    /// sled 中当前的 metadata
+    pub(crate) fn stored_metadata(&self) -> Result<db::Metadata, DBError> {
+        let raw = self
+            .store
+            .get_raw(METADAT_KEY)?
+            .ok_or(kv::EncodeError::InvalidLength)?;
+        Ok(db::Metadata::decode(&raw)?)
+    }
+
+    /// 插入一个新文档或一个子节点，只需要共享引用，不同 root 的插入可以在多个线程中同时进行
+    pub fn insert_json(&self, key: &[u8], value: &mut [u8]) -> Result<(), DBError> {
+        let k = Key::decode(key)?;
+        let _lock = self.locks.write(key);
+        let mut editor = Editor::new(self);
+        if k.field_key.is_root() {
+            if editor.has_root(key)? {
+                return Err(DBError::DuplicateRootKey);
+            }
+            editor.register_root(key);
         } else {
-            return Err(DBError::NoSuperNode)?;
-        };
-        // 如果父节点是object，那么子节点只能是field
-        if k.field_key.is_field() && !super_value.is_object() {
-            return Err(DBError::InvalidSuperNodeType)?;
-        }
-        // 如果父节点是array，那么子节点只能是id
-        if k.field_key.is_id() && !super_value.is_array() {
-            return Err(DBError::InvalidSuperNodeType)?;
+            // 如果不是root，查找它的父节点，如果不存在报错
+            if k.ids.len() < 2 {
+                return Err(DBError::NoSuperNode);
+            }
+            let (super_key, super_value) = if let Some(kv) = self.store.get_super_node(&k)? {
+                kv
+            } else {
+                return Err(DBError::NoSuperNode);
+            };
+            // 如果父节点是object，那么子节点只能是field
+            if k.field_key.is_field() && !super_value.is_object() {
+                return Err(DBError::InvalidSuperNodeType(format!(
+                    "{:?}",
+                    super_key.ids
+                )));
+            }
+            // 如果父节点是array，那么子节点只能是id
+            if k.field_key.is_id() && !super_value.is_array() {
+                return Err(DBError::InvalidSuperNodeType(format!(
+                    "{:?}",
+                    super_key.ids
+                )));
+            }
+            editor.expand(&super_key)?;
         }
+        let root_value = simd_json::to_tape(value).map_err(|_| DBError::DatabaseJsonError)?;
+        editor.write_value(k, &root_value.0);
+        editor.commit()?;
+        Ok(())
     }
-    let root_value = simd_json::to_borrowed_value(value).map_err(|_| DBError::DatabaseJsonError)?;
-    let json_iter = json::JsonDfsIter::new(&root_value, k, |item, node_key| {
-        let sub_key = match item {
-            json::IterItem::KV(k, _) => make_sub_key(
-                node_key,
-                &mut metadata,
-                kv::KeyIndex::Field(Bytes::copy_from_slice(k.as_bytes())),
-            ),
-            json::IterItem::IV(idx, _) => make_sub_key(
-                node_key,
-                &mut metadata,
-                kv::KeyIndex::Id(VariableSizedId::new(*idx as u64)),
-            ),
-            json::IterItem::Array
-            | json::IterItem::Object
-            | json::IterItem::String(_)
-            | json::IterItem::Static(_) => {
-                if let Some(last_id) = node_key.ids.last() {
-                    if let Ok(last_id) = last_id.to_u64() {
-                        if metadata.last_id < last_id {
-                            metadata.last_id = last_id;
-                        }
-                    }
-                }
-                node_key.clone()
-            }
-        };
-        sub_key
-    });
-    let mut betch = sled::Batch::default();
-    for (item, key) in json_iter {
-        let encoded_key = key.encode();
-        let key_raw = encoded_key.as_slice();
-        let value = match item {
-            json::IterItem::IV(_, v) | json::IterItem::KV(_, v) => v,
-            json::IterItem::Array => json::ItemValue::Array,
-            json::IterItem::Object => json::ItemValue::Object,
-            json::IterItem::Static(s) => json::ItemValue::Static(s),
-            json::IterItem::String(s) => json::ItemValue::String(s),
-        };
-        let node_value = match value {
-            ItemValue::Array => NodeValue::Array,
-            ItemValue::Object => NodeValue::Object,
-            ItemValue::String(s) => NodeValue::String(Bytes::copy_from_slice(s.as_bytes())),
-            ItemValue::Static(StaticNode::Bool(b)) => NodeValue::Bool(*b),
-            ItemValue::Static(StaticNode::F64(f)) => NodeValue::Number(*f),
-            ItemValue::Static(StaticNode::I64(i)) => NodeValue::NumberI(*i),
-            ItemValue::Static(StaticNode::U64(u)) => NodeValue::NumberU(*u),
-            ItemValue::Static(StaticNode::Null) => NodeValue::Null,
-        };
-        let node_value_raw: &[u8] = &node_value.encode();
-        betch.insert(key_raw, node_value_raw);
+
+    /// 按路径插入子树，由数据库根据父节点分配 key，不需要调用方手工构造子节点 key
+    ///
+    /// 路径最后一段是对象键时父节点必须是 object，已存在的成员会被替换；
+    /// 是数组下标时父节点必须是 array，在该下标处插入，下标等于长度时追加。
+    /// 父节点类型不匹配返回 `DBError::InvalidSuperNodeType`，父节点不存在返回
+    /// `DBError::PathNotFound`，下标超过长度返回 `DBError::IndexOutOfRange`。
+    /// `path` 为 "$" 时插入一个新文档，等同于用 root key 调用 `insert_json`。
+    ///
+    /// # 参数
+    /// * `root` - 编码后的 root key
+    /// * `path` - 新节点的 JSONPath，如 "$.a.b" 或 "$.list[0]"
+    /// * `value` - 新节点的 json
+    pub fn insert_at(&self, root: &[u8], path: &str, value: &mut [u8]) -> Result<(), DBError> {
+        let segments = parse(path)?;
+        let _lock = self.locks.write(root);
+        if segments.is_empty() && self.has_root(root)? {
+            return Err(DBError::DuplicateRootKey);
+        }
+        let value = simd_json::to_tape(value).map_err(|_| DBError::DatabaseJsonError)?;
+        let mut editor = Editor::new(self);
+        let key = editor.prepare_slot(root, &segments)?;
+        editor.write_value(key, &value.0);
+        editor.commit()?;
+        Ok(())
     }
-    // insert metadata
-    let metadata_raw = metadata.encode();
-    betch.insert(METADAT_KEY, metadata_raw);
-    db.store.tree.apply_batch(betch)?;
-    db.metadata = metadata;
-    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use kv::VariableSizedId;
 
     #[test]
     fn test_insert_json() {
@@ -201,7 +473,7 @@ mod tests {
         let root_key_raw = root_key.encode();
         insert_json(&root_key_raw, &mut value).unwrap();
 
-        let db = get_database().unwrap().read();
+        let db = get_database().unwrap();
         db.store.tree.flush().unwrap();
         db.store.tree.iter().for_each(|r| {
             let (k, v) = r.unwrap();
@@ -220,7 +492,6 @@ mod tests {
         
         let _ = set_database_path(path); // 忽略可能的错误，因为可能已经设置过
         let db = get_database().unwrap();
-        let db = db.read();
         db.store.tree.iter().for_each(|r| {
             let (k, v) = r.unwrap();
             println!("{:?} {:?}", k, v);
@@ -252,11 +523,11 @@ mod tests {
         let result1 = insert_json(&root_key_raw, &mut value1);
         assert!(result1.is_ok(), "First insertion should succeed");
         
-        // 检查插入后metadata.roots长度应该是1
+        // 检查插入后root_count应该是1
         {
-            let db = get_database().unwrap().read();
-            assert_eq!(db.metadata.roots.len(), 1, "After first insertion, metadata.roots should contain exactly 1 root key");
-            assert!(db.metadata.roots.contains(&root_key_raw), "metadata.roots should contain the inserted root key");
+            let db = get_database().unwrap();
+            assert_eq!(db.root_count().unwrap(), 1, "After first insertion, root_count should be exactly 1");
+            assert!(db.has_root(&root_key_raw).unwrap(), "the inserted root key should be registered");
         }
         
         // 第二次插入相同的root key应该失败
@@ -272,4 +543,179 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_long_root_key() {
+        use test_util::{json, read_json, temp_database};
+
+        let db = temp_database("long_root_key");
+        // 超过 255 字节的 root key
+        let root = Key {
+            ids: (1000..1200).map(VariableSizedId::new).collect(),
+            field_key: kv::KeyIndex::Root,
+        }
+        .encode();
+        assert!(root.len() > 255);
+        db.insert_json(&root, &mut br#"{"a": [1]}"#.to_vec())
+            .unwrap();
+        assert!(db.has_root(&root).unwrap());
+        assert_eq!(db.root_count().unwrap(), 1);
+        assert_eq!(read_json(&db, &root), json(r#"{"a": [1]}"#));
+        assert_eq!(db.stored_metadata().unwrap().encode().len(), 32);
+    }
+
+    #[test]
+    fn test_parallel_insert_json() {
+        use test_util::{json, read_json, temp_database};
+
+        let db = temp_database("parallel_insert_json");
+        let duplicates = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..8u64)
+                .map(|t| {
+                    let db = &db;
+                    s.spawn(move || {
+                        for i in 0..50 {
+                            let mut doc =
+                                format!(r#"{{"t": {}, "items": [{}, {{"i": {}}}]}}"#, t, i, i)
+                                    .into_bytes();
+                            db.insert_json(&root_key(t * 100 + i), &mut doc).unwrap();
+                        }
+                        // 所有线程同时注册同一个 root，只有一个能成功
+                        db.insert_json(&root_key(999), &mut b"[]".to_vec())
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .filter(|r| matches!(r, Err(DBError::DuplicateRootKey)))
+                .count()
+        });
+        assert_eq!(duplicates, 7);
+        assert_eq!(db.root_count().unwrap(), 401);
+        for t in 0..8 {
+            for i in 0..50 {
+                assert_eq!(
+                    read_json(&db, &root_key(t * 100 + i)),
+                    json(&format!(
+                        r#"{{"t": {}, "items": [{}, {{"i": {}}}]}}"#,
+                        t, i, i
+                    ))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_insert_at() {
+        use test_util::{json, read_json, temp_document};
+
+        let (db, root) = temp_document("insert_at", r#"{"a": {}, "list": [1, 2]}"#);
+        db.insert_at(&root, "$.a.b", &mut br#"{"c": [true]}"#.to_vec())
+            .unwrap();
+        db.insert_at(&root, "$.list[0]", &mut b"0".to_vec())
+            .unwrap();
+        db.insert_at(&root, "$.list[3]", &mut b"3".to_vec())
+            .unwrap();
+        db.insert_at(&root, "$.a.b", &mut b"null".to_vec()).unwrap();
+        assert_eq!(
+            read_json(&db, &root),
+            json(r#"{"a": {"b": null}, "list": [0, 1, 2, 3]}"#)
+        );
+
+        let err = db.insert_at(&root, "$.list.x", &mut b"1".to_vec());
+        assert!(matches!(err, Err(DBError::InvalidSuperNodeType(p)) if p == "$.list"));
+        let err = db.insert_at(&root, "$.a[0]", &mut b"1".to_vec());
+        assert!(matches!(err, Err(DBError::InvalidSuperNodeType(p)) if p == "$.a"));
+        let err = db.insert_at(&root, "$.x.y.z", &mut b"1".to_vec());
+        assert!(matches!(err, Err(DBError::PathNotFound(p)) if p == "$.x"));
+        let err = db.insert_at(&root, "$.list[9]", &mut b"1".to_vec());
+        assert!(matches!(err, Err(DBError::IndexOutOfRange(9))));
+        let err = db.insert_at(&root, "$", &mut b"1".to_vec());
+        assert!(matches!(err, Err(DBError::DuplicateRootKey)));
+    }
+
+    #[test]
+    fn test_inline_small_subtrees() {
+        use test_util::{json, read_json, temp_database};
+
+        let doc = r#"{"list": [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16],
+            "small": {"z": 1, "a": [true, null], "m": {"s": "x"}}, "n": 1.5}"#;
+        let plain = temp_database("inline_plain");
+        plain
+            .insert_json(&root_key(0), &mut doc.as_bytes().to_vec())
+            .unwrap();
+        let db = temp_database("inline_small_subtrees");
+        db.set_inline_threshold(40);
+        let root = root_key(0);
+        db.insert_json(&root, &mut doc.as_bytes().to_vec()).unwrap();
+        // "small" 编码后 36 字节，连同子孙只占一个 key，省掉 6 个节点、其中 4 个成员的字段名索引
+        // 和 2 个元素的下标索引；超过阈值的 "list" 和根节点照常拆开
+        assert_eq!(plain.store.tree.len() - db.store.tree.len(), 6 + 4 + 2);
+        assert_eq!(read_json(&db, &root), json(doc));
+        let mut out = Vec::new();
+        db.write_json(&root, "$.small", &mut out, &WriteOptions::default())
+            .unwrap();
+        assert_eq!(out, br#"{"z":1,"a":[true,null],"m":{"s":"x"}}"#);
+
+        // 只读操作不改变存储的形式
+        let keys = db.store.tree.len();
+        let a: Vec<Option<bool>> = db.get_value(&root, "$.small.a").unwrap();
+        let m: std::collections::HashMap<String, String> =
+            db.get_value(&root, "$.small.m").unwrap();
+        assert_eq!(a, vec![Some(true), None]);
+        assert_eq!(m["s"], "x");
+        assert_eq!(db.array_len(&root, "$.small.a").unwrap(), 2);
+        assert_eq!(db.store.tree.len(), keys);
+
+        // 写到 packed 子树内部时沿途的 packed 节点被拆开，其它成员和顺序不变
+        assert_eq!(db.incr_path(&root, "$.small.z", 2).unwrap(), json("3"));
+        db.array_push(&root, "$.small.a", &mut b"[0]".to_vec())
+            .unwrap();
+        db.merge_patch(&root, "$.small.m", &mut br#"{"t": "y"}"#.to_vec())
+            .unwrap();
+        let expected = r#"{"list": [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16],
+            "small": {"z": 3, "a": [true, null, [0]], "m": {"s": "x", "t": "y"}}, "n": 1.5}"#;
+        assert_eq!(read_json(&db, &root), json(expected));
+        let mut out = Vec::new();
+        db.write_json(&root, "$.small", &mut out, &WriteOptions::default())
+            .unwrap();
+        assert_eq!(out, br#"{"z":3,"a":[true,null,[0]],"m":{"s":"x","t":"y"}}"#);
+
+        db.insert_json(&root_key(1), &mut br#"{"a": {"b": [1]}}"#.to_vec())
+            .unwrap();
+        let key = json_path_key(&db, &root_key(1), &parse("$.a.b").unwrap()).unwrap();
+        assert!(db.store.get_raw(&key).unwrap().is_some());
+        let mut child = Key::decode(&key).unwrap();
+        child.ids.push(kv::VariableSizedId::new(db.ids.next()));
+        child.field_key = kv::KeyIndex::Id(kv::VariableSizedId::new(1));
+        db.insert_json(&child.encode(), &mut b"2".to_vec()).unwrap();
+        let mut patch = br#"[{"op": "add", "path": "/a/c", "value": {}}]"#.to_vec();
+        db.apply_patch(&root_key(1), &mut patch).unwrap();
+        assert_eq!(
+            read_json(&db, &root_key(1)),
+            json(r#"{"a": {"b": [1, 2], "c": {}}}"#)
+        );
+
+        // 复制只读取源节点，packed 的源子树保持原样，目标只多出一个 packed 节点和它的成员索引
+        db.insert_json(&root_key(2), &mut br#"{"p": {"q": [1]}, "r": []}"#.to_vec())
+            .unwrap();
+        let keys = db.store.tree.len();
+        db.copy_path(&root_key(2), "$.p.q", &root_key(1), "$.a.c.q")
+            .unwrap();
+        assert_eq!(db.store.tree.len(), keys + 2);
+        db.move_path(&root_key(2), "$.p", &root_key(2), "$.r[0]")
+            .unwrap();
+        assert_eq!(read_json(&db, &root_key(2)), json(r#"{"r": [{"q": [1]}]}"#));
+        let keys = db.store.tree.len();
+        let q = db
+            .transaction(|tx| tx.get_path(&root_key(2), "$.r[0].q"))
+            .unwrap();
+        assert_eq!(q, json("[1]"));
+        assert_eq!(db.store.tree.len(), keys);
+        assert_eq!(
+            read_json(&db, &root_key(1)),
+            json(r#"{"a": {"b": [1, 2], "c": {"q": [1]}}}"#)
+        );
+    }
 }
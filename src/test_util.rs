@@ -0,0 +1,53 @@
+//! 测试用的辅助函数
+
+use bytes::Bytes;
+use simd_json::OwnedValue;
+
+use crate::db::Editor;
+use crate::kv::{Key, NodeValue};
+use crate::Database;
+
+/// 在临时目录中创建一个全新的数据库，不经过全局的 `get_database`
+pub(crate) fn temp_database(name: &str) -> Database {
+    let path = std::env::temp_dir().join(format!("dm_cache_{}_{}", name, std::process::id()));
+    if path.exists() {
+        std::fs::remove_dir_all(&path).expect("Failed to remove test database");
+    }
+    Database::open(path.to_str().unwrap()).unwrap()
+}
+
+pub(crate) use crate::root_key;
+
+/// 创建临时数据库并以 root 0 插入一个文档
+pub(crate) fn temp_document(name: &str, doc: &str) -> (Database, Vec<u8>) {
+    let db = temp_database(name);
+    let root = root_key(0);
+    db.insert_json(&root, &mut doc.as_bytes().to_vec()).unwrap();
+    (db, root)
+}
+
+pub(crate) fn read_json(db: &Database, root: &[u8]) -> OwnedValue {
+    let editor = Editor::new(db);
+    let key = editor.root_key(root).unwrap();
+    editor.read_value(&key).unwrap()
+}
+
+pub(crate) fn json(s: &str) -> OwnedValue {
+    simd_json::to_owned_value(&mut s.as_bytes().to_vec()).unwrap()
+}
+
+/// 文档中所有标量节点存储的值，排序后返回，用来比较不同写入方式得到的数值类型
+pub(crate) fn stored_leaves(db: &Database, root: &[u8]) -> Vec<String> {
+    let prefix = Key::decode(root).unwrap().id_prefix();
+    let mut leaves: Vec<String> = db
+        .store
+        .tree
+        .scan_prefix(prefix)
+        .values()
+        .map(|v| NodeValue::decode(&Bytes::copy_from_slice(&v.unwrap())).unwrap())
+        .filter(|v| !matches!(v, NodeValue::Array | NodeValue::Object))
+        .map(|v| format!("{:?}", v))
+        .collect();
+    leaves.sort();
+    leaves
+}